@@ -0,0 +1,356 @@
+//! # ezjs-derive
+//!
+//! Derive macros to expose Rust types as classes of an ezjs runtime.
+//!
+//! `#[derive(JsClass)]` implements `Hookable` for a struct with named fields, each field is a property
+//! of the script object, converted with `FromJs`/`IntoJs`. `#[js_methods]` on an impl block wraps the methods
+//! with argument conversion and generates `js_register`, which defines the class in a `JsRuntime<H>`.
+//! `H` is the class itself, or a hook type holding several classes which implements `JsClassHook` for each
+//! and forwards its `Hookable` methods to the generated `js_get_property`/`js_set_property`.
+//!
+//! ```ignore
+//! #[derive(Clone, JsClass)]
+//! struct Sensor {
+//!     #[js(readonly)]
+//!     id: u32,
+//!     scale: f64,
+//! }
+//!
+//! #[js_methods]
+//! impl Sensor {
+//!     #[js(constructor)]
+//!     fn new(id: u32) -> Sensor { Sensor { id: id, scale: 1.0 } }
+//!     fn read(&mut self, offset: f64) -> f64 { self.id as f64 * self.scale + offset }
+//! }
+//!
+//! Sensor::js_register(&mut rt);   // new Sensor(3).read(1)
+//! ```
+//!
+//! Attributes: `#[js(name = "...")]` renames a class, field or method, `#[js(skip)]` hides a field or method,
+//! `#[js(readonly)]` makes a field read only, `#[js(constructor)]` marks the constructor and `#[js(getter)]`
+//! exposes a method without arguments as an accessor property. Fields shadow methods of the same name.
+//! A method or constructor returning `Result` throws its error, a `String` message is thrown as a TypeError.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Fields, FnArg, ImplItem, ItemImpl, LitStr, ReturnType, Type};
+
+#[derive(Default)]
+struct JsAttr {
+    name: Option<String>,
+    skip: bool,
+    readonly: bool,
+    constructor: bool,
+    getter: bool,
+}
+
+fn parse_js_attr(attrs: &[syn::Attribute]) -> syn::Result<JsAttr> {
+    let mut js = JsAttr::default();
+    for attr in attrs {
+        if !attr.path().is_ident("js") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                let s: LitStr = meta.value()?.parse()?;
+                js.name = Some(s.value());
+            } else if meta.path.is_ident("skip") {
+                js.skip = true;
+            } else if meta.path.is_ident("readonly") {
+                js.readonly = true;
+            } else if meta.path.is_ident("constructor") {
+                js.constructor = true;
+            } else if meta.path.is_ident("getter") {
+                js.getter = true;
+            } else {
+                return Err(meta.error("unsupported js attribute"));
+            }
+            return Ok(());
+        })?;
+    }
+    return Ok(js);
+}
+
+/* Result<T, E> returned by a wrapped function is unwrapped with ? */
+fn returns_result(output: &ReturnType) -> bool {
+    if let ReturnType::Type(_, ty) = output {
+        if let Type::Path(ref p) = **ty {
+            if let Some(seg) = p.path.segments.last() {
+                return seg.ident == "Result";
+            }
+        }
+    }
+    return false;
+}
+
+#[proc_macro_derive(JsClass, attributes(js))]
+pub fn derive_js_class(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_js_class(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_js_class(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(&input.generics, "JsClass can't be derived for a generic type"));
+    }
+    let class = parse_js_attr(&input.attrs)?;
+    let class_name = class.name.unwrap_or_else(|| ident.to_string());
+
+    let fields = match input.data {
+        Data::Struct(ref s) => match s.fields {
+            Fields::Named(ref named) => &named.named,
+            _ => return Err(syn::Error::new_spanned(ident, "JsClass needs a struct with named fields")),
+        },
+        _ => return Err(syn::Error::new_spanned(ident, "JsClass needs a struct with named fields")),
+    };
+
+    let mut names = Vec::new();
+    let mut get_arms = Vec::new();
+    let mut set_arms = Vec::new();
+    for field in fields {
+        let js = parse_js_attr(&field.attrs)?;
+        if js.skip {
+            continue;
+        }
+        let field_ident = field.ident.as_ref().unwrap();
+        let name = js.name.unwrap_or_else(|| field_ident.to_string());
+
+        get_arms.push(quote! {
+            #name => {
+                return Ok(Some(::ezjs::convert::IntoJs::into_js(::std::clone::Clone::clone(&self.#field_ident), rt)));
+            },
+        });
+        if js.readonly {
+            set_arms.push(quote! {
+                #name => {
+                    return Err(rt.new_type_error(format!("{}.{} is read only", #class_name, #name)));
+                },
+            });
+        } else {
+            set_arms.push(quote! {
+                #name => {
+                    self.#field_ident = ::ezjs::convert::FromJs::from_js(rt, &value)?;
+                    return Ok(true);
+                },
+            });
+        }
+        names.push(name);
+    }
+
+    let has_property = if names.is_empty() {
+        quote! { return None; }
+    } else {
+        quote! {
+            match name {
+                #(#names)|* => {
+                    return Some(true);
+                },
+                _ => {
+                    return None;
+                }
+            }
+        }
+    };
+
+    return Ok(quote! {
+        impl #ident {
+            pub const JS_CLASS_NAME: &'static str = #class_name;
+
+            /* properties of the fields, for a runtime of this class or of a hook type holding it */
+            #[allow(unused_variables)]
+            pub fn js_get_property<H: ::ezjs::runtime::Hookable>(&mut self, rt: &mut ::ezjs::runtime::JsRuntime<H>, name: &str)
+                -> Result<Option<::ezjs::value::SharedValue>, ::ezjs::error::JsError> {
+                match name {
+                    #(#get_arms)*
+                    _ => {
+                        return Ok(None);
+                    }
+                }
+            }
+
+            #[allow(unused_variables)]
+            pub fn js_set_property<H: ::ezjs::runtime::Hookable>(&mut self, rt: &mut ::ezjs::runtime::JsRuntime<H>, name: &str, value: ::ezjs::value::SharedValue)
+                -> Result<bool, ::ezjs::error::JsError> {
+                match name {
+                    #(#set_arms)*
+                    _ => {
+                        return Ok(false);
+                    }
+                }
+            }
+
+            #[allow(unused_variables)]
+            pub fn js_has_property(&self, name: &str) -> Option<bool> {
+                #has_property
+            }
+
+            pub fn js_own_keys(&self) -> Vec<String> {
+                return vec![ #(#names.to_string()),* ];
+            }
+        }
+
+        impl ::ezjs::runtime::Hookable for #ident {
+            fn name(&self) -> String {
+                return #class_name.to_string();
+            }
+
+            fn get_property(&mut self, rt: &mut ::ezjs::runtime::JsRuntime<Self>, name: &str)
+                -> Result<Option<::ezjs::value::SharedValue>, ::ezjs::error::JsError> {
+                return self.js_get_property(rt, name);
+            }
+
+            fn set_property(&mut self, rt: &mut ::ezjs::runtime::JsRuntime<Self>, name: &str, value: ::ezjs::value::SharedValue)
+                -> Result<bool, ::ezjs::error::JsError> {
+                return self.js_set_property(rt, name, value);
+            }
+
+            fn has_property(&mut self, _rt: &mut ::ezjs::runtime::JsRuntime<Self>, name: &str)
+                -> Result<Option<bool>, ::ezjs::error::JsError> {
+                return Ok(self.js_has_property(name));
+            }
+
+            fn own_keys(&mut self, _rt: &mut ::ezjs::runtime::JsRuntime<Self>) -> Option<Vec<String>> {
+                return Some(self.js_own_keys());
+            }
+        }
+    });
+}
+
+#[proc_macro_attribute]
+pub fn js_methods(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut item = parse_macro_input!(item as ItemImpl);
+    match expand_js_methods(&mut item) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_js_methods(item: &mut ItemImpl) -> syn::Result<TokenStream2> {
+    if !item.generics.params.is_empty() || item.trait_.is_some() {
+        return Err(syn::Error::new_spanned(&item.self_ty, "js_methods needs an inherent impl of a type without generics"));
+    }
+    let self_ty = item.self_ty.clone();
+
+    let mut constructor = None;
+    let mut methods = Vec::new();
+    let mut getters = Vec::new();
+    for impl_item in item.items.iter_mut() {
+        let f = match impl_item {
+            ImplItem::Fn(f) => f,
+            _ => continue,
+        };
+        let js = parse_js_attr(&f.attrs)?;
+        f.attrs.retain(|a| !a.path().is_ident("js"));
+        if js.skip {
+            continue;
+        }
+
+        let rust_name = f.sig.ident.clone();
+        let name = js.name.unwrap_or_else(|| rust_name.to_string());
+        let has_receiver = matches!(f.sig.inputs.first(), Some(FnArg::Receiver(_)));
+
+        /* arguments are read with FromJs, a missing argument is undefined */
+        let mut args = Vec::new();
+        let mut convs = Vec::new();
+        for (i, input) in f.sig.inputs.iter().enumerate() {
+            if let FnArg::Typed(ref pat) = input {
+                let index = if has_receiver { i - 1 } else { i };
+                let ty = &pat.ty;
+                let arg = format_ident!("a{}", index);
+                convs.push(quote! { let #arg: #ty = rt.arg(argc, #index)?; });
+                args.push(arg);
+            }
+        }
+        let result = returns_result(&f.sig.output);
+
+        if js.constructor {
+            if has_receiver {
+                return Err(syn::Error::new_spanned(&f.sig, "a constructor can't have a receiver"));
+            }
+            let call = if result {
+                quote! { <#self_ty>::#rust_name(#(#args),*).map_err(|e| ::ezjs::convert::IntoJsError::into_js_error(e, rt))? }
+            } else {
+                quote! { <#self_ty>::#rust_name(#(#args),*) }
+            };
+            constructor = Some(quote! {
+                {
+                    #[allow(unused_variables)]
+                    fn constructor<H: ::ezjs::runtime::JsClassHook<#self_ty>>(rt: &mut ::ezjs::runtime::JsRuntime<H>, argc: usize) -> Result<H, ::ezjs::error::JsError> {
+                        #(#convs)*
+                        return Ok(H::from(#call));
+                    }
+                    constructor::<H>
+                }
+            });
+            continue;
+        }
+        if !has_receiver {
+            continue;
+        }
+        if js.getter && !args.is_empty() {
+            return Err(syn::Error::new_spanned(&f.sig, "a getter can't have arguments"));
+        }
+
+        let call = if result {
+            quote! { hook.#rust_name(#(#args),*).map_err(|e| ::ezjs::convert::IntoJsError::into_js_error(e, rt))? }
+        } else {
+            quote! { hook.#rust_name(#(#args),*) }
+        };
+        let wrapper = quote! {
+            {
+                #[allow(unused_variables)]
+                fn method<H: ::ezjs::runtime::JsClassHook<#self_ty>>(hook: &mut H, rt: &mut ::ezjs::runtime::JsRuntime<H>, argc: usize) -> Result<(), ::ezjs::error::JsError> {
+                    let hook = match hook.class_mut() {
+                        Some(hook) => hook,
+                        None => {
+                            return Err(rt.new_type_error(format!("{}.{} called on incompatible receiver", <#self_ty>::JS_CLASS_NAME, #name)));
+                        }
+                    };
+                    #(#convs)*
+                    let r = #call;
+                    rt.push_value(r);
+                    return Ok(());
+                }
+                method::<H> as ::ezjs::runtime::JsClassMethod<H>
+            }
+        };
+        if js.getter {
+            getters.push(quote! { getters.insert(#name.to_string(), #wrapper); });
+        } else {
+            methods.push(quote! { methods.insert(#name.to_string(), #wrapper); });
+        }
+    }
+
+    let constructor = constructor.unwrap_or_else(|| quote! {
+        {
+            fn constructor<H: ::ezjs::runtime::JsClassHook<#self_ty>>(rt: &mut ::ezjs::runtime::JsRuntime<H>, _argc: usize) -> Result<H, ::ezjs::error::JsError> {
+                return Err(rt.new_type_error(format!("{} has no constructor", <#self_ty>::JS_CLASS_NAME)));
+            }
+            constructor::<H>
+        }
+    });
+
+    return Ok(quote! {
+        #item
+
+        impl #self_ty {
+            /* defines the class as a global of the runtime, returns the constructor.
+               The hook type of the runtime is this class or a type holding it, like an enum of classes. */
+            #[allow(unused_mut)]
+            pub fn js_register<H: ::ezjs::runtime::JsClassHook<#self_ty> + 'static>(rt: &mut ::ezjs::runtime::JsRuntime<H>) -> ::ezjs::value::SharedObject {
+                let mut methods: ::std::collections::HashMap<String, ::ezjs::runtime::JsClassMethod<H>> = ::std::collections::HashMap::new();
+                let mut getters: ::std::collections::HashMap<String, ::ezjs::runtime::JsClassMethod<H>> = ::std::collections::HashMap::new();
+                #(#methods)*
+                #(#getters)*
+                return rt.define_class(<#self_ty>::JS_CLASS_NAME, #constructor, methods, getters);
+            }
+        }
+    });
+}
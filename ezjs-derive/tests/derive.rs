@@ -0,0 +1,191 @@
+// Classes generated by derive(JsClass) and js_methods.
+
+use ezjs::error::JsError;
+use ezjs::runtime::{Hookable, JsClassHook, JsRuntime};
+use ezjs::value::SharedValue;
+use ezjs_derive::{js_methods, JsClass};
+
+#[derive(Clone, JsClass)]
+struct Sensor {
+    #[js(readonly)]
+    id: u32,
+    scale: f64,
+    #[js(name = "label")]
+    tag: String,
+    #[js(skip)]
+    reads: u32,
+}
+
+#[js_methods]
+impl Sensor {
+    #[js(constructor)]
+    fn new(id: u32, tag: Option<String>) -> Sensor {
+        return Sensor { id: id, scale: 1.0, tag: tag.unwrap_or_default(), reads: 0 };
+    }
+
+    fn read(&mut self, offset: f64) -> f64 {
+        self.reads = self.reads + 1;
+        return self.id as f64 * self.scale + offset;
+    }
+
+    #[js(getter)]
+    fn reads(&self) -> u32 {
+        return self.reads;
+    }
+
+    #[js(name = "checkedScale")]
+    fn checked_scale(&mut self, scale: f64) -> Result<(), String> {
+        if scale <= 0.0 {
+            return Err("scale must be positive".to_string());
+        }
+        self.scale = scale;
+        return Ok(());
+    }
+
+    #[js(skip)]
+    #[allow(dead_code)]
+    fn hidden(&self) {
+    }
+}
+
+#[derive(Clone, JsClass)]
+struct Motor {
+    speed: f64,
+}
+
+#[js_methods]
+impl Motor {
+    #[js(constructor)]
+    fn new(speed: f64) -> Result<Motor, JsError> {
+        return Ok(Motor { speed: speed });
+    }
+
+    fn stop(&mut self) {
+        self.speed = 0.0;
+    }
+}
+
+/* a runtime with both classes holds them in one hook type */
+#[derive(Clone)]
+enum Device {
+    Sensor(Sensor),
+    Motor(Motor),
+}
+
+impl From<Sensor> for Device {
+    fn from(s: Sensor) -> Self {
+        return Device::Sensor(s);
+    }
+}
+
+impl From<Motor> for Device {
+    fn from(m: Motor) -> Self {
+        return Device::Motor(m);
+    }
+}
+
+impl JsClassHook<Sensor> for Device {
+    fn class_mut(&mut self) -> Option<&mut Sensor> {
+        match self {
+            Device::Sensor(s) => return Some(s),
+            _ => return None,
+        }
+    }
+}
+
+impl JsClassHook<Motor> for Device {
+    fn class_mut(&mut self) -> Option<&mut Motor> {
+        match self {
+            Device::Motor(m) => return Some(m),
+            _ => return None,
+        }
+    }
+}
+
+impl Hookable for Device {
+    fn name(&self) -> String {
+        match self {
+            Device::Sensor(_) => return Sensor::JS_CLASS_NAME.to_string(),
+            Device::Motor(_) => return Motor::JS_CLASS_NAME.to_string(),
+        }
+    }
+
+    fn get_property(&mut self, rt: &mut JsRuntime<Self>, name: &str) -> Result<Option<SharedValue>, JsError> {
+        match self {
+            Device::Sensor(s) => return s.js_get_property(rt, name),
+            Device::Motor(m) => return m.js_get_property(rt, name),
+        }
+    }
+
+    fn set_property(&mut self, rt: &mut JsRuntime<Self>, name: &str, value: SharedValue) -> Result<bool, JsError> {
+        match self {
+            Device::Sensor(s) => return s.js_set_property(rt, name, value),
+            Device::Motor(m) => return m.js_set_property(rt, name, value),
+        }
+    }
+}
+
+fn eval<H: Hookable>(rt: &mut JsRuntime<H>, script: &str) -> Result<SharedValue, JsError> {
+    let vmf = ezjs::build_function_from_code(script)?;
+    return ezjs::run_script(rt, vmf);
+}
+
+fn new_runtime() -> JsRuntime<Sensor> {
+    let mut rt = ezjs::new_runtime(Sensor::new(0, None));
+    Sensor::js_register(&mut rt);
+    return rt;
+}
+
+#[test]
+fn fields_are_properties() {
+    let mut rt = new_runtime();
+    eval(&mut rt, "var s = new Sensor(3, 'hall');").unwrap();
+    assert_eq!(eval(&mut rt, "s.id;").unwrap().to_number(), 3.0);
+    assert_eq!(eval(&mut rt, "s.label;").unwrap().to_string(), "hall");
+    assert!(eval(&mut rt, "s.reads;").unwrap().to_number() == 0.0);
+
+    eval(&mut rt, "s.scale = 2;").unwrap();
+    assert_eq!(eval(&mut rt, "s.read(1);").unwrap().to_number(), 7.0);
+
+    let msg = eval(&mut rt, "var r; try { s.id = 5; } catch(e) { r = e.message(); } r;").unwrap();
+    assert_eq!(msg.to_string(), "TypeError: Sensor.id is read only");
+
+    let keys = eval(&mut rt, "var k = ''; for (var p in s) { k = k + p + ','; } k;").unwrap();
+    assert_eq!(keys.to_string(), "id,scale,label,");
+}
+
+#[test]
+fn methods_convert_arguments_and_results() {
+    let mut rt = new_runtime();
+    eval(&mut rt, "var s = new Sensor(4);").unwrap();
+    assert_eq!(eval(&mut rt, "s.read(0.5); s.read(0.5);").unwrap().to_number(), 4.5);
+    assert_eq!(eval(&mut rt, "s.reads;").unwrap().to_number(), 2.0);
+    assert!(eval(&mut rt, "s instanceof Sensor;").unwrap().to_boolean());
+
+    let msg = eval(&mut rt, "var r; try { s.read('x'); } catch(e) { r = e.message(); } r;").unwrap();
+    assert_eq!(msg.to_string(), "TypeError: expected number but got string");
+
+    eval(&mut rt, "s.checkedScale(3);").unwrap();
+    assert_eq!(eval(&mut rt, "s.scale;").unwrap().to_number(), 3.0);
+    let msg = eval(&mut rt, "var r; try { s.checkedScale(-1); } catch(e) { r = e.message(); } r;").unwrap();
+    assert_eq!(msg.to_string(), "TypeError: scale must be positive");
+    assert_eq!(eval(&mut rt, "s.scale;").unwrap().to_number(), 3.0);
+    assert!(eval(&mut rt, "s.hidden;").unwrap().is_undefined());
+}
+
+#[test]
+fn classes_share_a_hook_type() {
+    let mut rt = ezjs::new_runtime(Device::Motor(Motor { speed: 0.0 }));
+    Sensor::js_register(&mut rt);
+    Motor::js_register(&mut rt);
+
+    eval(&mut rt, "var s = new Sensor(2); var m = new Motor(5);").unwrap();
+    assert_eq!(eval(&mut rt, "s.read(1);").unwrap().to_number(), 3.0);
+    assert_eq!(eval(&mut rt, "m.speed;").unwrap().to_number(), 5.0);
+    eval(&mut rt, "m.stop();").unwrap();
+    assert_eq!(eval(&mut rt, "m.speed;").unwrap().to_number(), 0.0);
+    assert!(eval(&mut rt, "m instanceof Motor && !(m instanceof Sensor);").unwrap().to_boolean());
+
+    let msg = eval(&mut rt, "var r; try { Sensor.prototype.read.call(m, 1); } catch(e) { r = e.message(); } r;").unwrap();
+    assert!(msg.to_string().starts_with("TypeError: "));
+}
@@ -1,4 +1,5 @@
 use crate::token::*;
+use crate::error::*;
 
 /* ast stuff */
 #[allow(non_camel_case_types)]
@@ -114,9 +115,15 @@ pub enum AstType {
 	STM_DEFAULT,
 
 	STM_DEBUG,
+
+	/* module items, only at top level */
+	STM_IMPORT,
+	STM_EXPORT,
+	STM_EXPORT_DEFAULT,
+	AST_MODULE_SPEC,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct AstNode {
     pub ast_type:   AstType,
     pub src_line:   u32,
@@ -183,7 +190,7 @@ impl AstNode {
         }
     }
 
-    fn new_a(ntype: AstType, line: u32, a: Self) -> Self {
+    pub(crate) fn new_a(ntype: AstType, line: u32, a: Self) -> Self {
         AstNode {
             ast_type: ntype,
             src_line: line,
@@ -236,7 +243,7 @@ impl AstNode {
     }
 
     // linked list
-    fn new_list(anode: AstNode) -> Self {
+    pub(crate) fn new_list(anode: AstNode) -> Self {
         let mut new_list_item = AstNode::new(AstType::AST_LIST, anode.src_line);
         new_list_item.a = Some(Box::new(anode));
         return new_list_item;
@@ -250,7 +257,104 @@ impl AstNode {
     }
 }
 
-fn tk_accept(tkr: &mut Tokenlizer, tkt: TokenType) -> Result<bool, String> {
+/*
+ * Release children with a work list, the default recursive drop overflows the
+ * stack on long statement lists and long expression chains.
+ */
+impl Drop for AstNode {
+    fn drop(&mut self) {
+        let mut nodes: Vec<Box<AstNode>> = Vec::new();
+        nodes.extend(self.a.take());
+        nodes.extend(self.b.take());
+        nodes.extend(self.c.take());
+        nodes.extend(self.d.take());
+        while let Some(mut node) = nodes.pop() {
+            nodes.extend(node.a.take());
+            nodes.extend(node.b.take());
+            nodes.extend(node.c.take());
+            nodes.extend(node.d.take());
+        }
+    }
+}
+
+/* Lists are cloned along b with a loop, for the same reason as drop. */
+impl Clone for AstNode {
+    fn clone(&self) -> Self {
+        fn clone_without_b(node: &AstNode) -> AstNode {
+            AstNode {
+                ast_type: node.ast_type,
+                src_line: node.src_line,
+                num_value: node.num_value,
+                str_value: node.str_value.clone(),
+                a: node.a.clone(),
+                b: None,
+                c: node.c.clone(),
+                d: node.d.clone(),
+            }
+        }
+
+        let mut head = clone_without_b(self);
+        if self.ast_type != AstType::AST_LIST {
+            head.b = self.b.clone();
+            return head;
+        }
+
+        let mut tail: &mut AstNode = &mut head;
+        let mut next = self.b.as_ref();
+        while let Some(item) = next {
+            tail.b = Some(Box::new(clone_without_b(item)));
+            tail = tail.b.as_mut().unwrap();
+            next = item.b.as_ref();
+        }
+        return head;
+    }
+}
+
+fn tk_error(tk: &Token, msg: String) -> JsError {
+    return JsError::syntax(tk.src_line, tk.src_col, msg);
+}
+
+/* limits of nested parsing and of the final tree, deeper scripts are rejected before overflowing the stack */
+const AST_RECURSION_LIMIT: usize = 100;
+const AST_DEPTH_LIMIT: usize = 1000;
+
+fn ast_nested(tkr: &mut Tokenlizer, parse: fn(&mut Tokenlizer) -> Result<AstNode, JsError>) -> Result<AstNode, JsError> {
+    let depth = tkr.depth;
+    if depth >= AST_RECURSION_LIMIT {
+        let lookahead = tkr.forward()?;
+        return Err(tk_error(&lookahead, "AST error: too much recursion".to_string()));
+    }
+    tkr.depth = depth + 1;
+    let node = parse(tkr)?;
+    tkr.depth = depth;
+    return Ok(node);
+}
+
+/* left-associative chains are parsed by loops, so the depth of the tree is checked after parsing */
+fn ast_check_depth(root: &AstNode) -> Result<(), JsError> {
+    let mut nodes: Vec<(&AstNode, usize)> = vec![(root, 0)];
+    while let Some((node, depth)) = nodes.pop() {
+        if depth > AST_DEPTH_LIMIT {
+            return Err(JsError::syntax(node.src_line, 0, "AST error: expression is nested too deeply".to_string()));
+        }
+        if let Some(b) = node.b.as_ref() {
+            // items of a list are linked by b, they are not nested
+            if node.ast_type == AstType::AST_LIST {
+                nodes.push((b, depth));
+            } else {
+                nodes.push((b, depth + 1));
+            }
+        }
+        for child in [&node.a, &node.c, &node.d].iter() {
+            if let Some(child) = child.as_ref() {
+                nodes.push((child, depth + 1));
+            }
+        }
+    }
+    return Ok(());
+}
+
+fn tk_accept(tkr: &mut Tokenlizer, tkt: TokenType) -> Result<bool, JsError> {
     let ntk = tkr.forward()?;
     if ntk.tk_type != tkt {
         return Ok(false);
@@ -259,15 +363,15 @@ fn tk_accept(tkr: &mut Tokenlizer, tkt: TokenType) -> Result<bool, String> {
     return Ok(true);
 }
 
-fn tk_expect(tkr: &mut Tokenlizer, tkt: TokenType) -> Result<Token, String> {
+fn tk_expect(tkr: &mut Tokenlizer, tkt: TokenType) -> Result<Token, JsError> {
     let ntk = tkr.next()?;
     if ntk.tk_type != tkt {
-        return Err(format!("AST error: except {:?} but got {:?} @ {}", tkt, ntk.tk_type, tkr.line()));
+        return Err(tk_error(&ntk, format!("AST error: except {:?} but got {:?}", tkt, ntk.tk_type)));
     }
     return Ok(ntk);
 }
 
-fn tk_lookahead(tkr: &mut Tokenlizer, tkt: TokenType) -> Result<bool, String> {
+fn tk_lookahead(tkr: &mut Tokenlizer, tkt: TokenType) -> Result<bool, JsError> {
     let fwd = tkr.forward()?;
     if fwd.tk_type == tkt {
         return Ok(true);
@@ -275,13 +379,13 @@ fn tk_lookahead(tkr: &mut Tokenlizer, tkt: TokenType) -> Result<bool, String> {
     return Ok(false);
 }
 
-fn ast_identifier(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_identifier(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let id = tk_expect(tkr, TokenType::TK_IDENTIFIER)?;
     let node = AstNode::new_string(AstType::AST_IDENTIFIER, tkr.line(), &id.tk_value.unwrap());
     return Ok(node);
 }
 
-fn ast_identifier_opt(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_identifier_opt(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let ntk = tkr.forward()?;
     if ntk.tk_type == TokenType::TK_IDENTIFIER {
         tkr.next()?;
@@ -293,7 +397,7 @@ fn ast_identifier_opt(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
 }
 
 
-fn ast_propname(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_propname(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let lookahead = tkr.forward()?;
     if tk_accept(tkr, TokenType::TK_NUMBER)? {
         let value = lookahead.to_number();
@@ -307,7 +411,7 @@ fn ast_propname(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return ast_identifier(tkr);
 }
 
-fn ast_propassign(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_propassign(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let name = ast_propname(tkr)?;
     let lookahead = tkr.forward()?;
 
@@ -324,7 +428,8 @@ fn ast_propassign(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
         if name.str_value.as_ref().unwrap() == "set" {
             let name = ast_propname(tkr)?;
             tk_expect(tkr, TokenType::TK_PAREN_LEFT)?;
-            let arg = ast_identifier(tkr)?;
+            /* the parameter is a list like the parameters of any function */
+            let arg = AstNode::new_list(ast_identifier(tkr)?);
             tk_expect(tkr, TokenType::TK_PAREN_RIGHT)?;
             let body = ast_funbody(tkr)?;
             let exp = AstNode::new_a_b_c(AstType::EXP_PROP_SET, tkr.line(), name, arg, body);
@@ -337,7 +442,7 @@ fn ast_propassign(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(exp);
 }
 
-fn ast_objectliteral(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_objectliteral(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     if tkr.forward()?.tk_type == TokenType::TK_BRACE_RIGHT {
         let null = AstNode::new(AstType::AST_NULL, tkr.line());
         return Ok(null);
@@ -357,14 +462,14 @@ fn ast_objectliteral(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(head);
 }
 
-fn ast_arrayelement(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_arrayelement(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     if tkr.forward()?.tk_type == TokenType::TK_COMMA {
         return Ok(AstNode::new(AstType::EXP_UNDEF, tkr.line()));
     }
     return ast_assignment(tkr);
 }
 
-fn ast_arrayliteral(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_arrayliteral(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let node = ast_arrayelement(tkr)?;
     let mut head = AstNode::new_list( node );
     let mut tail: &mut AstNode = &mut head;
@@ -377,7 +482,7 @@ fn ast_arrayliteral(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(head);
 }
 
-fn ast_primary(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_primary(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let lookahead = tkr.forward()?;
     if tk_accept(tkr, TokenType::TK_IDENTIFIER)? {
         let a = AstNode::new_string(AstType::EXP_IDENTIFIER, tkr.line(), &lookahead.tk_value.unwrap());
@@ -435,10 +540,10 @@ fn ast_primary(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
         return Ok(a);
     }
 
-    panic!(format!("unexpected token in expression: {:?} @ {}", lookahead, tkr.line()));
+    return Err(tk_error(&lookahead, format!("AST error: unexpected token in expression: {:?}", lookahead.tk_type)));
 }
 
-fn ast_arguments(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_arguments(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     if tkr.forward()?.tk_type == TokenType::TK_PAREN_RIGHT {
         return Ok(AstNode::new(AstType::AST_NULL, tkr.line()));
     }
@@ -454,7 +559,7 @@ fn ast_arguments(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(head);
 }
 
-fn ast_formula_funexp(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_formula_funexp(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let a = ast_identifier_opt(tkr)?;
     tk_expect(tkr, TokenType::TK_PAREN_LEFT)?;
     let b = ast_parameters(tkr)?;
@@ -464,7 +569,7 @@ fn ast_formula_funexp(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(node);
 }
 
-fn ast_formula_memberexp(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_formula_memberexp(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let mut a = ast_formula_newexp(tkr)?;
 
     loop {
@@ -484,9 +589,9 @@ fn ast_formula_memberexp(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(a);
 }
 
-fn ast_formula_newexp(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_formula_newexp(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     if tk_accept(tkr, TokenType::TK_NEW)? {
-        let a = ast_formula_memberexp(tkr)?;
+        let a = ast_nested(tkr, ast_formula_memberexp)?;
         if tk_accept(tkr, TokenType::TK_PAREN_LEFT)? {
             let b = ast_arguments(tkr)?;
             tk_expect(tkr, TokenType::TK_PAREN_RIGHT)?;
@@ -501,7 +606,7 @@ fn ast_formula_newexp(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return ast_primary(tkr);
 }
 
-fn ast_formula_callexp(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_formula_callexp(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let mut a = ast_formula_newexp(tkr)?;
     loop {
         if tk_accept(tkr, TokenType::TK_POINT)? {
@@ -526,7 +631,7 @@ fn ast_formula_callexp(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(a);
 }
 
-fn ast_formula_postfix(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_formula_postfix(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let a = ast_formula_callexp(tkr)?;
     if tkr.new_line()? == false {
         if tk_accept(tkr, TokenType::TK_INC)? {
@@ -541,56 +646,56 @@ fn ast_formula_postfix(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(a);
 }
 
-fn ast_formula_unary(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_formula_unary(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     if tk_accept(tkr, TokenType::TK_DELETE)? {
-        let a = ast_formula_unary(tkr)?;
+        let a = ast_nested(tkr, ast_formula_unary)?;
         let stm = AstNode::new_a(AstType::EXP_DELETE, tkr.line(), a);
         return Ok(stm);
     }
     if tk_accept(tkr, TokenType::TK_VOID)? {
-        let a = ast_formula_unary(tkr)?;
+        let a = ast_nested(tkr, ast_formula_unary)?;
         let stm = AstNode::new_a(AstType::EXP_VOID, tkr.line(), a);
         return Ok(stm);
     }
     if tk_accept(tkr, TokenType::TK_TYPEOF)? {
-        let a = ast_formula_unary(tkr)?;
+        let a = ast_nested(tkr, ast_formula_unary)?;
         let stm = AstNode::new_a(AstType::EXP_TYPEOF, tkr.line(), a);
         return Ok(stm);
     }
     if tk_accept(tkr, TokenType::TK_INC)? {
-        let a = ast_formula_unary(tkr)?;
+        let a = ast_nested(tkr, ast_formula_unary)?;
         let stm = AstNode::new_a(AstType::EXP_PREINC, tkr.line(), a);
         return Ok(stm);
     }
     if tk_accept(tkr, TokenType::TK_DEC)? {
-        let a = ast_formula_unary(tkr)?;
+        let a = ast_nested(tkr, ast_formula_unary)?;
         let stm = AstNode::new_a(AstType::EXP_PREDEC, tkr.line(), a);
         return Ok(stm);
     }
     if tk_accept(tkr, TokenType::TK_BITNOT)? {
-        let a = ast_formula_unary(tkr)?;
+        let a = ast_nested(tkr, ast_formula_unary)?;
         let stm = AstNode::new_a(AstType::EXP_BITNOT, tkr.line(), a);
         return Ok(stm);
     }
     if tk_accept(tkr, TokenType::TK_NOT)? {
-        let a = ast_formula_unary(tkr)?;
+        let a = ast_nested(tkr, ast_formula_unary)?;
         let stm = AstNode::new_a(AstType::EXP_LOGNOT, tkr.line(), a);
         return Ok(stm);
     }
     if tk_accept(tkr, TokenType::TK_SUB)? {
-        let a = ast_formula_unary(tkr)?;
+        let a = ast_nested(tkr, ast_formula_unary)?;
         let stm = AstNode::new_a(AstType::EXP_NEG, tkr.line(), a);
         return Ok(stm);
     }
     if tk_accept(tkr, TokenType::TK_ADD)? {
-        let a = ast_formula_unary(tkr)?;
+        let a = ast_nested(tkr, ast_formula_unary)?;
         let stm = AstNode::new_a(AstType::EXP_POS, tkr.line(), a);
         return Ok(stm);
     }
     return ast_formula_postfix(tkr);
 }
 
-fn ast_formula_multiplicative(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_formula_multiplicative(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let mut a = ast_formula_unary(tkr)?;
 
     loop {
@@ -614,7 +719,7 @@ fn ast_formula_multiplicative(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(a);
 }
 
-fn ast_formula_additive(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_formula_additive(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let mut a = ast_formula_multiplicative(tkr)?;
 
     loop {
@@ -633,7 +738,7 @@ fn ast_formula_additive(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(a);
 }
 
-fn ast_formula_shift(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_formula_shift(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let mut a = ast_formula_additive(tkr)?;
 
     loop {
@@ -657,7 +762,7 @@ fn ast_formula_shift(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(a);
 }
 
-fn ast_formula_relational(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_formula_relational(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let mut a = ast_formula_shift(tkr)?;
 
     loop {
@@ -698,7 +803,7 @@ fn ast_formula_relational(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(a);
 }
 
-fn ast_formula_eq(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_formula_eq(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let mut a = ast_formula_relational(tkr)?;
 
     loop {
@@ -727,7 +832,7 @@ fn ast_formula_eq(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(a);
 }
 
-fn ast_formula_bitand(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_formula_bitand(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let mut a = ast_formula_eq(tkr)?;
     while tk_accept(tkr, TokenType::TK_AND)? {
         let b = ast_formula_eq(tkr)?;
@@ -736,7 +841,7 @@ fn ast_formula_bitand(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(a);
 }
 
-fn ast_formula_bitxor(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_formula_bitxor(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let mut a = ast_formula_bitand(tkr)?;
     while tk_accept(tkr, TokenType::TK_XOR)? {
         let b = ast_formula_bitand(tkr)?;
@@ -745,7 +850,7 @@ fn ast_formula_bitxor(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(a);
 }
 
-fn ast_formula_bitor(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_formula_bitor(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let mut a = ast_formula_bitxor(tkr)?;
     while tk_accept(tkr, TokenType::TK_OR)? {
         let b = ast_formula_bitxor(tkr)?;
@@ -754,7 +859,7 @@ fn ast_formula_bitor(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(a);
 }
 
-fn ast_formula_and(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_formula_and(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let mut a = ast_formula_bitor(tkr)?;
     while tk_accept(tkr, TokenType::TK_AND_AND)? {
         let b = ast_formula_bitor(tkr)?;
@@ -763,7 +868,7 @@ fn ast_formula_and(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(a);
 }
 
-fn ast_formula_or(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_formula_or(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let mut a = ast_formula_and(tkr)?;
     while tk_accept(tkr, TokenType::TK_OR_OR)? {
         let b = ast_formula_and(tkr)?;
@@ -772,7 +877,7 @@ fn ast_formula_or(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(a);
 }
 
-fn ast_formula(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_formula(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let mut a = ast_formula_or(tkr)?;
     if tk_accept(tkr, TokenType::TK_QUEST)? {
         let b = ast_assignment(tkr)?;
@@ -783,7 +888,11 @@ fn ast_formula(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(a);
 }
 
-fn ast_assignment(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_assignment(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
+    return ast_nested(tkr, ast_assignment_inner);
+}
+
+fn ast_assignment_inner(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let a = ast_formula(tkr)?;
 
     if tk_accept(tkr, TokenType::TK_ASS)? {
@@ -838,7 +947,7 @@ fn ast_assignment(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(a);
 }
 
-fn ast_expression(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_expression(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let mut a = ast_assignment(tkr)?;
     while tk_accept(tkr, TokenType::TK_COMMA)? {
         let b = ast_assignment(tkr)?;
@@ -847,7 +956,7 @@ fn ast_expression(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(a);
 }
 
-fn ast_vardec(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_vardec(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let a = ast_identifier(tkr)?;
     if tk_accept(tkr, TokenType::TK_ASS)? {
         let b = ast_assignment(tkr)?;
@@ -858,7 +967,7 @@ fn ast_vardec(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(exp);
 }
 
-fn ast_vardeclist(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_vardeclist(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let node = ast_vardec(tkr)?;
     let mut head = AstNode::new_list( node );
     let mut tail: &mut AstNode = &mut head;
@@ -869,7 +978,7 @@ fn ast_vardeclist(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(head);
 }
 
-fn ast_parameters(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_parameters(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let n = tkr.forward()?;
     if n.tk_type == TokenType::TK_PAREN_RIGHT {
         return Ok(AstNode::new(AstType::AST_NULL, tkr.line()));
@@ -887,7 +996,7 @@ fn ast_parameters(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(head);
 }
 
-fn ast_caseclause(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_caseclause(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     if tk_accept(tkr, TokenType::TK_CASE)? {
         let a = ast_expression(tkr)?;
         tk_expect(tkr, TokenType::TK_COLON)?;
@@ -902,10 +1011,11 @@ fn ast_caseclause(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
         return Ok(stm);
     }
 
-    return Err(format!("AST error: unexpected token in switch: {:?} @ {}  (expected 'case' or 'default')", tkr.forward(), tkr.line()));
+    let lookahead = tkr.forward()?;
+    return Err(tk_error(&lookahead, format!("AST error: unexpected token in switch: {:?} (expected 'case' or 'default')", lookahead.tk_type)));
 }
 
-fn ast_semicolon(tkr: &mut Tokenlizer) -> Result<(), String> {
+fn ast_semicolon(tkr: &mut Tokenlizer) -> Result<(), JsError> {
     if tkr.new_line()? {
         return Ok(());
     }
@@ -924,10 +1034,10 @@ fn ast_semicolon(tkr: &mut Tokenlizer) -> Result<(), String> {
         return Ok(());
     }
 
-    return Err(format!("unexpected token: {:?} (expected ';')", lookahead));
+    return Err(tk_error(&lookahead, format!("unexpected token: {:?} (expected ';')", lookahead.tk_type)));
 }
 
-fn ast_forexpression(tkr: &mut Tokenlizer, stop: TokenType) -> Result<AstNode, String> {
+fn ast_forexpression(tkr: &mut Tokenlizer, stop: TokenType) -> Result<AstNode, JsError> {
     if tkr.forward()?.tk_type == stop {
         tk_expect(tkr, stop)?;
         return Ok(AstNode::new(AstType::AST_NULL, tkr.line()));
@@ -937,7 +1047,7 @@ fn ast_forexpression(tkr: &mut Tokenlizer, stop: TokenType) -> Result<AstNode, S
     return Ok(a);
 }
 
-fn ast_forstatement(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_forstatement(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     tk_expect(tkr, TokenType::TK_PAREN_LEFT)?;
 
     if tk_accept(tkr, TokenType::TK_VAR)? {
@@ -958,7 +1068,8 @@ fn ast_forstatement(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
             let stm = AstNode::new_a_b_c(AstType::STM_FOR_IN_VAR, tkr.line(), a, b, c);
             return Ok(stm);
         }
-        return Err(format!("unexpected token in for-var-statement: {:?}", tkr.forward()));
+        let lookahead = tkr.forward()?;
+        return Err(tk_error(&lookahead, format!("unexpected token in for-var-statement: {:?}", lookahead.tk_type)));
     }
 
     let mut a = AstNode::new(AstType::AST_NULL, tkr.line());
@@ -986,10 +1097,11 @@ fn ast_forstatement(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
         return Ok(stm);
     }
 
-    return Err(format!("unexpected token in for-statement: {:?}", tkr.forward()));
+    let lookahead = tkr.forward()?;
+    return Err(tk_error(&lookahead, format!("unexpected token in for-statement: {:?}", lookahead.tk_type)));
 }
 
-fn ast_caselist(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_caselist(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let node = ast_caseclause(tkr)?;
 
     let mut head = AstNode::new_list( node );
@@ -1001,7 +1113,7 @@ fn ast_caselist(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(head);
 }
 
-fn ast_statement_list(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_statement_list(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let tk = tkr.forward()?;
     if tk.tk_type == TokenType::TK_BRACE_RIGHT || tk.tk_type == TokenType::TK_CASE || tk.tk_type == TokenType::TK_DEFAULT {
         return Ok(AstNode::new(AstType::AST_NULL, tkr.line()));
@@ -1021,14 +1133,18 @@ fn ast_statement_list(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(head);
 }
 
-fn ast_block(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_block(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let _leftbrace = tk_expect(tkr, TokenType::TK_BRACE_LEFT)?;
     let a = ast_statement_list(tkr)?;
     tk_expect(tkr, TokenType::TK_BRACE_RIGHT)?;
     return Ok( AstNode::new_a(AstType::STM_BLOCK, tkr.line(), a) );
 }
 
-fn ast_statement(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_statement(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
+    return ast_nested(tkr, ast_statement_inner);
+}
+
+fn ast_statement_inner(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     if tkr.forward()?.tk_type == TokenType::TK_BRACE_LEFT {
         return ast_block(tkr);
 
@@ -1042,15 +1158,30 @@ fn ast_statement(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
         return Ok( AstNode::new(AstType::STM_EMPTY, tkr.line()) );
 
     } else if tk_accept(tkr, TokenType::TK_IF)? {
-        tk_expect(tkr, TokenType::TK_PAREN_LEFT)?;
-        let a = ast_expression(tkr)?;
-        tk_expect(tkr, TokenType::TK_PAREN_RIGHT)?;
-        let b = ast_statement(tkr)?;
-        if tk_accept(tkr, TokenType::TK_ELSE)? {
-            let c = ast_statement(tkr)?;
-            return Ok(AstNode::new_a_b_c(AstType::STM_IF, tkr.line(), a, b, c));
+        // the arms of an else if chain are parsed by a loop, they don't count as nested
+        let mut arms = Vec::new();
+        let mut stm = None;
+        loop {
+            tk_expect(tkr, TokenType::TK_PAREN_LEFT)?;
+            let a = ast_expression(tkr)?;
+            tk_expect(tkr, TokenType::TK_PAREN_RIGHT)?;
+            let b = ast_statement(tkr)?;
+            arms.push((a, b, tkr.line()));
+            if !tk_accept(tkr, TokenType::TK_ELSE)? {
+                break;
+            }
+            if !tk_accept(tkr, TokenType::TK_IF)? {
+                stm = Some(ast_statement(tkr)?);
+                break;
+            }
+        }
+        while let Some((a, b, line)) = arms.pop() {
+            stm = match stm {
+                Some(c) => Some(AstNode::new_a_b_c(AstType::STM_IF, line, a, b, c)),
+                None => Some(AstNode::new_a_b(AstType::STM_IF, line, a, b)),
+            };
         }
-        return Ok(AstNode::new_a_b(AstType::STM_IF, tkr.line(), a, b));
+        return Ok(stm.unwrap());
 
     } else if tk_accept(tkr, TokenType::TK_DO)? {
         let a = ast_statement(tkr)?;
@@ -1145,7 +1276,8 @@ fn ast_statement(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
             let stm = AstNode::new_a_b(AstType::STM_TRY, tkr.line(), a, b);
             return Ok(stm);
         }
-        return Err(format!("unexpected token in try: {:?} (expected 'catch' or 'finally')", tkr.forward()? ));
+        let lookahead = tkr.forward()?;
+        return Err(tk_error(&lookahead, format!("unexpected token in try: {:?} (expected 'catch' or 'finally')", lookahead.tk_type)));
 
     } else if tk_accept(tkr, TokenType::TK_FUNCTION)? {
         let a = ast_identifier(tkr)?;
@@ -1186,7 +1318,7 @@ fn ast_statement(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(stm);
 }
 
-fn ast_funbody(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_funbody(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     tk_expect(tkr, TokenType::TK_BRACE_LEFT)?;
     
     if tk_accept(tkr, TokenType::TK_BRACE_RIGHT)? == true {
@@ -1205,7 +1337,7 @@ fn ast_funbody(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(head);
 }
 
-fn ast_fundec(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_fundec(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     let a = ast_identifier(tkr)?;
     tk_expect(tkr, TokenType::TK_PAREN_LEFT)?;
     let b = ast_parameters(tkr)?;
@@ -1216,14 +1348,157 @@ fn ast_fundec(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
     return Ok(func);
 }
 
-fn ast_element(tkr: &mut Tokenlizer) -> Result<AstNode, String> {
+fn ast_element(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
     if tk_accept(tkr, TokenType::TK_FUNCTION)? {
-        return ast_fundec(tkr);
+        return ast_nested(tkr, ast_fundec);
     }
     return ast_statement(tkr);
 }
 
-pub fn build_ast_from_script(script: &str) -> Result<AstNode, String> {
+/* names in import and export lists may be the default keyword */
+fn ast_module_name(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
+    if tk_accept(tkr, TokenType::TK_DEFAULT)? {
+        return Ok(AstNode::new_string(AstType::AST_IDENTIFIER, tkr.line(), "default"));
+    }
+    return ast_identifier(tkr);
+}
+
+/* from and as are not keywords, they are identifiers in module items */
+fn tk_expect_word(tkr: &mut Tokenlizer, word: &str) -> Result<(), JsError> {
+    let ntk = tkr.next()?;
+    if ntk.tk_type != TokenType::TK_IDENTIFIER || ntk.tk_value.as_deref() != Some(word) {
+        return Err(tk_error(&ntk, format!("AST error: except '{}' but got {:?}", word, ntk.tk_type)));
+    }
+    return Ok(());
+}
+
+fn tk_accept_word(tkr: &mut Tokenlizer, word: &str) -> Result<bool, JsError> {
+    let ntk = tkr.forward()?;
+    if ntk.tk_type != TokenType::TK_IDENTIFIER || ntk.tk_value.as_deref() != Some(word) {
+        return Ok(false);
+    }
+    tkr.next()?;
+    return Ok(true);
+}
+
+/* { a, b as c }, the spec's a is the name before 'as' and b the name after it */
+fn ast_module_speclist(tkr: &mut Tokenlizer, head: &mut AstNode) -> Result<(), JsError> {
+    tk_expect(tkr, TokenType::TK_BRACE_LEFT)?;
+    while tk_accept(tkr, TokenType::TK_BRACE_RIGHT)? == false {
+        let a = ast_module_name(tkr)?;
+        let b = if tk_accept_word(tkr, "as")? {
+            ast_module_name(tkr)?
+        } else {
+            a.clone()
+        };
+        let spec = AstNode::new_a_b(AstType::AST_MODULE_SPEC, tkr.line(), a, b);
+        ast_module_specpush(head, spec);
+        if tk_accept(tkr, TokenType::TK_BRACE_RIGHT)? {
+            break;
+        }
+        tk_expect(tkr, TokenType::TK_COMMA)?;
+    }
+    return Ok(());
+}
+
+fn ast_module_specpush(head: &mut AstNode, spec: AstNode) {
+    if head.ast_type == AstType::AST_NULL {
+        *head = AstNode::new_list(spec);
+        return;
+    }
+    let mut tail: &mut AstNode = head;
+    while tail.b.is_some() {
+        tail = tail.b.as_mut().unwrap();
+    }
+    AstNode::list_tail_push(tail, spec);
+}
+
+/* import "m"; import d, { a, b as c } from "m"; import * as ns from "m"; */
+fn ast_import(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
+    let mut specs = AstNode::new(AstType::AST_NULL, tkr.line());
+    if tk_lookahead(tkr, TokenType::TK_STRING)? == false {
+        if tk_lookahead(tkr, TokenType::TK_IDENTIFIER)? {
+            let a = AstNode::new_string(AstType::AST_IDENTIFIER, tkr.line(), "default");
+            let b = ast_identifier(tkr)?;
+            ast_module_specpush(&mut specs, AstNode::new_a_b(AstType::AST_MODULE_SPEC, tkr.line(), a, b));
+            if tk_accept(tkr, TokenType::TK_COMMA)? == false {
+                tk_expect_word(tkr, "from")?;
+                return ast_import_from(tkr, specs);
+            }
+        }
+        if tk_accept(tkr, TokenType::TK_MUL)? {
+            tk_expect_word(tkr, "as")?;
+            let a = AstNode::new_string(AstType::AST_IDENTIFIER, tkr.line(), "*");
+            let b = ast_identifier(tkr)?;
+            ast_module_specpush(&mut specs, AstNode::new_a_b(AstType::AST_MODULE_SPEC, tkr.line(), a, b));
+        } else {
+            ast_module_speclist(tkr, &mut specs)?;
+        }
+        tk_expect_word(tkr, "from")?;
+    }
+    return ast_import_from(tkr, specs);
+}
+
+fn ast_import_from(tkr: &mut Tokenlizer, specs: AstNode) -> Result<AstNode, JsError> {
+    let module = tk_expect(tkr, TokenType::TK_STRING)?;
+    let a = AstNode::new_string(AstType::EXP_STRING, tkr.line(), &module.tk_value.unwrap());
+    ast_semicolon(tkr)?;
+    return Ok(AstNode::new_a_b(AstType::STM_IMPORT, tkr.line(), a, specs));
+}
+
+/* export var ...; export function f() {} export { a, b as c }; export default ... */
+fn ast_export(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
+    let null = AstNode::new(AstType::AST_NULL, tkr.line());
+    if tk_accept(tkr, TokenType::TK_DEFAULT)? {
+        if tk_accept(tkr, TokenType::TK_FUNCTION)? {
+            if tk_lookahead(tkr, TokenType::TK_IDENTIFIER)? {
+                let func = ast_fundec(tkr)?;
+                let a = func.a.as_ref().unwrap().as_ref().clone();
+                let b = AstNode::new_string(AstType::AST_IDENTIFIER, tkr.line(), "default");
+                let specs = AstNode::new_list(AstNode::new_a_b(AstType::AST_MODULE_SPEC, tkr.line(), a, b));
+                return Ok(AstNode::new_a_b(AstType::STM_EXPORT, tkr.line(), func, specs));
+            }
+            let exp = ast_formula_funexp(tkr)?;
+            return Ok(AstNode::new_a(AstType::STM_EXPORT_DEFAULT, tkr.line(), exp));
+        }
+        let exp = ast_assignment(tkr)?;
+        ast_semicolon(tkr)?;
+        return Ok(AstNode::new_a(AstType::STM_EXPORT_DEFAULT, tkr.line(), exp));
+    }
+    if tk_accept(tkr, TokenType::TK_FUNCTION)? {
+        let func = ast_fundec(tkr)?;
+        return Ok(AstNode::new_a_b(AstType::STM_EXPORT, tkr.line(), func, null));
+    }
+    if tk_lookahead(tkr, TokenType::TK_VAR)? {
+        let stm = ast_statement(tkr)?;
+        return Ok(AstNode::new_a_b(AstType::STM_EXPORT, tkr.line(), stm, null));
+    }
+    let mut specs = AstNode::new(AstType::AST_NULL, tkr.line());
+    ast_module_speclist(tkr, &mut specs)?;
+    ast_semicolon(tkr)?;
+    return Ok(AstNode::new_a_b(AstType::STM_EXPORT, tkr.line(), null, specs));
+}
+
+/* import and export are not keywords either, they start an item only at the top level of a module */
+fn ast_module_element(tkr: &mut Tokenlizer) -> Result<AstNode, JsError> {
+    if tk_accept_word(tkr, "import")? {
+        return ast_import(tkr);
+    }
+    if tk_accept_word(tkr, "export")? {
+        return ast_export(tkr);
+    }
+    return ast_element(tkr);
+}
+
+pub fn build_ast_from_script(script: &str) -> Result<AstNode, JsError> {
+    return build_ast(script, ast_element);
+}
+
+pub fn build_ast_from_module(script: &str) -> Result<AstNode, JsError> {
+    return build_ast(script, ast_module_element);
+}
+
+fn build_ast(script: &str, element: fn(&mut Tokenlizer) -> Result<AstNode, JsError>) -> Result<AstNode, JsError> {
     let mut tkr = Tokenlizer::new(script);
 
     if tk_accept(&mut tkr, TokenType::TK_EOF)? {
@@ -1231,14 +1506,15 @@ pub fn build_ast_from_script(script: &str) -> Result<AstNode, String> {
         return Ok(empty);
     }
 
-    let mut head = AstNode::new_list( ast_element(&mut tkr)?);
+    let mut head = AstNode::new_list( element(&mut tkr)?);
 
     let mut tail: &mut AstNode = &mut head;
     while tk_accept(&mut tkr, TokenType::TK_EOF)? == false {
-        AstNode::list_tail_push(tail, ast_element(&mut tkr)?);
+        AstNode::list_tail_push(tail, element(&mut tkr)?);
         tail = tail.b.as_mut().unwrap();
     }
 
+    ast_check_depth(&head)?;
     return Ok(head);
 }
 
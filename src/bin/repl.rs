@@ -30,28 +30,34 @@ impl ezjs::runtime::Hookable for MyHook {
     }
 }
 
-fn new_hook(rt: &mut ezjs::runtime::JsRuntime<MyHook>, argc: usize)  {
+fn new_hook(rt: &mut ezjs::runtime::JsRuntime<MyHook>, argc: usize) -> Result<(), ezjs::error::JsError> {
     if argc != 1 {
-        panic!("new_hook argument count error! {}", line!());
+        return Err(rt.new_type_error("new_hook argument count error!".to_string()));
     }
-    let value = rt.top(-1).to_string();
+    let value: String = rt.arg(argc, 0)?;
     let new_hook = rt.new_hook(MyHook::new(value));
     rt.push_object( ezjs::value::SharedObject_new(new_hook));
+    return Ok(());
 }
 
-fn print_hook(rt: &mut ezjs::runtime::JsRuntime<MyHook>, argc: usize) {
+fn print_hook(rt: &mut ezjs::runtime::JsRuntime<MyHook>, argc: usize) -> Result<(), ezjs::error::JsError> {
     if argc != 1 {
-        panic!("print_hook argument count error! {}", line!());
+        return Err(rt.new_type_error("print_hook argument count error!".to_string()));
     }
     let value = rt.top(-1);
+    if !value.is_object() || !value.get_object().borrow().is_hook() {
+        return Err(rt.new_type_error("print_hook needs a hook object".to_string()));
+    }
     let hook = rt.get_hook( &value );
     println!("hook {:?} ", hook);
     rt.push_undefined();
+    return Ok(());
 }
 
-fn show_hooks(rt: &mut ezjs::runtime::JsRuntime<MyHook>, _argc: usize)  {    
+fn show_hooks(rt: &mut ezjs::runtime::JsRuntime<MyHook>, _argc: usize) -> Result<(), ezjs::error::JsError> {
     println!("{:?}", rt.hooks);
-    rt.push_number( rt.hooks.keys().len() as f64);
+    rt.push_value( rt.hooks.keys().len() );
+    return Ok(());
 }
 
 pub fn main() {
@@ -66,11 +72,34 @@ pub fn main() {
     let fobj = rt.new_builtin(ezjs::runtime::JsBuiltinFunction::new(show_hooks));
     rt.genv.borrow_mut().init_var("show_hooks", ezjs::value::SharedValue::new_object(fobj) );
 
+    // a closure keeps its own state, without going through the root hook
+    let mut counter = 0;
+    let fobj = rt.new_builtin(ezjs::runtime::JsBuiltinFunction::new_closure(move |rt, _argc| {
+        counter = counter + 1;
+        rt.push_number(counter as f64);
+        return Ok(());
+    }));
+    rt.genv.borrow_mut().init_var("counter", ezjs::value::SharedValue::new_object(fobj) );
+
+    // require() loads scripts relative to the working directory
+    rt.set_module_loader(Box::new(ezjs::module::FsModuleLoader::new(".")));
+
     println!("REPL of ezjs v0.1.0");
     let args: Vec<String> = env::args().collect();
     for i in 1..args.len() {
-        let content = fs::read_to_string(&args[i]).unwrap();
-        let vmf = ezjs::build_function_from_code(&content).unwrap();
+        // precompiled .ezbc files are loaded without parsing
+        let vmf = if args[i].ends_with(".ezbc") {
+            ezjs::build_function_from_bytes(&fs::read(&args[i]).unwrap())
+        } else {
+            ezjs::build_function_from_code(&fs::read_to_string(&args[i]).unwrap())
+        };
+        let vmf = match vmf {
+            Ok(vmf) => vmf,
+            Err(e) => {
+                println!("{}: {}", args[i], e);
+                break;
+            }
+        };
         ezjs::dump_function(&vmf);
         let ret = ezjs::run_script(&mut rt, vmf);
         if ret.is_err() {
@@ -87,7 +116,12 @@ pub fn main() {
             Ok(_n) => {
                 if line != "" {
 
-                    let vmf = ezjs::build_function_from_code(&line).unwrap();
+                    let vmf = ezjs::build_function_from_code(&line);
+                    if let Err(e) = vmf {
+                        println!("{}", e);
+                        continue;
+                    }
+                    let vmf = vmf.unwrap();
                     //ezjs::dump_function(&vmf);
 
                     let begin = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
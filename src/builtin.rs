@@ -1,41 +1,49 @@
 use std::collections::HashMap;
+use std::rc::Rc;
+use std::cell::RefCell;
 
 use crate::bytecode::*;
 use crate::value::*;
 use crate::runtime::*;
+use crate::error::*;
 
 use crate::builtin_script::*;
 
 impl<T:Hookable> JsBuiltinFunction<T> {
-	pub fn new(f: fn(&mut JsRuntime<T>, usize)) -> Self {
-		JsBuiltinFunction {
-			f:	f,
-		}
+	pub fn new(f: fn(&mut JsRuntime<T>, usize) -> Result<(), JsError>) -> Self {
+		JsBuiltinFunction::native(f)
+	}
+	pub fn new_closure<F>(f: F) -> Self
+		where F: FnMut(&mut JsRuntime<T>, usize) -> Result<(), JsError> + 'static {
+		let f: JsHostFunction<T> = Box::new(f);
+		JsBuiltinFunction::closure(Rc::new(RefCell::new(f)))
 	}
 }
 
 // The Object class 
-fn object_constructor<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) {
+fn object_constructor<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsError> {
     if argc > 0 {        
         let value = rt.top(-1);
         if value.is_something() {        
             rt.push( value.duplicate() );
-            return;
+            return Ok(());
         }
     }
     rt.push( SharedValue::new_vanilla(rt.prototypes.object_prototype.clone()) );
+    return Ok(());
 }
 
-fn object_tostring<T: Hookable>(rt: &mut JsRuntime<T>, _argc: usize)  {
+fn object_tostring<T: Hookable>(rt: &mut JsRuntime<T>, _argc: usize) -> Result<(), JsError> {
     let thiz = rt.top(-1);
     rt.push_string( thiz.to_string());
+    return Ok(());
 }
 
-fn object_proto<T: Hookable>(rt: &mut JsRuntime<T>, _argc: usize) {
+fn object_proto<T: Hookable>(rt: &mut JsRuntime<T>, _argc: usize) -> Result<(), JsError> {
     let target = rt.top(-1);
     if !target.is_object() {
         rt.push_undefined();    
-        return;
+        return Ok(());
     }
 
     let target_object = target.get_object();
@@ -43,9 +51,10 @@ fn object_proto<T: Hookable>(rt: &mut JsRuntime<T>, _argc: usize) {
     if target_object.borrow().__proto__.is_some() {
         let proto = target_object.borrow().__proto__.as_ref().unwrap().clone();
         rt.push_object(proto);
-        return;
+        return Ok(());
     }
     rt.push_null();
+    return Ok(());
 }
 
 fn object_proto_builtins<T: Hookable>() -> HashMap<String, JsBuiltinFunction<T>> {
@@ -55,9 +64,9 @@ fn object_proto_builtins<T: Hookable>() -> HashMap<String, JsBuiltinFunction<T>>
     return builtins;
 }
 
-fn object_preventextensions<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) {
+fn object_preventextensions<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsError> {
     if argc != 1 {
-        panic!("object_preventextensions argument count error! {}", line!());
+        return Err(rt.new_type_error("object_preventextensions argument count error!".to_string()));
     }
 
     let value = rt.top(-1);
@@ -65,52 +74,71 @@ fn object_preventextensions<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) {
         value.get_object().borrow_mut().extensible = false;
     }
     rt.push(value);
+    return Ok(());
 }
 
-fn object_setprototypeof<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) {
+const JS_PROTO_DEPTH_LIMIT: usize = 1000;
+
+fn object_setprototypeof<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsError> {
     if argc != 2 {
-        panic!("object_setprototypeof argument count error! {}", line!());
+        return Err(rt.new_type_error("object_setprototypeof argument count error!".to_string()));
     }
 
     let target = rt.top(-2);
     if !target.is_object() {
         rt.push_undefined();    
-        return;
+        return Ok(());
     }
 
     let proto = rt.top(-1);
     if !proto.is_object() {
         rt.push_undefined();
-        return;    
+        return Ok(());
+    }
+
+    /* a chain through the target itself would never end, and a longer chain than the walk allows is refused */
+    let target_obj = target.get_object();
+    let mut next = Some(proto.get_object());
+    let mut depth = 0;
+    while let Some(p) = next {
+        if Rc::ptr_eq(&p, &target_obj) {
+            return Err(rt.new_type_error("Object.setPrototypeOf: cyclic __proto__ value".to_string()));
+        }
+        depth = depth + 1;
+        if depth > JS_PROTO_DEPTH_LIMIT {
+            return Err(rt.new_type_error("Object.setPrototypeOf: __proto__ chain is too deep".to_string()));
+        }
+        next = p.borrow().__proto__.clone();
     }
 
-    target.get_object().borrow_mut().__proto__ = Some(proto.get_object());
+    target_obj.borrow_mut().__proto__ = Some(proto.get_object());
     rt.push(target);
+    return Ok(());
 }
 
-fn object_defineproperty<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) {
+fn object_defineproperty<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsError> {
     if argc != 3 {
-        panic!("object_defineproperty argument count error! {}", line!());
+        return Err(rt.new_type_error("object_defineproperty argument count error!".to_string()));
     }
 
     let target = rt.top(-3);
     if !target.is_object() {
         rt.push(target);
-        return;
+        return Ok(());
     }
     let target_object = target.get_object();
 
     let name = rt.top(-2);
     if !name.is_string() {
         rt.push(target);
-        return;
+        return Ok(());
     }
     let name = name.to_string();
 
     let desc = rt.top(-1);
     if !desc.is_object() {
         rt.push(target);
-        return;
+        return Ok(());
     }
     let desc_object = desc.get_object();
 
@@ -173,7 +201,7 @@ fn object_defineproperty<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) {
     prop.fill(value, prop_attr, getter, setter);
     target_object.borrow_mut().set_property(&name, prop);
     rt.push(target);
-    return;
+    return Ok(());
 }
 
 // Object.XXXX not in Object.prototype
@@ -187,17 +215,18 @@ fn object_builtins<T:Hookable>() -> HashMap<String, JsBuiltinFunction<T>> {
 }
 
 // The String class
-fn string_constructor<T:Hookable>(rt: &mut JsRuntime<T>, argc: usize) {
+fn string_constructor<T:Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsError> {
     if argc != 1 {
-        panic!("string_constructor argument count error! {}", line!());
+        return Err(rt.new_type_error("string_constructor argument count error!".to_string()));
     }
 
     let value = rt.top(-1);
     if value.is_string() {
         rt.push(value.duplicate());
-        return;
+        return Ok(());
     }    
     rt.push_string("".to_string());
+    return Ok(());
 }
 
 fn string_proto_builtins<T:Hookable>() -> HashMap<String, JsBuiltinFunction<T>> {
@@ -207,37 +236,43 @@ fn string_proto_builtins<T:Hookable>() -> HashMap<String, JsBuiltinFunction<T>>
 }
 
 // The Array class
-fn array_constructor<T:Hookable>(rt: &mut JsRuntime<T>, _argc: usize) {    
+fn array_constructor<T:Hookable>(rt: &mut JsRuntime<T>, _argc: usize) -> Result<(), JsError> {
     let obj = JsObject::new_array(rt.prototypes.array_prototype.clone());
     let jv = SharedValue::new_object(obj);
     rt.push(jv);
+    return Ok(());
 }
 
-fn array_push<T:Hookable>(rt: &mut JsRuntime<T>, argc: usize) {
+fn array_push<T:Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsError> {
     if argc != 1 {
-        panic!("array_push argument count error! {}", line!());
+        return Err(rt.new_type_error("array_push argument count error!".to_string()));
     }
 
     let target = rt.top(-2);
-    assert!(target.is_object());
+    if !target.is_object() || !target.get_object().borrow().is_array() {
+        return Err(rt.new_type_error("Array.prototype.push called on none array".to_string()));
+    }
+    let value = rt.top(-1).duplicate();
     let sobj = target.get_object();
     let mut object = sobj.borrow_mut();
-    assert!(object.is_array());
    
-    let value = rt.top(-1).duplicate();
     object.get_mut_array().push(value);
+    object.charge(&rt.heap, JS_VALUE_COST);
     
     rt.push_number(object.get_array().len() as f64);
+    return Ok(());
 }
 
-fn array_length<T:Hookable>(rt: &mut JsRuntime<T>, _argc: usize) {
+fn array_length<T:Hookable>(rt: &mut JsRuntime<T>, _argc: usize) -> Result<(), JsError> {
     let target = rt.top(-1);
-    assert!(target.is_object());
+    if !target.is_object() || !target.get_object().borrow().is_array() {
+        return Err(rt.new_type_error("Array.prototype.length called on none array".to_string()));
+    }
     let sobj = target.get_object();
     let object = sobj.borrow_mut();
-    assert!(object.is_array());
    
     rt.push_number(object.get_array().len() as f64);
+    return Ok(());
 }
 
 fn array_proto_builtins<T:Hookable>() -> HashMap<String, JsBuiltinFunction<T>> {
@@ -249,15 +284,16 @@ fn array_proto_builtins<T:Hookable>() -> HashMap<String, JsBuiltinFunction<T>> {
 }
 
 // The Function class
-fn function_constructor<T:Hookable>(rt: &mut JsRuntime<T>, _argc: usize) {
+fn function_constructor<T:Hookable>(rt: &mut JsRuntime<T>, _argc: usize) -> Result<(), JsError> {
     let vmf = SharedFunction_new(VMFunction::new_anonymous());
     let fobj = JsObject::new_function(vmf, rt.cenv.clone(), rt.prototypes.function_prototype.clone());
     rt.push(SharedValue::new_object(fobj));
+    return Ok(());
 }
 
-fn function_apply<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize)  {
+fn function_apply<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsError> {
     if argc != 2 {
-        panic!("function_apply argument count error! {}", line!());
+        return Err(rt.new_type_error("function_apply argument count error!".to_string()));
     }
 
     let func = rt.top(-3);
@@ -268,6 +304,9 @@ fn function_apply<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize)  {
     if arguments_object.is_object() {
         let obj_ = arguments_object.get_object();
         let obj = obj_.borrow();
+        if !obj.is_array() {
+            return Err(rt.new_type_error("Function.prototype.apply needs an array of arguments".to_string()));
+        }
 
         let args = obj.get_array();
         for i in 0..args.len() {
@@ -279,26 +318,16 @@ fn function_apply<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize)  {
     rt.push(func);
     rt.push(new_thiz);
     for i in 0..arguments.len() {
+        rt.check_interrupt()?;
         rt.push( arguments[i].clone() );
     }
 
-    let ret = jscall(rt, argc);
-    if ret.is_ok() {
-        return;
-    }
-    let exp = ret.err().unwrap();
-
-    panic!(" apply with exception happen! {:?}", exp);
+    return jscall(rt, argc);
 }
 
-fn function_call<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize)  {
+fn function_call<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsError> {
     if argc == 0 {
-        let ret = jscall(rt, argc);
-        if ret.is_ok() {
-            return;
-        }
-        let exp = ret.err().unwrap();
-        panic!(" apply with exception happen! {:?}", exp);
+        return jscall(rt, argc);
     }
 
     let func = rt.top( -1 - argc as isize);
@@ -312,16 +341,11 @@ fn function_call<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize)  {
     rt.push(func);
     rt.push(new_thiz);
     for i in 0..arguments.len() {
+        rt.check_interrupt()?;
         rt.push( arguments[i].clone() );
     }
 
-    let ret = jscall(rt, argc - 1);
-    if ret.is_ok() {
-        return;
-    }
-    let exp = ret.err().unwrap();
-
-    panic!(" call with exception happen! {:?}", exp);
+    return jscall(rt, argc - 1);
 }
 
 fn function_proto_builtins<T:Hookable>() -> HashMap<String, JsBuiltinFunction<T>> {
@@ -333,9 +357,9 @@ fn function_proto_builtins<T:Hookable>() -> HashMap<String, JsBuiltinFunction<T>
 }
 
 // The Exception class
-fn exception_constructor<T:Hookable>(rt: &mut JsRuntime<T>, argc: usize) {
+fn exception_constructor<T:Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsError> {
     if argc != 1 {
-        panic!("exception_constructor argument count error! {}", line!());
+        return Err(rt.new_type_error("exception_constructor argument count error!".to_string()));
     }
     
     let value = rt.top(-1);    
@@ -344,12 +368,29 @@ fn exception_constructor<T:Hookable>(rt: &mut JsRuntime<T>, argc: usize) {
     let exp = JsException::new(msg);
     let value = SharedValue::new_object(JsObject::new_exception(rt.prototypes.exception_prototype.clone(), exp));
     rt.push(value);
+    return Ok(());
 }
 
-fn exception_message<T:Hookable>(rt: &mut JsRuntime<T>, _argc: usize) {
+fn type_error_constructor<T:Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsError> {
+    if argc != 1 {
+        return Err(rt.new_type_error("type_error_constructor argument count error!".to_string()));
+    }
+
+    let msg = format!("TypeError: {}", rt.top(-1).to_string());
+    let exp = JsException::new(msg);
+    let value = SharedValue::new_object(JsObject::new_exception(rt.prototypes.type_error_prototype.clone(), exp));
+    rt.push(value);
+    return Ok(());
+}
+
+fn exception_message<T:Hookable>(rt: &mut JsRuntime<T>, _argc: usize) -> Result<(), JsError> {
+    if !rt.top(-1).is_exception() {
+        return Err(rt.new_type_error("Error.prototype.message called on none exception".to_string()));
+    }
     let exp_object = rt.top(-1).get_object();
     let exp = exp_object.borrow().get_exception();
     rt.push_string(exp.msg);
+    return Ok(());
 }
 
 fn exception_proto_builtins<T:Hookable>() -> HashMap<String, JsBuiltinFunction<T>> {
@@ -369,7 +410,7 @@ fn create_class_functions<T:Hookable>(rt: &mut JsRuntime<T>, target: SharedObjec
         prop.fill_attr(JS_READONLY_ATTR);
         prop.value = SharedValue::new_object(func_obj);
 
-        class_obj.properties.insert(k, prop);
+        class_obj.set_property(&k, prop);
     }
 }
 
@@ -388,12 +429,12 @@ fn create_builtin_class<T:Hookable>(rt: &mut JsRuntime<T>, constructor: JsBuilti
         prop.fill_attr(JS_READONLY_ATTR);
         prop.value = SharedValue::new_object(func_obj);
 
-        prototype_obj.properties.insert(k, prop);
+        prototype_obj.set_property(&k, prop);
     }
     let mut prop = JsProperty::new();
     prop.fill_attr(JS_READONLY_ATTR);
     prop.value = SharedValue::new_sobject(class_obj.clone());
-    prototype_obj.properties.insert("constructor".to_string(), prop);
+    prototype_obj.set_property("constructor", prop);
     prototype_obj.__proto__ = top;
 
     let prototype_obj = SharedObject_new(prototype_obj);
@@ -401,7 +442,7 @@ fn create_builtin_class<T:Hookable>(rt: &mut JsRuntime<T>, constructor: JsBuilti
     let mut prop = JsProperty::new();
     prop.fill_attr(JS_READONLY_ATTR);
     prop.value = SharedValue::new_sobject(prototype_obj.clone());
-    class_obj.borrow_mut().properties.insert("prototype".to_string(), prop);
+    class_obj.borrow_mut().set_property("prototype", prop);
     
     return (class_obj, prototype_obj);
 }
@@ -412,6 +453,80 @@ fn set_global_class<T:Hookable>(rt: &mut JsRuntime<T>, name: &str, class_obj: Sh
     rt.genv.borrow_mut().target().borrow_mut().set_property(name, prop);
 }
 
+// classes defined by host, backed by hooks
+fn class_receiver<T:Hookable>(rt: &JsRuntime<T>, argc: usize, prototype: &SharedObject) -> Option<u64> {
+    let thiz = rt.top(-1 - argc as isize);
+    if !thiz.is_object() {
+        return None;
+    }
+    let thiz = thiz.get_object();
+    let hid = thiz.borrow().hook_id()?;
+    let mut proto = thiz.borrow().__proto__.clone();
+    while let Some(p) = proto {
+        if Rc::ptr_eq(&p, prototype) {
+            return Some(hid);
+        }
+        proto = p.borrow().__proto__.clone();
+    }
+    return None;
+}
+
+fn class_method<T:Hookable + 'static>(name: String, method: JsClassMethod<T>, prototype: SharedObject) -> JsBuiltinFunction<T> {
+    return JsBuiltinFunction::new_closure(move |rt, argc| {
+        let hid = match class_receiver(rt, argc, &prototype) {
+            Some(hid) => hid,
+            None => {
+                return Err(rt.new_type_error(format!("{} called on incompatible receiver", name)));
+            }
+        };
+        match rt.with_hook(hid, |hook, rt| method(hook, rt, argc)) {
+            Some(r) => r,
+            None => Err(rt.new_type_error(format!("{} can't be called recursively", name))),
+        }
+    });
+}
+
+impl<T: Hookable + 'static> JsRuntime<T> {
+    /* Define a global class for a host type, `new Name(...)` calls the constructor and wraps its result in a hook object.
+       Methods are read only functions of the prototype, getters are accessor properties called with argc 0. */
+    pub fn define_class(&mut self, name: &str, constructor: JsClassConstructor<T>,
+        methods: HashMap<String, JsClassMethod<T>>, getters: HashMap<String, JsClassMethod<T>>) -> SharedObject {
+
+        let (class_obj, prototype) = create_builtin_class(self, JsBuiltinFunction::new(object_constructor), HashMap::new(), Some(self.prototypes.object_prototype.clone()));
+
+        let proto = prototype.clone();
+        let ctor = JsBuiltinFunction::new_closure(move |rt, argc| {
+            let hook = constructor(rt, argc)?;
+            let mut obj = rt.new_hook(hook);
+            obj.__proto__ = Some(proto.clone());
+            rt.push_object(SharedObject_new(obj));
+            return Ok(());
+        });
+        let fid = class_obj.borrow().get_builtin();
+        self.builtins[fid] = ctor;
+
+        for (k, v) in methods {
+            let func_obj = self.new_builtin(class_method(format!("{}.prototype.{}", name, k), v, prototype.clone()));
+
+            let mut prop = JsProperty::new();
+            prop.fill_attr(JS_READONLY_ATTR);
+            prop.value = SharedValue::new_object(func_obj);
+            prototype.borrow_mut().set_property(&k, prop);
+        }
+        for (k, v) in getters {
+            let func_obj = self.new_builtin(class_method(format!("{}.prototype.{}", name, k), v, prototype.clone()));
+
+            let mut prop = JsProperty::new();
+            prop.fill_attr(JS_READONLY_ATTR);
+            prop.getter = Some(SharedObject_new(func_obj));
+            prototype.borrow_mut().set_property(&k, prop);
+        }
+
+        set_global_class(self, name, class_obj.clone());
+        return class_obj;
+    }
+}
+
 pub fn prototypes_init<T:Hookable>(rt: &mut JsRuntime<T>) {
     // Object
     let (top_class, top_prototype) = create_builtin_class(rt, JsBuiltinFunction::new(object_constructor), object_proto_builtins(), None);
@@ -437,14 +552,19 @@ pub fn prototypes_init<T:Hookable>(rt: &mut JsRuntime<T>) {
     // Exception
     let (exp_classs_object, exp_prototype) = create_builtin_class(rt, JsBuiltinFunction::new(exception_constructor), exception_proto_builtins(), Some(top_prototype.clone()));
     set_global_class(rt, "Error", exp_classs_object.clone());
-    rt.prototypes.exception_prototype = exp_prototype;
+    rt.prototypes.exception_prototype = exp_prototype.clone();
+
+    let (type_error_object, type_error_prototype) = create_builtin_class(rt, JsBuiltinFunction::new(type_error_constructor), HashMap::new(), Some(exp_prototype));
+    set_global_class(rt, "TypeError", type_error_object.clone());
+    rt.prototypes.type_error_prototype = type_error_prototype;
 }
 
 fn create_console_object<T:Hookable>(runtime: &mut JsRuntime<T>) {
-    fn println<T:Hookable>(rt: &mut JsRuntime<T>, _argc: usize) {
+    fn println<T:Hookable>(rt: &mut JsRuntime<T>, _argc: usize) -> Result<(), JsError> {
         let msg = rt.top(-1).to_string();
         println!("{}", msg);
         rt.push_undefined();        
+        return Ok(());
     }
 
     let console_value = SharedValue::new_vanilla(runtime.prototypes.object_prototype.clone());
@@ -459,15 +579,16 @@ fn create_console_object<T:Hookable>(runtime: &mut JsRuntime<T>) {
 
 pub fn builtin_init<T:Hookable>(runtime: &mut JsRuntime<T>) {
     // global functions for runtime 
-    fn assert<T:Hookable>(rt: &mut JsRuntime<T>, argc: usize) {    
+    fn assert<T:Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsError> {
         if argc == 2 {
             let b = rt.top(-2).to_boolean();
             if !b {
                 let info = rt.top(-1).to_string();
-                panic!("ASSERT: {}", info);
+                return Err(rt.new_error(format!("ASSERT: {}", info)));
             }
         }
         rt.push_undefined();
+        return Ok(());
     }
     // TODO : isFinite() isNaN() parseFloat() parseInt()
     
@@ -477,6 +598,7 @@ pub fn builtin_init<T:Hookable>(runtime: &mut JsRuntime<T>) {
 
     // register some basic runtime objects
     create_console_object(runtime);
+    crate::module::module_init(runtime);
 
     // executing builtin code before any code.
     let vmf = crate::build_function_from_code(BUILDIN_SCRIPT).unwrap();    
@@ -1,7 +1,12 @@
+use std::cell::RefCell;
 use std::convert::TryFrom;
 use std::rc::Rc;
 
 use crate::ast::*;
+use crate::shape::*;
+
+/* version of the opcodes and their operands, bump it when they change so saved bytecode is rejected */
+pub const OPCODE_SET_VERSION: u16 = 3;
 
 /* bytecode stuff */
 #[allow(non_camel_case_types)]
@@ -35,15 +40,17 @@ pub enum OpcodeType {
 	OP_GETVAR,	/* -S- <value> */
 	OP_SETVAR,	/* <value> -S- <value> */
 	OP_DELVAR,	/* -S- <success> */
+	OP_GETLOCAL,	/* -K- <value> */
+	OP_SETLOCAL,	/* <value> -K- <value> */
 
 	OP_INITPROP,	/* <obj> <key> <val> -- <obj> */
 	OP_INITGETTER,	/* <obj> <key> <closure> -- <obj> */
 	OP_INITSETTER,	/* <obj> <key> <closure> -- <obj> */
 
 	OP_GETPROP,	/* <obj> <name> -- <value> */
-	OP_GETPROP_S,	/* <obj> -S- <value> */
+	OP_GETPROP_S,	/* <obj> -P- <value> */
 	OP_SETPROP,	/* <obj> <name> <value> -- <value> */
-	OP_SETPROP_S,	/* <obj> <value> -S- <value> */
+	OP_SETPROP_S,	/* <obj> <value> -P- <value> */
 	OP_DELPROP,	/* <obj> <name> -- <success> */
 	OP_DELPROP_S,	/* <obj> -S- <success> */
 
@@ -133,6 +140,8 @@ impl TryFrom<u16> for OpcodeType {
 			x if x == OpcodeType::OP_GETVAR as u16 => Ok(OpcodeType::OP_GETVAR),
 			x if x == OpcodeType::OP_SETVAR as u16 => Ok(OpcodeType::OP_SETVAR),
 			x if x == OpcodeType::OP_DELVAR as u16 => Ok(OpcodeType::OP_DELVAR),
+			x if x == OpcodeType::OP_GETLOCAL as u16 => Ok(OpcodeType::OP_GETLOCAL),
+			x if x == OpcodeType::OP_SETLOCAL as u16 => Ok(OpcodeType::OP_SETLOCAL),
 			x if x == OpcodeType::OP_INITPROP as u16 => Ok(OpcodeType::OP_INITPROP),
 			x if x == OpcodeType::OP_INITGETTER as u16 => Ok(OpcodeType::OP_INITGETTER),
 			x if x == OpcodeType::OP_INITSETTER as u16 => Ok(OpcodeType::OP_INITSETTER),
@@ -194,6 +203,48 @@ impl TryFrom<u16> for OpcodeType {
     }
 }
 
+impl OpcodeType {
+	/* number of values the opcode takes from stack, arguments of OP_CALL/OP_NEW are not included */
+	pub fn stack_pops(&self) -> usize {
+		match self {
+			OpcodeType::OP_POP | OpcodeType::OP_DUP => 1,
+			OpcodeType::OP_DUP2 | OpcodeType::OP_ROT2 => 2,
+			OpcodeType::OP_ROT3 => 3,
+			OpcodeType::OP_ROT4 => 4,
+
+			OpcodeType::OP_SETVAR | OpcodeType::OP_SETLOCAL => 1,
+			OpcodeType::OP_INITPROP | OpcodeType::OP_INITGETTER | OpcodeType::OP_INITSETTER => 3,
+			OpcodeType::OP_GETPROP => 2,
+			OpcodeType::OP_GETPROP_S => 1,
+			OpcodeType::OP_SETPROP => 3,
+			OpcodeType::OP_SETPROP_S => 2,
+			OpcodeType::OP_DELPROP => 2,
+			OpcodeType::OP_DELPROP_S => 1,
+			OpcodeType::OP_ITERATOR | OpcodeType::OP_NEXTITER => 1,
+
+			OpcodeType::OP_CALL => 2,
+			OpcodeType::OP_NEW => 1,
+
+			OpcodeType::OP_TYPEOF | OpcodeType::OP_POS | OpcodeType::OP_NEG | OpcodeType::OP_BITNOT |
+			OpcodeType::OP_LOGNOT | OpcodeType::OP_INC | OpcodeType::OP_DEC |
+			OpcodeType::OP_POSTINC | OpcodeType::OP_POSTDEC => 1,
+
+			OpcodeType::OP_MUL | OpcodeType::OP_DIV | OpcodeType::OP_MOD | OpcodeType::OP_ADD |
+			OpcodeType::OP_SUB | OpcodeType::OP_SHL | OpcodeType::OP_SHR | OpcodeType::OP_USHR |
+			OpcodeType::OP_LT | OpcodeType::OP_GT | OpcodeType::OP_LE | OpcodeType::OP_GE |
+			OpcodeType::OP_EQ | OpcodeType::OP_NE | OpcodeType::OP_STRICTEQ | OpcodeType::OP_STRICTNE |
+			OpcodeType::OP_JCASE | OpcodeType::OP_BITAND | OpcodeType::OP_BITXOR | OpcodeType::OP_BITOR |
+			OpcodeType::OP_IN | OpcodeType::OP_INSTANCEOF => 2,
+
+			OpcodeType::OP_THROW | OpcodeType::OP_CATCH => 1,
+			OpcodeType::OP_JTRUE | OpcodeType::OP_JFALSE => 1,
+			OpcodeType::OP_RETURN => 1,
+
+			_ => 0,
+		}
+	}
+}
+
 #[allow(non_camel_case_types)]
 #[derive(Debug, Copy, Clone)]
 pub enum VMJumpType {
@@ -205,13 +256,14 @@ pub enum VMJumpType {
 #[derive(Debug, Clone)]
 pub enum VMJumpScope {
 	TryScope(Option<AstNode>),
-	CatchScope,
+	CatchScope(Option<AstNode>),
 	SwitchScope,
 	ForLoop,
 	ForInLoop,
 	DoLoop,
 	WhileLoop,
 	LabelSection(String),
+	FinallyValue,
 }
 
 #[allow(non_camel_case_types)]
@@ -226,11 +278,44 @@ pub struct VMFunction {
 	pub script:		bool,
 	pub numparams:	usize,
 	pub numvars:	usize,
+	/* for each param and var, true if it's kept in a slot of the call instead of the scope */
+	pub locals:		Vec<bool>,
+	/* the call creates the arguments object, the code uses it */
+	pub arguments:	bool,
+	/* the call creates a scope, for variables of inner functions and catch, arguments and undeclared names */
+	pub scope:		bool,
 	pub code:		Vec<u16>,
 
 	pub num_tab:	Vec<f64>,
 	pub str_tab:	Vec<String>,
 	pub func_tab:	Vec<Rc<Box<VMFunction>>>,
+	/* name in str_tab of each OP_GETPROP_S/OP_SETPROP_S, every instruction has an entry and an inline cache,
+	   at most 0x10000 of them, later accesses use OP_GETPROP/OP_SETPROP */
+	pub prop_tab:	Vec<usize>,
+	pub caches:		InlineCaches,
 
 	pub jumps:		Vec<VMJumpTable>,
+	/* code emitted by copies of finally blocks while compiling, nested ones multiply it */
+	pub finally_code:	usize,
+
+	/* module items of a script, linked to other modules by the runtime */
+	pub imports:	Vec<VMModuleBinding>,
+	pub exports:	Vec<VMModuleBinding>,
+}
+
+/* Inline caches of OP_GETPROP_S and OP_SETPROP_S, by entry of prop_tab. The runtime fills them,
+   a new or loaded function starts with empty caches. */
+#[derive(Default)]
+pub struct InlineCaches {
+	pub(crate) entries:	RefCell<Vec<PropertyCache>>,
+}
+
+/* import: name of specifier module bound to local, "*" is the namespace and "default" the default export.
+   export: local exported as name, specifier is empty. */
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct VMModuleBinding {
+	pub specifier:	String,
+	pub name:		String,
+	pub local:		String,
 }
@@ -15,7 +15,7 @@ pub fn str_to_number(symbol: &str) -> Option<f64> {
 			return Some(v as f64);
 		}
 	}
-	if symbol.starts_with("bx") {
+	if symbol.starts_with("0b") {
 		let symbol:&str = &symbol[2..];
 		if let Ok(v) = u64::from_str_radix(&symbol, 2) {
 			return Some(v as f64);
@@ -1,7 +1,9 @@
+use std::collections::HashSet;
 use std::rc::Rc;
 
 use crate::ast::*;
 use crate::bytecode::*;
+use crate::error::*;
 
 /* Local help function and struct */
 struct AstListIterator<'a> {
@@ -121,6 +123,97 @@ impl AstNode {
     }
 }
 
+fn compile_error(node: &AstNode, msg: &str) -> JsError {
+    return JsError::compile(node.src_line, msg.to_string());
+}
+
+/* Names in the code of a function, inner functions are collected apart */
+struct FuncNames<'a> {
+    used:       HashSet<&'a str>,       // identifiers read or written
+    declared:   HashSet<&'a str>,       // params, vars, declared functions, the name and arguments
+    catches:    HashSet<&'a str>,       // catch variables
+    inner:      Vec<&'a AstNode>,
+}
+
+impl<'a> FuncNames<'a> {
+    fn scan(name: Option<&'a AstNode>, params: Option<&'a AstNode>, body: Option<&'a AstNode>) -> Self {
+        let mut names = FuncNames {
+            used:       HashSet::new(),
+            declared:   HashSet::new(),
+            catches:    HashSet::new(),
+            inner:      Vec::new(),
+        };
+        names.declared.insert("arguments");
+        if let Some(name) = name {
+            if !name.is_null() {
+                names.declared.insert(name.str());
+            }
+        }
+        if let Some(params) = params {
+            if params.is_list() {
+                for p in params.iter() {
+                    names.declared.insert(p.str());
+                }
+            }
+        }
+
+        let mut nodes: Vec<&AstNode> = Vec::new();
+        if let Some(body) = body {
+            /* only functions declared at the top of the body are hoisted, like parsing_funname */
+            if body.is_list() {
+                for n in body.iter() {
+                    if n.ast_type == AstType::AST_FUNDEC {
+                        names.declared.insert(n.a().str());
+                    }
+                }
+            }
+            nodes.push(body);
+        }
+        while let Some(node) = nodes.pop() {
+            if node.is_func() {
+                names.inner.push(node);
+                continue;
+            }
+            match node.ast_type {
+                AstType::EXP_IDENTIFIER => {
+                    names.used.insert(node.str());
+                },
+                AstType::EXP_VAR => {
+                    names.declared.insert(node.a().str());
+                },
+                AstType::STM_TRY if node.has_b() && node.has_c() => {
+                    names.catches.insert(node.b().str());
+                },
+                _ => {},
+            }
+            for child in [&node.a, &node.b, &node.c, &node.d].iter() {
+                if let Some(child) = child.as_ref() {
+                    nodes.push(child);
+                }
+            }
+        }
+        return names;
+    }
+}
+
+/* Names an inner function takes from the scopes around it, with the names its own inner functions take */
+fn outer_names<'a>(func: &'a AstNode, outer: &mut HashSet<&'a str>) {
+    let name = match func.ast_type {
+        AstType::AST_FUNDEC | AstType::EXP_FUN => func.a.as_deref(),
+        _ => None,
+    };
+    let names = FuncNames::scan(name, func.b.as_deref(), func.c.as_deref());
+    let mut used = names.used;
+    for inner in names.inner.iter() {
+        outer_names(inner, &mut used);
+    }
+    for n in used {
+        if !names.declared.contains(n) {
+            outer.insert(n);
+        }
+    }
+}
+
 /* component stuff */
 impl VMFunction {
     fn new(script: bool) -> Self {
@@ -129,12 +222,20 @@ impl VMFunction {
             script: script,
             numparams: 0,
             numvars: 0,
+            locals:     Vec::new(),
+            arguments:  true,
+            scope:      true,
             code:       Vec::new(),
             num_tab:    Vec::new(),
             str_tab:    Vec::new(),           
             func_tab:   Vec::new(),
+            prop_tab:   Vec::new(),
+            caches:     InlineCaches::default(),
 
             jumps:      Vec::new(),
+            finally_code: 0,
+            imports:    Vec::new(),
+            exports:    Vec::new(),
         }
     }
 
@@ -155,9 +256,6 @@ impl VMFunction {
     fn emitjump(&mut self, op: OpcodeType) -> usize {
         self.code.push(op as u16);
         let addr = self.code.len();
-        if addr > 0xFFFFFFFF {
-            panic!("code length is out of 4G!");
-        }
         self.code.push(0);
         self.code.push(0);
         return addr;
@@ -181,6 +279,27 @@ impl VMFunction {
         self.emit(id);
     }
 
+    /* OP_GETPROP_S or OP_SETPROP_S, every instruction gets an entry of prop_tab for its inline cache.
+       When prop_tab is full the name is pushed for OP_GETPROP or OP_SETPROP, which have no cache. */
+    fn emitprop(&mut self, op: OpcodeType, name: &str) {
+        if self.prop_tab.len() >= 0x10000 {
+            self.emitstring(OpcodeType::OP_STRING, name);
+            if op == OpcodeType::OP_GETPROP_S {
+                self.emitop(OpcodeType::OP_GETPROP);
+            } else {
+                /* <obj> <value> <name> -> <obj> <name> <value> */
+                self.emitop(OpcodeType::OP_ROT2);
+                self.emitop(OpcodeType::OP_SETPROP);
+            }
+            return;
+        }
+        self.emitop(op);
+        let id = self.addstring(name);
+        let r = self.prop_tab.len();
+        self.prop_tab.push(id as usize);
+        self.emit(r as u16);
+    }
+
     fn emitfunction(&mut self, func: VMFunction) {
         self.emitop(OpcodeType::OP_CLOSURE);
         let id = self.addfunc(func);
@@ -219,6 +338,39 @@ impl VMFunction {
         return r as u16;
     }    
 
+    /* slot of a param or var which is kept out of the scope */
+    fn findlocal(&self, var: &str) -> Option<u16> {
+        for i in 0..self.locals.len() {
+            if self.locals[i] && self.str_tab[i].eq(var) {
+                return Some(i as u16);
+            }
+        }
+        return None;
+    }
+
+    /* OP_GETVAR or OP_HASVAR of a name, a local is always defined */
+    fn emitgetvar(&mut self, op: OpcodeType, var: &str) {
+        if let Some(id) = self.findlocal(var) {
+            self.emitop(OpcodeType::OP_GETLOCAL);
+            self.emit(id);
+        } else {
+            self.emitstring(op, var);
+        }
+    }
+
+    fn emitsetvar(&mut self, var: &str) {
+        if let Some(id) = self.findlocal(var) {
+            self.emitop(OpcodeType::OP_SETLOCAL);
+            self.emit(id);
+        } else {
+            /* an undeclared name is created in the scope of the call */
+            if !self.script {
+                self.scope = true;
+            }
+            self.emitstring(OpcodeType::OP_SETVAR, var);
+        }
+    }
+
     fn current(& self) -> usize {
         return self.code.len();
     }
@@ -228,9 +380,6 @@ impl VMFunction {
     }
 
     fn labelto(&mut self, addr:usize,  target_addr: usize) {
-        if target_addr > 0xFFFFFFFF {
-            panic!("current address is out of 4G!");
-        }
         self.code[addr] = (target_addr & 0xFFFF) as u16;
         self.code[addr+1] = ((target_addr >> 16) & 0xFFFF) as u16;
     }
@@ -353,32 +502,74 @@ impl VMFunction {
         }
     }
 
-    fn parsing_fundec(&mut self, lst: &AstNode) {
+    fn parsing_funname(&mut self, lst: &AstNode) {
+        if lst.is_list() {
+            for n in lst.iter() {
+                let n = if n.ast_type == AstType::STM_EXPORT && n.a().ast_type == AstType::AST_FUNDEC { n.a() } else { n };
+                if n.ast_type == AstType::AST_FUNDEC {
+                    self.addstring(n.a().str());
+                }
+            }
+        }
+    }
+
+    /*
+     * Params and vars which no inner function uses are kept in slots of the call, the others
+     * are looked up by name in the scope. A catch variable shadows the slot of the same name,
+     * so it stays in the scope too. The call creates arguments only when the code uses it, and
+     * the scope only when something is left in it.
+     */
+    fn resolve_locals(&mut self, params: &AstNode, body: &AstNode) {
+        let names = FuncNames::scan(None, Some(params), Some(body));
+        let mut captured: HashSet<&str> = HashSet::new();
+        for inner in names.inner.iter() {
+            outer_names(inner, &mut captured);
+        }
+
+        let mut locals = Vec::new();
+        for i in 0..(self.numparams + self.numvars) {
+            let var = self.str_tab[i].as_str();
+            locals.push(!captured.contains(var) && !names.catches.contains(var));
+        }
+        self.locals = locals;
+
+        self.arguments = names.used.contains("arguments")
+            && !self.str_tab[0..(self.numparams + self.numvars)].iter().any(|v| v == "arguments");
+        let named = match self.name {
+            Some(ref name) => names.used.contains(name.as_str()) || captured.contains(name.as_str()),
+            None => false,
+        };
+        /* an undeclared name set by the code makes it a scope too, see emitsetvar */
+        self.scope = self.arguments || named || self.locals.contains(&false);
+    }
+
+    fn parsing_fundec(&mut self, lst: &AstNode) -> Result<(), JsError> {
         if lst.is_list() {
             let it = lst.iter();
             for n in it {
+                /* exported declarations are hoisted too */
+                let n = if n.ast_type == AstType::STM_EXPORT && n.a().ast_type == AstType::AST_FUNDEC { n.a() } else { n };
                 if n.ast_type == AstType::AST_FUNDEC {
-                    let newfunc = compile_func( n.a(), n.b(), n.c(), false).unwrap();
+                    let newfunc = compile_func( n.a(), n.b(), n.c(), false)?;
                     let fid = self.addfunc(newfunc);
                     self.emitop(OpcodeType::OP_CLOSURE);
                     self.emit(fid);
 
                     let name = n.a().str();
-                    let vid = self.addstring( &name );
-                    self.emitop(OpcodeType::OP_SETVAR);
-                    self.emit(vid);
+                    self.emitsetvar(name);
                     self.emitop(OpcodeType::OP_POP);
                 }
             }
-            return;
+            return Ok(());
         }
+        return Ok(());
     }
 }
 
 /* Expressions */
-fn compile_object(f: &mut VMFunction, lst: &AstNode) {
+fn compile_object(f: &mut VMFunction, lst: &AstNode) -> Result<(), JsError> {
     if lst.is_null() {
-        return;
+        return Ok(());
     }
 
     let it = lst.iter();
@@ -394,7 +585,7 @@ fn compile_object(f: &mut VMFunction, lst: &AstNode) {
                 f.emitnumber(prop_number);
             },
             _ => {
-                panic!("invalid property name in object initializer");
+                return Err(compile_error(prop, "invalid property name in object initializer"));
             }
         }
 
@@ -404,106 +595,116 @@ fn compile_object(f: &mut VMFunction, lst: &AstNode) {
         */
         match kv.ast_type {
             AstType::EXP_PROP_VAL => {
-                compile_exp(f, kv.b());
+                compile_exp(f, kv.b())?;
                 f.emitop(OpcodeType::OP_INITPROP);
             },
             AstType::EXP_PROP_GET => {
                 let null = AstNode::null();
-                let func = compile_func( &null, &null, kv.c(), false).unwrap();
+                let func = compile_func( &null, &null, kv.c(), false)?;
                 f.emitfunction(func);
                 f.emitop(OpcodeType::OP_INITGETTER);                
             },
             AstType::EXP_PROP_SET => {
                 let null = AstNode::null();
-                let func = compile_func( &null, kv.b(), kv.c(), false).unwrap();
+                let func = compile_func( &null, kv.b(), kv.c(), false)?;
                 f.emitfunction(func);
                 f.emitop(OpcodeType::OP_INITSETTER);  
             },
             _ => {
-                panic!("invalid value type in object initializer");
+                return Err(compile_error(kv, "invalid value type in object initializer"));
             }
         }
     }
+    return Ok(());
 }
 
-fn compile_array(f: &mut VMFunction, lst: &AstNode) {
+fn compile_array(f: &mut VMFunction, lst: &AstNode) -> Result<(), JsError> {
     if lst.is_null() {
-        return;
+        return Ok(());
     }
-    let mut i:u16 = 0;
+    let mut i:usize = 0;
     let it = lst.iter();
     for n in it {
         if n.ast_type != AstType::EXP_UNDEF {
-            f.emitop(OpcodeType::OP_INTEGER);
-            f.emit(i);
-            compile_exp(f, n);
+            if i <= 0xFFFF {
+                f.emitop(OpcodeType::OP_INTEGER);
+                f.emit(i as u16);
+            } else {
+                f.emitnumber(i as f64);
+            }
+            compile_exp(f, n)?;
             f.emitop(OpcodeType::OP_INITPROP);
         }
         i = i + 1;
     }
+    return Ok(());
 }
 
-fn compile_delete(f: &mut VMFunction, exp: &AstNode) {
+fn compile_delete(f: &mut VMFunction, exp: &AstNode) -> Result<(), JsError> {
     let arg = exp.a();
     match arg.ast_type {
 
         AstType::EXP_INDEX => {
-            compile_exp(f, arg.a());
-            compile_exp(f, arg.b());
+            compile_exp(f, arg.a())?;
+            compile_exp(f, arg.b())?;
             f.emitop(OpcodeType::OP_DELPROP);
         },
         AstType::EXP_MEMBER => {
-            compile_exp(f, arg.a());
+            compile_exp(f, arg.a())?;
             let member_str = arg.b().str();
             f.emitstring(OpcodeType::OP_DELPROP_S, member_str);
         },
         AstType::EXP_IDENTIFIER => {
-            panic!("delete on an unqualified name is not allowed in strict mode");
+            return Err(compile_error(arg, "delete on an unqualified name is not allowed in strict mode"));
         },
         _ => {
-            panic!("invalid l-value in delete expression");
+            return Err(compile_error(arg, "invalid l-value in delete expression"));
         }
     }
+    return Ok(());
 }
 
-fn compile_typeof(f: &mut VMFunction, exp: &AstNode) {
+fn compile_typeof(f: &mut VMFunction, exp: &AstNode) -> Result<(), JsError> {
     if exp.a().ast_type == AstType::EXP_IDENTIFIER {
         let var_str = exp.a().str();
-        f.emitstring(OpcodeType::OP_HASVAR, var_str);
+        f.emitgetvar(OpcodeType::OP_HASVAR, var_str);
     } else {
-        compile_exp(f, exp.a());
+        compile_exp(f, exp.a())?;
     }
     f.emitop(OpcodeType::OP_TYPEOF);
+    return Ok(());
 }
 
-fn compile_unary(f: &mut VMFunction, exp: &AstNode, op: OpcodeType) {
-    compile_exp(f, exp.a());
+fn compile_unary(f: &mut VMFunction, exp: &AstNode, op: OpcodeType) -> Result<(), JsError> {
+    compile_exp(f, exp.a())?;
     f.emitop(op);
+    return Ok(());
 }
 
-fn compile_binary(f: &mut VMFunction, exp: &AstNode, op: OpcodeType) {
-    compile_exp(f, exp.a());
-    compile_exp(f, exp.b());
+fn compile_binary(f: &mut VMFunction, exp: &AstNode, op: OpcodeType) -> Result<(), JsError> {
+    compile_exp(f, exp.a())?;
+    compile_exp(f, exp.b())?;
     f.emitop(op);
+    return Ok(());
 }
 
-fn compile_assignop(f: &mut VMFunction, var: &AstNode, op: OpcodeType, is_post: bool) {
+fn compile_assignop(f: &mut VMFunction, var: &AstNode, op: OpcodeType, is_post: bool) -> Result<(), JsError> {
     match var.ast_type {
         AstType::EXP_IDENTIFIER => {
             let id_str = var.str();
-            f.emitstring(OpcodeType::OP_GETVAR, id_str);
+            f.emitgetvar(OpcodeType::OP_GETVAR, id_str);
             f.emitop(op);
             if is_post {
                 f.emitop(OpcodeType::OP_ROT2);
             }
-            f.emitstring(OpcodeType::OP_SETVAR, id_str);
+            f.emitsetvar(id_str);
             if is_post {
                 f.emitop(OpcodeType::OP_POP);
             }
         },
         AstType::EXP_INDEX => {
-            compile_exp(f, var.a());
-            compile_exp(f, var.b());
+            compile_exp(f, var.a())?;
+            compile_exp(f, var.b())?;
             f.emitop(OpcodeType::OP_DUP2);
             f.emitop(OpcodeType::OP_GETPROP);
             f.emitop(op);
@@ -516,133 +717,140 @@ fn compile_assignop(f: &mut VMFunction, var: &AstNode, op: OpcodeType, is_post:
             }
         },
         AstType::EXP_MEMBER => {
-            compile_exp(f, var.a());
+            compile_exp(f, var.a())?;
             f.emitop(OpcodeType::OP_DUP);
             let member_str = var.b().str();
-            f.emitstring(OpcodeType::OP_GETPROP_S, member_str);
+            f.emitprop(OpcodeType::OP_GETPROP_S, member_str);
             f.emitop(op);
             if is_post {
                 f.emitop(OpcodeType::OP_ROT3);
             }
-            f.emitstring(OpcodeType::OP_SETPROP_S, member_str);
+            f.emitprop(OpcodeType::OP_SETPROP_S, member_str);
             if is_post {
                 f.emitop(OpcodeType::OP_POP);
             }
         },
         _ => {
-            panic!("invalid l-value in assignment");
+            return Err(compile_error(var, "invalid l-value in assignment"));
         }
     }
+    return Ok(());
 }
 
-fn compile_assignwith(f: &mut VMFunction, exp: &AstNode, op: OpcodeType) {
+fn compile_assignwith(f: &mut VMFunction, exp: &AstNode, op: OpcodeType) -> Result<(), JsError> {
     let var = exp.a();
     let rhs = exp.b();
 
     match var.ast_type {
         AstType::EXP_IDENTIFIER => {
             let id_str = var.str();
-            f.emitstring(OpcodeType::OP_GETVAR, id_str);
-            compile_exp(f, rhs);
+            f.emitgetvar(OpcodeType::OP_GETVAR, id_str);
+            compile_exp(f, rhs)?;
             f.emitop(op);
-            f.emitstring(OpcodeType::OP_SETVAR, id_str);
+            f.emitsetvar(id_str);
         },
         AstType::EXP_INDEX => {
-            compile_exp(f, var.a());
-            compile_exp(f, var.b());
+            compile_exp(f, var.a())?;
+            compile_exp(f, var.b())?;
             f.emitop(OpcodeType::OP_DUP2);
             f.emitop(OpcodeType::OP_GETPROP);
-            compile_exp(f, rhs);
+            compile_exp(f, rhs)?;
             f.emitop(op);
             f.emitop(OpcodeType::OP_SETPROP);
         },
         AstType::EXP_MEMBER => {
-            compile_exp(f, var.a());
+            compile_exp(f, var.a())?;
             f.emitop(OpcodeType::OP_DUP);
             let member_str = var.b().str();
-            f.emitstring(OpcodeType::OP_GETPROP_S, member_str);
-            compile_exp(f, rhs);
+            f.emitprop(OpcodeType::OP_GETPROP_S, member_str);
+            compile_exp(f, rhs)?;
             f.emitop(op);
-            f.emitstring(OpcodeType::OP_SETPROP_S, member_str);
+            f.emitprop(OpcodeType::OP_SETPROP_S, member_str);
         },
         _ => {
-            panic!("invalid l-value in assignment");
+            return Err(compile_error(var, "invalid l-value in assignment"));
         }
     }
+    return Ok(());
 }
 
-fn compile_assign(f: &mut VMFunction, exp: &AstNode) {
+fn compile_assign(f: &mut VMFunction, exp: &AstNode) -> Result<(), JsError> {
     let var = exp.a();
     let rhs = exp.b();
 
     match var.ast_type {
         AstType::EXP_IDENTIFIER => {
             let id_str = var.str();
-            compile_exp(f, rhs);
-            f.emitstring(OpcodeType::OP_SETVAR, id_str);
+            compile_exp(f, rhs)?;
+            f.emitsetvar(id_str);
         },
         AstType::EXP_INDEX => {
-            compile_exp(f, var.a());
-            compile_exp(f, var.b());
-            compile_exp(f, rhs);
+            compile_exp(f, var.a())?;
+            compile_exp(f, var.b())?;
+            compile_exp(f, rhs)?;
             f.emitop(OpcodeType::OP_SETPROP);
         },
         AstType::EXP_MEMBER => {            
             let member_str = var.b().str();
-            compile_exp(f, var.a());
-            compile_exp(f, rhs);
-            f.emitstring(OpcodeType::OP_SETPROP_S, member_str);
+            compile_exp(f, var.a())?;
+            compile_exp(f, rhs)?;
+            f.emitprop(OpcodeType::OP_SETPROP_S, member_str);
         },
         _ => {
-            panic!("invalid l-value in assignment");
+            return Err(compile_error(var, "invalid l-value in assignment"));
         }
     }
+    return Ok(());
 }
 
-fn compile_args(f: &mut VMFunction, lst: &AstNode) -> u16 {
+fn compile_args(f: &mut VMFunction, lst: &AstNode) -> Result<u16, JsError> {
     if lst.is_null() {
-        return 0;
+        return Ok(0);
     }
-    let mut num:u16 = 0;
+    let mut num:usize = 0;
     let it = lst.iter();
     for n in it {
-        compile_exp(f, n);
+        compile_exp(f, n)?;
         num = num + 1;
     }
-    return num;
+    if num > 0xFFFF {
+        return Err(compile_error(lst, "too many arguments in function call"));
+    }
+    return Ok(num as u16);
 }
 
-fn compile_call(f: &mut VMFunction, exp: &AstNode) {
+fn compile_call(f: &mut VMFunction, exp: &AstNode) -> Result<(), JsError> {
     let fun = exp.a();
     let args = exp.b();
 
     match fun.ast_type {
         AstType::EXP_INDEX => {
-            compile_exp(f, fun.a());
+            compile_exp(f, fun.a())?;
             f.emitop(OpcodeType::OP_DUP);
-            compile_exp(f, fun.b());
+            compile_exp(f, fun.b())?;
             f.emitop(OpcodeType::OP_GETPROP);
             f.emitop(OpcodeType::OP_ROT2);
         },
         AstType::EXP_MEMBER => {
-            compile_exp(f, fun.a());
+            compile_exp(f, fun.a())?;
             f.emitop(OpcodeType::OP_DUP);
             let member = fun.b().str();
-            f.emitstring(OpcodeType::OP_GETPROP_S, member);            
+            f.emitprop(OpcodeType::OP_GETPROP_S, member);            
             f.emitop(OpcodeType::OP_ROT2);      // function object | this object
         },
         _ => {
-            compile_exp(f, fun);
+            compile_exp(f, fun)?;
             f.emitop(OpcodeType::OP_UNDEF);     // this object is UNDEFINED
         }
     }
 
-    let n = compile_args(f, args);
+    let n = compile_args(f, args)?;
     f.emitop(OpcodeType::OP_CALL);
     f.emit(n);
+    return Ok(());
 }
 
-fn compile_exp(f: &mut VMFunction, exp: &AstNode) {
+fn compile_exp(f: &mut VMFunction, exp: &AstNode) -> Result<(), JsError> {
     match exp.ast_type {
         /* immediately value*/ 
         AstType::EXP_STRING => {
@@ -672,233 +880,234 @@ fn compile_exp(f: &mut VMFunction, exp: &AstNode) {
         /* complex value*/
         AstType::EXP_OBJECT => {
             f.emitop(OpcodeType::OP_NEWOBJECT);
-            compile_object(f, exp.a());
+            compile_object(f, exp.a())?;
         },
 
         AstType::EXP_ARRAY => {
             f.emitop(OpcodeType::OP_NEWARRAY);
-            compile_array(f, exp.a());
+            compile_array(f, exp.a())?;
         },
 
         AstType::EXP_FUN => {
-            let func = compile_func( exp.a(), exp.b(), exp.c(), false).unwrap();
+            let func = compile_func( exp.a(), exp.b(), exp.c(), false)?;
             f.emitfunction(func);
         },
 
         AstType::EXP_VOID => {
-            compile_exp(f, exp.a());
+            compile_exp(f, exp.a())?;
             f.emitop(OpcodeType::OP_POP);
             f.emitop(OpcodeType::OP_UNDEF);
         }
 
         AstType::EXP_IDENTIFIER => {
             let var_string = exp.str();
-            f.emitstring(OpcodeType::OP_GETVAR, var_string);
+            f.emitgetvar(OpcodeType::OP_GETVAR, var_string);
         },
 
         AstType::EXP_INDEX => {
-            compile_exp(f, exp.a());
-            compile_exp(f, exp.b());
+            compile_exp(f, exp.a())?;
+            compile_exp(f, exp.b())?;
             f.emitop(OpcodeType::OP_GETPROP);
         },
 
         AstType::EXP_MEMBER => {
-            compile_exp(f, exp.a());
+            compile_exp(f, exp.a())?;
             let prop_str = exp.b().str();
-            f.emitstring(OpcodeType::OP_GETPROP_S, prop_str);
+            f.emitprop(OpcodeType::OP_GETPROP_S, prop_str);
         },
 
         AstType::EXP_CALL => {
-            compile_call(f, exp);
+            compile_call(f, exp)?;
         },
 
         AstType::EXP_NEW => {
-            compile_exp(f, exp.a());
-            let n = compile_args(f, exp.b());
+            compile_exp(f, exp.a())?;
+            let n = if exp.b.is_some() { compile_args(f, exp.b())? } else { 0 };
             f.emitop(OpcodeType::OP_NEW);
             f.emit(n);
         },
         
         // multiple exps 
         AstType::EXP_COMMA => {
-            compile_exp(f, exp.a());
+            compile_exp(f, exp.a())?;
             f.emitop(OpcodeType::OP_POP);
-            compile_exp(f, exp.b());
+            compile_exp(f, exp.b())?;
         },
         
         AstType::EXP_LOGOR => {
-            compile_exp(f, exp.a());
+            compile_exp(f, exp.a())?;
             f.emitop(OpcodeType::OP_DUP);
             let end = f.emitjump(OpcodeType::OP_JTRUE);
             f.emitop(OpcodeType::OP_POP);
-            compile_exp(f, exp.b());
+            compile_exp(f, exp.b())?;
             f.label_current_to(end);
         },
 
         AstType::EXP_LOGAND => {
-            compile_exp(f, exp.a());
+            compile_exp(f, exp.a())?;
             f.emitop(OpcodeType::OP_DUP);
             let end = f.emitjump(OpcodeType::OP_JFALSE);
             f.emitop(OpcodeType::OP_POP);
-            compile_exp(f, exp.b());
+            compile_exp(f, exp.b())?;
             f.label_current_to(end);
         },
         
         AstType::EXP_COND => {
-            compile_exp(f, exp.a());
+            compile_exp(f, exp.a())?;
             let then = f.emitjump(OpcodeType::OP_JTRUE);
-            compile_exp(f, exp.c());
+            compile_exp(f, exp.c())?;
             let end = f.emitjump(OpcodeType::OP_JUMP);
             f.label_current_to(then);
-            compile_exp(f, exp.b());
+            compile_exp(f, exp.b())?;
             f.label_current_to(end);
         },
 
         // Unary operation
         AstType::EXP_DELETE => {
-            compile_delete(f, exp);
+            compile_delete(f, exp)?;
         },
         AstType::EXP_PREINC => {
-            compile_assignop(f, exp.a(), OpcodeType::OP_INC, false);
+            compile_assignop(f, exp.a(), OpcodeType::OP_INC, false)?;
         },
         AstType::EXP_PREDEC => {
-            compile_assignop(f, exp.a(), OpcodeType::OP_DEC, false);
+            compile_assignop(f, exp.a(), OpcodeType::OP_DEC, false)?;
         },
         AstType::EXP_POSTINC => {
-            compile_assignop(f, exp.a(), OpcodeType::OP_POSTINC, true);
+            compile_assignop(f, exp.a(), OpcodeType::OP_POSTINC, true)?;
         },
         AstType::EXP_POSTDEC => {
-            compile_assignop(f, exp.a(), OpcodeType::OP_POSTDEC, true);
+            compile_assignop(f, exp.a(), OpcodeType::OP_POSTDEC, true)?;
         },
         AstType::EXP_TYPEOF => {
-            compile_typeof(f, exp);
+            compile_typeof(f, exp)?;
         },
         AstType::EXP_POS => {
-            compile_unary(f, exp,  OpcodeType::OP_POS);
+            compile_unary(f, exp,  OpcodeType::OP_POS)?;
         },
         AstType::EXP_NEG => {
-            compile_unary(f, exp,  OpcodeType::OP_NEG);
+            compile_unary(f, exp,  OpcodeType::OP_NEG)?;
         },
         AstType::EXP_BITNOT => {
-            compile_unary(f, exp,  OpcodeType::OP_BITNOT);
+            compile_unary(f, exp,  OpcodeType::OP_BITNOT)?;
         },
         AstType::EXP_LOGNOT => {
-            compile_unary(f, exp,  OpcodeType::OP_LOGNOT);
+            compile_unary(f, exp,  OpcodeType::OP_LOGNOT)?;
         },
 
         // Binary operation
         AstType::EXP_BITOR => {
-            compile_binary(f, exp,  OpcodeType::OP_BITOR);
+            compile_binary(f, exp,  OpcodeType::OP_BITOR)?;
         },
         AstType::EXP_BITXOR => {
-            compile_binary(f, exp,  OpcodeType::OP_BITXOR);
+            compile_binary(f, exp,  OpcodeType::OP_BITXOR)?;
         },
         AstType::EXP_BITAND => {
-            compile_binary(f, exp,  OpcodeType::OP_BITAND);
+            compile_binary(f, exp,  OpcodeType::OP_BITAND)?;
         },
         AstType::EXP_EQ => {
-            compile_binary(f, exp,  OpcodeType::OP_EQ);
+            compile_binary(f, exp,  OpcodeType::OP_EQ)?;
         },
         AstType::EXP_NE => {
-            compile_binary(f, exp,  OpcodeType::OP_NE);
+            compile_binary(f, exp,  OpcodeType::OP_NE)?;
         },
         AstType::EXP_STRICTEQ => {
-            compile_binary(f, exp,  OpcodeType::OP_STRICTEQ);
+            compile_binary(f, exp,  OpcodeType::OP_STRICTEQ)?;
         },
         AstType::EXP_STRICTNE => {
-            compile_binary(f, exp,  OpcodeType::OP_STRICTNE);
+            compile_binary(f, exp,  OpcodeType::OP_STRICTNE)?;
         },
         AstType::EXP_LT => {
-            compile_binary(f, exp,  OpcodeType::OP_LT);
+            compile_binary(f, exp,  OpcodeType::OP_LT)?;
         },
         AstType::EXP_GT => {
-            compile_binary(f, exp,  OpcodeType::OP_GT);
+            compile_binary(f, exp,  OpcodeType::OP_GT)?;
         },
         AstType::EXP_LE => {
-            compile_binary(f, exp,  OpcodeType::OP_LE);
+            compile_binary(f, exp,  OpcodeType::OP_LE)?;
         },
         AstType::EXP_GE => {
-            compile_binary(f, exp,  OpcodeType::OP_GE);
+            compile_binary(f, exp,  OpcodeType::OP_GE)?;
         },
         AstType::EXP_INSTANCEOF => {
-            compile_binary(f, exp,  OpcodeType::OP_INSTANCEOF);
+            compile_binary(f, exp,  OpcodeType::OP_INSTANCEOF)?;
         },
         AstType::EXP_IN => {
-            compile_binary(f, exp,  OpcodeType::OP_IN);
+            compile_binary(f, exp,  OpcodeType::OP_IN)?;
         },
         AstType::EXP_SHL => {
-            compile_binary(f, exp,  OpcodeType::OP_SHL);
+            compile_binary(f, exp,  OpcodeType::OP_SHL)?;
         },
         AstType::EXP_SHR => {
-            compile_binary(f, exp,  OpcodeType::OP_SHR);
+            compile_binary(f, exp,  OpcodeType::OP_SHR)?;
         },
         AstType::EXP_USHR => {
-            compile_binary(f, exp,  OpcodeType::OP_USHR);
+            compile_binary(f, exp,  OpcodeType::OP_USHR)?;
         },
         AstType::EXP_ADD => {
-            compile_binary(f, exp,  OpcodeType::OP_ADD);
+            compile_binary(f, exp,  OpcodeType::OP_ADD)?;
         },
         AstType::EXP_SUB => {
-            compile_binary(f, exp,  OpcodeType::OP_SUB);
+            compile_binary(f, exp,  OpcodeType::OP_SUB)?;
         },
         AstType::EXP_MUL => {
-            compile_binary(f, exp,  OpcodeType::OP_MUL);
+            compile_binary(f, exp,  OpcodeType::OP_MUL)?;
         },
         AstType::EXP_DIV => {
-            compile_binary(f, exp,  OpcodeType::OP_DIV);
+            compile_binary(f, exp,  OpcodeType::OP_DIV)?;
         },
         AstType::EXP_MOD => {
-            compile_binary(f, exp,  OpcodeType::OP_MOD);
+            compile_binary(f, exp,  OpcodeType::OP_MOD)?;
         },
 
         // assignments 
         AstType::EXP_ASS => {
-            compile_assign(f, exp);
+            compile_assign(f, exp)?;
         },
         AstType::EXP_ASS_MUL => {
-            compile_assignwith(f, exp, OpcodeType::OP_MUL);
+            compile_assignwith(f, exp, OpcodeType::OP_MUL)?;
         },
         AstType::EXP_ASS_DIV => {
-            compile_assignwith(f, exp, OpcodeType::OP_DIV);
+            compile_assignwith(f, exp, OpcodeType::OP_DIV)?;
         },
         AstType::EXP_ASS_MOD => {
-            compile_assignwith(f, exp, OpcodeType::OP_MOD);
+            compile_assignwith(f, exp, OpcodeType::OP_MOD)?;
         },
         AstType::EXP_ASS_ADD => {
-            compile_assignwith(f, exp, OpcodeType::OP_ADD);
+            compile_assignwith(f, exp, OpcodeType::OP_ADD)?;
         },
         AstType::EXP_ASS_SUB => {
-            compile_assignwith(f, exp, OpcodeType::OP_SUB);
+            compile_assignwith(f, exp, OpcodeType::OP_SUB)?;
         },
         AstType::EXP_ASS_SHL => {
-            compile_assignwith(f, exp, OpcodeType::OP_SHL);
+            compile_assignwith(f, exp, OpcodeType::OP_SHL)?;
         },
         AstType::EXP_ASS_SHR => {
-            compile_assignwith(f, exp, OpcodeType::OP_SHR);
+            compile_assignwith(f, exp, OpcodeType::OP_SHR)?;
         },
         AstType::EXP_ASS_USHR => {
-            compile_assignwith(f, exp, OpcodeType::OP_USHR);
+            compile_assignwith(f, exp, OpcodeType::OP_USHR)?;
         },
         AstType::EXP_ASS_BITAND => {
-            compile_assignwith(f, exp, OpcodeType::OP_BITAND);
+            compile_assignwith(f, exp, OpcodeType::OP_BITAND)?;
         },
         AstType::EXP_ASS_BITXOR => {
-            compile_assignwith(f, exp, OpcodeType::OP_BITXOR);
+            compile_assignwith(f, exp, OpcodeType::OP_BITXOR)?;
         },
         AstType::EXP_ASS_BITOR => {
-            compile_assignwith(f, exp, OpcodeType::OP_BITOR);
+            compile_assignwith(f, exp, OpcodeType::OP_BITOR)?;
         },
 
         _ => {
-            panic!("unknown expression: ({:?})", exp);
+            return Err(compile_error(exp, &format!("unknown expression: {:?}", exp.ast_type)));
         }
     }
+    return Ok(());
 }
 
 /* Emit code to rebalance stack and scopes during an abrupt exit */
-fn compile_exit(f: &mut VMFunction, scope_index: usize, jump_type: AstType) {
+fn compile_exit(f: &mut VMFunction, scope_index: usize, jump_type: AstType) -> Result<(), JsError> {
     if f.jumps.len() == 0 {
-        return;
+        return Ok(());
     }
     for i in (scope_index .. f.jumps.len()).rev() {
         let scope_type = f.jumps[i].scope.clone();
@@ -906,11 +1115,21 @@ fn compile_exit(f: &mut VMFunction, scope_index: usize, jump_type: AstType) {
             VMJumpScope::TryScope(stm_d) => {
                 f.emitop(OpcodeType::OP_ENDTRY);
                 if stm_d.is_some() {
-                    compile_stm(f, stm_d.as_ref().unwrap());
+                    compile_exit_finally(f, i, stm_d.as_ref().unwrap(), jump_type)?;
                 }
             },
-            VMJumpScope::CatchScope => {
+            VMJumpScope::CatchScope(stm_d) => {
                 f.emitop(OpcodeType::OP_ENDCATCH);
+                if stm_d.is_some() {
+                    compile_exit_finally(f, i, stm_d.as_ref().unwrap(), jump_type)?;
+                }
+            },
+            VMJumpScope::FinallyValue => {
+                if jump_type == AstType::STM_RETURN {
+                    /* drop the waiting value, save the value of this return */
+                    f.emitop(OpcodeType::OP_ROT2);
+                }
+                f.emitop(OpcodeType::OP_POP);
             },
             VMJumpScope::ForInLoop => {
                 if jump_type == AstType::STM_BREAK {
@@ -926,7 +1145,7 @@ fn compile_exit(f: &mut VMFunction, scope_index: usize, jump_type: AstType) {
                     f.emitop(OpcodeType::OP_ROT2);
                     f.emitop(OpcodeType::OP_POP);
                 } else {
-                    panic!("compile_exit error: only break/continue/return supported!");
+                    return Err(JsError::compile(0, "compile_exit error: only break/continue/return supported!".to_string()));
                 }
             },
             _ => {
@@ -934,95 +1153,135 @@ fn compile_exit(f: &mut VMFunction, scope_index: usize, jump_type: AstType) {
             }
         }
     }
+    return Ok(());
+}
+
+/* finally blocks are copied to every way out of their try, copies in nested ones multiply,
+   a function copying more code than this is rejected before its compiling takes forever */
+const JS_FINALLY_CODE_LIMIT: usize = 1 << 20;
+
+fn compile_finally_copy(f: &mut VMFunction, finally_block: &AstNode) -> Result<(), JsError> {
+    let start = f.code.len();
+    compile_stm(f, finally_block)?;
+    f.finally_code = f.finally_code + f.code.len() - start;
+    if f.finally_code > JS_FINALLY_CODE_LIMIT {
+        return Err(compile_error(finally_block, "finally blocks are nested too deeply"));
+    }
+    return Ok(());
+}
+
+/* The finally block inlined by an exit is out of its try, so a return or break in it
+   only exits the scopes around that try, and doesn't run the same finally block again.
+   The value of a return waits on the stack meanwhile, like the exception in a handler. */
+fn compile_exit_finally(f: &mut VMFunction, scope_index: usize, finally_block: &AstNode, jump_type: AstType) -> Result<(), JsError> {
+    let inner = f.jumps.split_off(scope_index);
+    if jump_type == AstType::STM_RETURN {
+        f.new_scope(VMJumpScope::FinallyValue);
+    }
+    let result = compile_finally_copy(f, finally_block);
+    f.jumps.truncate(scope_index);
+    f.jumps.extend(inner);
+    result?;
+    return Ok(());
 }
 
 /* Try/catch/finally */
-fn compile_trycatchfinally(f: &mut VMFunction, try_block: &AstNode, catch_var: &AstNode, catch_block: &AstNode, finally_block: &AstNode) {
+fn compile_trycatchfinally(f: &mut VMFunction, try_block: &AstNode, catch_var: &AstNode, catch_block: &AstNode, finally_block: &AstNode) -> Result<(), JsError> {
     let l1:usize;
     let l2:usize;
     let l3:usize;
 
-    f.new_scope(VMJumpScope::TryScope(Some(finally_block.clone())));
     l1 = f.emitjump(OpcodeType::OP_TRY);
     {
-        /* if we get here, we have caught an exception in the try block */
+        /* if we get here, we have caught an exception in the try block,
+           it's bound before the second try so that try keeps the height of the stack without it */
+        let catchvar = catch_var.str();
+        f.emitstring(OpcodeType::OP_CATCH, catchvar);
         l2 = f.emitjump(OpcodeType::OP_TRY);
         {
             /* if we get here, we have caught an exception in the catch block */
-            compile_stm(f, finally_block);  /* inline finally block */
+            f.emitop(OpcodeType::OP_ENDCATCH);
+            f.new_scope(VMJumpScope::FinallyValue);
+            compile_finally_copy(f, finally_block)?;  /* inline finally block */
+            f.delete_scope();
             f.emitop(OpcodeType::OP_THROW);
         }
         f.label_current_to(l2);
 
-        let catchvar = catch_var.str();
-        f.new_scope(VMJumpScope::CatchScope);
-        {
-            f.emitstring(OpcodeType::OP_CATCH, catchvar);
-            compile_stm(f, catch_block);
-            f.emitop(OpcodeType::OP_ENDCATCH);
-        }
+        /* the catch block runs inside the second try, the finally block runs out of the catch scope */
+        f.new_scope(VMJumpScope::CatchScope(Some(finally_block.clone())));
+        f.new_scope(VMJumpScope::TryScope(None));
+        compile_stm(f, catch_block)?;
+        f.emitop(OpcodeType::OP_ENDTRY);
         f.delete_scope();
 
-        f.emitop(OpcodeType::OP_ENDTRY);
+        f.emitop(OpcodeType::OP_ENDCATCH);
+        f.delete_scope();
         l3 = f.emitjump(OpcodeType::OP_JUMP);
     }
     f.label_current_to(l1);
-    compile_stm(f, try_block);
+    f.new_scope(VMJumpScope::TryScope(Some(finally_block.clone())));
+    compile_stm(f, try_block)?;
     f.emitop(OpcodeType::OP_ENDTRY);
     f.delete_scope();
 
     f.label_current_to(l3);
-    compile_stm(f, finally_block);
-} 
+    compile_finally_copy(f, finally_block)?;
+    return Ok(());
+}
 
-fn compile_trycatch(f: &mut VMFunction, a: &AstNode, b: &AstNode, c: &AstNode) {
+fn compile_trycatch(f: &mut VMFunction, a: &AstNode, b: &AstNode, c: &AstNode) -> Result<(), JsError> {
     let l1:usize;
     let l2:usize;
 
-    f.new_scope(VMJumpScope::TryScope(None));
     l1 = f.emitjump(OpcodeType::OP_TRY);
     {
         /* if we get here, we have caught an exception in the try block */
         let catchvar = b.str();
-        f.new_scope(VMJumpScope::CatchScope);
+        f.new_scope(VMJumpScope::CatchScope(None));
         {
             f.emitstring(OpcodeType::OP_CATCH, catchvar);
-            compile_stm(f, c);
+            compile_stm(f, c)?;
             f.emitop(OpcodeType::OP_ENDCATCH);
         }
         f.delete_scope();
         l2 = f.emitjump(OpcodeType::OP_JUMP);
     }
     f.label_current_to(l1);
-    compile_stm(f, a);
+    f.new_scope(VMJumpScope::TryScope(None));
+    compile_stm(f, a)?;
     f.emitop(OpcodeType::OP_ENDTRY);
     f.delete_scope();    
     f.label_current_to(l2);
+    return Ok(());
 }
 
-fn compile_finally(f: &mut VMFunction, a: &AstNode, b: &AstNode) {
+fn compile_finally(f: &mut VMFunction, a: &AstNode, b: &AstNode) -> Result<(), JsError> {
     let l1:usize;
 
     l1 = f.emitjump(OpcodeType::OP_TRY);
-    f.new_scope(VMJumpScope::TryScope(Some(b.clone())));
     {
         /* if we get here, we have caught an exception in the try block */
-        compile_stm(f, b);
+        f.new_scope(VMJumpScope::FinallyValue);
+        compile_finally_copy(f, b)?;
+        f.delete_scope();
         f.emitop(OpcodeType::OP_THROW);
     }
     f.label_current_to(l1);
-    compile_stm(f, a);
+    f.new_scope(VMJumpScope::TryScope(Some(b.clone())));
+    compile_stm(f, a)?;
     f.emitop(OpcodeType::OP_ENDTRY);
     f.delete_scope();
 
-    compile_stm(f, b);
-} 
+    compile_finally_copy(f, b)?;
+    return Ok(());
+}
 
 /* Switch */
-fn compile_switch(f: &mut VMFunction, stm: &AstNode) {
+fn compile_switch(f: &mut VMFunction, stm: &AstNode) -> Result<(), JsError> {
     let mut def = None;
 
-    compile_exp(f, stm.a());
+    compile_exp(f, stm.a())?;
 
     let mut case_jumps = Vec::new();
 
@@ -1030,17 +1289,16 @@ fn compile_switch(f: &mut VMFunction, stm: &AstNode) {
         let it = stm.b().iter();
         for clause in it {            
             if clause.ast_type == AstType::STM_CASE {
-                compile_exp(f, clause.a());                
+                compile_exp(f, clause.a())?;                
                 let addr = f.emitjump(OpcodeType::OP_JCASE);
                 case_jumps.push(addr);
             } else if clause.ast_type == AstType::STM_DEFAULT {
                 if !def.is_none() {
-                    panic!("more than one default label in switch");
+                    return Err(compile_error(clause, "more than one default label in switch"));
                 }
                 def = Some(clause);
             } else {
-                panic!("Case list only support STM_CASE and STM_DEFAULT!");
-
+                return Err(compile_error(clause, "Case list only support STM_CASE and STM_DEFAULT!"));
             }
         }
     }
@@ -1056,11 +1314,11 @@ fn compile_switch(f: &mut VMFunction, stm: &AstNode) {
             if clause.ast_type == AstType::STM_CASE {
                 let addr = case_jumps[i];
                 f.label_current_to(addr);
-                compile_stmlist(f, clause.b());
+                compile_stmlist(f, clause.b())?;
                 i = i + 1;
             } else if clause.ast_type == AstType::STM_DEFAULT {
                 f.label_current_to(last_jump);
-                compile_stmlist(f, clause.a());
+                compile_stmlist(f, clause.a())?;
             }
         }
     }
@@ -1068,72 +1326,136 @@ fn compile_switch(f: &mut VMFunction, stm: &AstNode) {
     if def.is_none() {
         f.label_current_to(last_jump);
     }
+    return Ok(());
 }
 
 /* Statements */
-fn compile_varinit(f: &mut VMFunction, lst: &AstNode) {
+fn compile_varinit(f: &mut VMFunction, lst: &AstNode) -> Result<(), JsError> {
     let it = lst.iter();
     for n in it {
         if n.has_b() {
-            compile_exp(f, n.b());
+            compile_exp(f, n.b())?;
             let var_str = n.a().str();
-            f.emitstring(OpcodeType::OP_SETVAR, var_str); 
+            f.emitsetvar(var_str);
             f.emitop(OpcodeType::OP_POP);
         }
     }
+    return Ok(());
 }
 
-fn compile_assignforin(f: &mut VMFunction, stm: &AstNode) {
+fn compile_assignforin(f: &mut VMFunction, stm: &AstNode) -> Result<(), JsError> {
     let lhs = stm.a();
     if stm.ast_type == AstType::STM_FOR_IN_VAR {
         if !lhs.is_list() {
-            panic!("for var in statement must include an var list!");
+            return Err(compile_error(stm, "for var in statement must include an var list!"));
         }
         if lhs.has_b() {
-            panic!("more than one loop variable in for-in statement");
+            return Err(compile_error(stm, "more than one loop variable in for-in statement"));
         }
         let var = lhs.a().a().str();    /* list(var-init(ident)) */
-        f.emitstring(OpcodeType::OP_SETVAR, var);
+        f.emitsetvar(var);
         f.emitop(OpcodeType::OP_POP);
-        return;
+        return Ok(());
     }
 
     if lhs.ast_type != AstType::EXP_IDENTIFIER {
-        panic!("invalid l-value in for-in loop assignment");
+        return Err(compile_error(stm, "invalid l-value in for-in loop assignment"));
     }
 
     let var = lhs.str();
-    f.emitstring(OpcodeType::OP_SETVAR, var);
+    f.emitsetvar(var);
     f.emitop(OpcodeType::OP_POP);
-    return;
+    return Ok(());
+}
+
+/* Modules, imports are bound by the linker before the module runs, so they compile to nothing */
+const MODULE_DEFAULT_LOCAL: &str = "*default*";
+
+fn compile_import(f: &mut VMFunction, stm: &AstNode) -> Result<(), JsError> {
+    let specifier = stm.a().str();
+    if stm.b().is_null() {
+        f.imports.push(VMModuleBinding {
+            specifier: specifier.to_string(),
+            name: String::new(),
+            local: String::new(),
+        });
+        return Ok(());
+    }
+    for spec in stm.b().iter() {
+        let local = spec.b().str();
+        if f.imports.iter().any(|i| i.local == local) {
+            return Err(compile_error(spec, &format!("duplicate import binding '{}'", local)));
+        }
+        f.imports.push(VMModuleBinding {
+            specifier: specifier.to_string(),
+            name: spec.a().str().to_string(),
+            local: local.to_string(),
+        });
+    }
+    return Ok(());
+}
+
+fn add_export(f: &mut VMFunction, node: &AstNode, name: &str, local: &str) -> Result<(), JsError> {
+    if f.exports.iter().any(|e| e.name == name) {
+        return Err(compile_error(node, &format!("duplicate export '{}'", name)));
+    }
+    f.exports.push(VMModuleBinding {
+        specifier: String::new(),
+        name: name.to_string(),
+        local: local.to_string(),
+    });
+    return Ok(());
+}
+
+fn compile_export(f: &mut VMFunction, stm: &AstNode) -> Result<(), JsError> {
+    let decl = stm.a();
+    if !stm.b().is_null() {
+        /* a declared function is hoisted, as in export default function f() {} */
+        for spec in stm.b().iter() {
+            add_export(f, spec, spec.b().str(), spec.a().str())?;
+        }
+        return Ok(());
+    }
+
+    if decl.ast_type == AstType::AST_FUNDEC {
+        let name = decl.a().str();
+        add_export(f, stm, name, name)?;
+    } else if decl.ast_type == AstType::STM_VAR {
+        compile_stm(f, decl)?;
+        for n in decl.a().iter() {
+            let name = n.a().str();
+            add_export(f, n, name, name)?;
+        }
+    }
+    return Ok(());
 }
 
-fn compile_stm(f: &mut VMFunction, stm: &AstNode) -> bool {
+fn compile_stm(f: &mut VMFunction, stm: &AstNode) -> Result<bool, JsError> {
     match stm.ast_type {
         AstType::STM_BLOCK => {
             let block = stm.a.as_ref().unwrap();
-            compile_stmlist(f, block);
+            compile_stmlist(f, block)?;
         },
         AstType::STM_EMPTY => {
             // do nothing
         },
         AstType::STM_VAR => {            
             assert!( stm.a().ast_type == AstType::AST_LIST);
-            compile_varinit(f, stm.a());
+            compile_varinit(f, stm.a())?;
         },
         AstType::STM_IF => {
             if stm.c.is_some() {
-                compile_exp(f, stm.a.as_ref().unwrap());
+                compile_exp(f, stm.a.as_ref().unwrap())?;
                 let then = f.emitjump(OpcodeType::OP_JTRUE);
-                compile_stm(f, stm.c.as_ref().unwrap());
+                compile_stm(f, stm.c.as_ref().unwrap())?;
                 let end = f.emitjump(OpcodeType::OP_JUMP);
                 f.label_current_to(then);
-                compile_stm(f, stm.b.as_ref().unwrap());
+                compile_stm(f, stm.b.as_ref().unwrap())?;
                 f.label_current_to(end);
             } else {
-                compile_exp(f, stm.a.as_ref().unwrap());
+                compile_exp(f, stm.a.as_ref().unwrap())?;
                 let end = f.emitjump(OpcodeType::OP_JFALSE);
-                compile_stm(f, stm.b.as_ref().unwrap());
+                compile_stm(f, stm.b.as_ref().unwrap())?;
                 f.label_current_to(end);
             }
         },
@@ -1141,9 +1463,9 @@ fn compile_stm(f: &mut VMFunction, stm: &AstNode) -> bool {
             f.new_scope(VMJumpScope::DoLoop);
     
             let lop = f.current();
-            compile_stm(f, stm.a.as_ref().unwrap());
+            compile_stm(f, stm.a.as_ref().unwrap())?;
             let cont = f.current();
-            compile_exp(f, stm.b.as_ref().unwrap());
+            compile_exp(f, stm.b.as_ref().unwrap())?;
             f.emitjumpto(OpcodeType::OP_JTRUE, lop);
             
             f.fill_jumps(f.current(), cont);
@@ -1154,9 +1476,9 @@ fn compile_stm(f: &mut VMFunction, stm: &AstNode) -> bool {
             f.new_scope(VMJumpScope::WhileLoop);
 
             let lop = f.current();
-            compile_exp(f, stm.a());
+            compile_exp(f, stm.a())?;
             let end = f.emitjump(OpcodeType::OP_JFALSE);
-            compile_stm(f, stm.b());
+            compile_stm(f, stm.b())?;
             f.emitjumpto(OpcodeType::OP_JUMP, lop);
             f.label_current_to(end);
 
@@ -1168,11 +1490,11 @@ fn compile_stm(f: &mut VMFunction, stm: &AstNode) -> bool {
             f.new_scope(VMJumpScope::ForLoop);
 
             if stm.ast_type == AstType::STM_FOR_VAR {
-                compile_varinit(f, stm.a());
+                compile_varinit(f, stm.a())?;
             } else {       
                 let a = stm.a();
                 if ! a.is_null() {
-                    compile_exp(f, a);
+                    compile_exp(f, a)?;
                     f.emitop(OpcodeType::OP_POP);
                 }
             }
@@ -1180,18 +1502,18 @@ fn compile_stm(f: &mut VMFunction, stm: &AstNode) -> bool {
             let lop = f.current();
             let b = stm.b();
             let end = if ! b.is_null() {
-                compile_exp(f, b);
+                compile_exp(f, b)?;
                 f.emitjump(OpcodeType::OP_JFALSE)
             } else {
                 0
             };
 
-            compile_stm(f, stm.d.as_ref().unwrap());
+            compile_stm(f, stm.d.as_ref().unwrap())?;
 
             let cont = f.current();
             let c = stm.c();
             if !c.is_null() {
-                compile_exp(f, c);
+                compile_exp(f, c)?;
                 f.emitop(OpcodeType::OP_POP);
             }
             f.emitjumpto(OpcodeType::OP_JUMP, lop);
@@ -1207,15 +1529,15 @@ fn compile_stm(f: &mut VMFunction, stm: &AstNode) -> bool {
         AstType::STM_FOR_IN |  AstType::STM_FOR_IN_VAR => {
             f.new_scope(VMJumpScope::ForInLoop);
 
-            compile_exp(f, stm.b());
+            compile_exp(f, stm.b())?;
             f.emitop(OpcodeType::OP_ITERATOR);
             let lop = f.current();
             
             f.emitop(OpcodeType::OP_NEXTITER);
             let end = f.emitjump(OpcodeType::OP_JFALSE);
-            compile_assignforin(f, stm);
+            compile_assignforin(f, stm)?;
 
-            compile_stm(f, stm.c.as_ref().unwrap());
+            compile_stm(f, stm.c.as_ref().unwrap())?;
             
             f.emitjumpto(OpcodeType::OP_JUMP, lop);
             f.label_current_to(end);
@@ -1226,7 +1548,7 @@ fn compile_stm(f: &mut VMFunction, stm: &AstNode) -> bool {
         
         AstType::STM_SWITCH => {
             f.new_scope(VMJumpScope::SwitchScope);
-            compile_switch(f, stm);
+            compile_switch(f, stm)?;
             f.fill_jumps(f.current(), f.current());
             f.delete_scope();
         },
@@ -1235,7 +1557,7 @@ fn compile_stm(f: &mut VMFunction, stm: &AstNode) -> bool {
             let a = stm.a.as_ref().unwrap();
             f.new_scope(VMJumpScope::LabelSection(a.str().to_string()));           
             
-            compile_stm(f, stm.b.as_ref().unwrap());
+            compile_stm(f, stm.b.as_ref().unwrap())?;
             
             f.fill_jumps(f.current(), f.current());
             f.delete_scope();
@@ -1252,10 +1574,10 @@ fn compile_stm(f: &mut VMFunction, stm: &AstNode) -> bool {
                 break_scope = f.target_break_scope();
             }
             if break_scope == 0 {
-                panic!("Can't find break target!");
+                return Err(compile_error(stm, "Can't find break target!"));
             }
             
-            compile_exit(f, break_scope - 1, AstType::STM_BREAK);
+            compile_exit(f, break_scope - 1, AstType::STM_BREAK)?;
             let from = f.emitjump(OpcodeType::OP_JUMP);
             let jump = VMJumpType::BreakJump(from);
             f.add_jump(break_scope - 1, jump);
@@ -1272,10 +1594,10 @@ fn compile_stm(f: &mut VMFunction, stm: &AstNode) -> bool {
                 continue_scope = f.target_continue_scope();
             }
             if continue_scope == 0 {
-                panic!("Can't find continue target!");                
+                return Err(compile_error(stm, "Can't find continue target!"));
             }
 
-            compile_exit(f, continue_scope - 1, AstType::STM_CONTINUE);
+            compile_exit(f, continue_scope - 1, AstType::STM_CONTINUE)?;
             let from = f.emitjump(OpcodeType::OP_JUMP);
             let jump = VMJumpType::ContinueJump(from);
             f.add_jump(continue_scope - 1, jump);
@@ -1283,34 +1605,34 @@ fn compile_stm(f: &mut VMFunction, stm: &AstNode) -> bool {
         
         AstType::STM_RETURN => {
             if f.script {
-                panic!("Find return in script code!");
+                return Err(compile_error(stm, "Find return in script code!"));
             }
 
             let a = stm.a.as_ref().unwrap();
             if a.is_null() {
                 f.emitop(OpcodeType::OP_UNDEF);
             } else {
-                compile_exp(f, a);
+                compile_exp(f, a)?;
             }
             
-            compile_exit(f, 0, AstType::STM_RETURN);
+            compile_exit(f, 0, AstType::STM_RETURN)?;
             f.emitop(OpcodeType::OP_RETURN);
         },
 
         AstType::STM_THROW => {
-            compile_exp(f, stm.a.as_ref().unwrap());
+            compile_exp(f, stm.a.as_ref().unwrap())?;
             f.emitop(OpcodeType::OP_THROW);
         },
 
         AstType::STM_TRY => {
             if stm.has_b() && stm.has_c() {
                 if stm.has_d() {
-                    compile_trycatchfinally(f, stm.a(), stm.b(), stm.c(), stm.d());
+                    compile_trycatchfinally(f, stm.a(), stm.b(), stm.c(), stm.d())?;
                 } else {
-                    compile_trycatch(f, stm.a(), stm.b(), stm.c());
+                    compile_trycatch(f, stm.a(), stm.b(), stm.c())?;
                 }
             } else {
-                compile_finally(f, stm.a(), stm.b()); 
+                compile_finally(f, stm.a(), stm.b())?; 
             }
         },
 
@@ -1322,44 +1644,62 @@ fn compile_stm(f: &mut VMFunction, stm: &AstNode) -> bool {
             // just skip
         },
 
+        AstType::STM_IMPORT => {
+            compile_import(f, stm)?;
+        },
+        AstType::STM_EXPORT => {
+            compile_export(f, stm)?;
+        },
+        AstType::STM_EXPORT_DEFAULT => {
+            compile_exp(f, stm.a())?;
+            f.emitstring(OpcodeType::OP_SETVAR, MODULE_DEFAULT_LOCAL);
+            f.emitop(OpcodeType::OP_POP);
+            add_export(f, stm, "default", MODULE_DEFAULT_LOCAL)?;
+        },
+
         _ => {
-            compile_exp(f, stm);
+            compile_exp(f, stm)?;
             f.emitop(OpcodeType::OP_POP);
-            return true;
+            return Ok(true);
         }    
     }
 
-    return false;
+    return Ok(false);
 }
 
-fn compile_stmlist(f: &mut VMFunction, lst: &AstNode) -> bool {
+fn compile_stmlist(f: &mut VMFunction, lst: &AstNode) -> Result<bool, JsError> {
     if lst.is_null() {
-        return false;
+        return Ok(false);
     }
     let mut last_stm = false;
     for stm in lst.iter() {
-        last_stm = compile_stm(f, stm);
+        last_stm = compile_stm(f, stm)?;
     }
-    return last_stm;
+    return Ok(last_stm);
 }
 
-pub fn compile_func(name: &AstNode, params: &AstNode, body: &AstNode, script: bool) -> Result<VMFunction, String> {
+pub fn compile_func(name: &AstNode, params: &AstNode, body: &AstNode, script: bool) -> Result<VMFunction, JsError> {
     let mut f = VMFunction::new(script);
 
     // parsing params
     if !params.is_null() {
         f.numparams = params.len();
-        let it = params.iter();
-        for node in it {
-            let name = node.str();
-            f.addstring(name);
+        let names: Vec<&str> = params.iter().map(|node| node.str()).collect();
+        for (i, name) in names.iter().enumerate() {
+            /* a repeated parameter is bound to its last position, the earlier ones get names no code can use */
+            if names[i + 1..].contains(name) {
+                f.str_tab.push(format!("#{}", i));
+            } else {
+                f.addstring(name);
+            }
         }
     }
 
     if !body.is_null() {
 		f.parsing_vardec(body);
+        /* declared functions are variables of the function too */
+        f.parsing_funname(body);
         f.numvars = f.str_tab.len() - f.numparams;
-		f.parsing_fundec(body);
     }
 
     if !name.is_null() {
@@ -1370,8 +1710,15 @@ pub fn compile_func(name: &AstNode, params: &AstNode, body: &AstNode, script: bo
         }
     }
 
+    if !script {
+        f.resolve_locals(params, body);
+    }
+    if !body.is_null() {
+		f.parsing_fundec(body)?;
+    }
+
     if f.script {
-        let ret = compile_stmlist(&mut f, body);
+        let ret = compile_stmlist(&mut f, body)?;
         if ret == true {
             // removed pop op, return last value of compile_exp();
             f.drain(1);
@@ -1381,10 +1728,18 @@ pub fn compile_func(name: &AstNode, params: &AstNode, body: &AstNode, script: bo
             f.emitop(OpcodeType::OP_RETURN);
         }
     } else {
-        compile_stmlist(&mut f, body);
+        compile_stmlist(&mut f, body)?;
         f.emitop(OpcodeType::OP_UNDEF);
         f.emitop(OpcodeType::OP_RETURN);
     }
 
+    // operands of opcode are u16 index of tables, and jump addresses are 32bit
+    if f.num_tab.len() > 0x10000 || f.str_tab.len() > 0x10000 || f.func_tab.len() > 0x10000 || f.prop_tab.len() > 0x10000 {
+        return Err(compile_error(body, "too many constants in function"));
+    }
+    if f.code.len() > 0xFFFFFFFF {
+        return Err(compile_error(body, "function is too large"));
+    }
+
     return Ok(f);
 }
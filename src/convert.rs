@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use crate::error::*;
+use crate::value::*;
+use crate::runtime::*;
+
+/* conversion from a JavaScript value to a Rust value, a value with wrong type is a TypeError */
+pub trait FromJs: Sized {
+	fn from_js<T: Hookable>(rt: &JsRuntime<T>, jv: &SharedValue) -> Result<Self, JsError>;
+}
+
+/* conversion from a Rust value to a JavaScript value */
+pub trait IntoJs {
+	fn into_js<T: Hookable>(self, rt: &JsRuntime<T>) -> SharedValue;
+}
+
+/* conversion from the error of a host method, a message is thrown as a TypeError */
+pub trait IntoJsError {
+	fn into_js_error<T: Hookable>(self, rt: &JsRuntime<T>) -> JsError;
+}
+
+impl IntoJsError for JsError {
+	fn into_js_error<T: Hookable>(self, _rt: &JsRuntime<T>) -> JsError {
+		return self;
+	}
+}
+impl IntoJsError for String {
+	fn into_js_error<T: Hookable>(self, rt: &JsRuntime<T>) -> JsError {
+		return rt.new_type_error(self);
+	}
+}
+impl IntoJsError for &str {
+	fn into_js_error<T: Hookable>(self, rt: &JsRuntime<T>) -> JsError {
+		return rt.new_type_error(self.to_string());
+	}
+}
+
+fn type_error<T: Hookable>(rt: &JsRuntime<T>, expected: &str, jv: &SharedValue) -> JsError {
+	return rt.new_type_error(format!("expected {} but got {}", expected, jv.type_string()));
+}
+
+/* helpers for host functions, arguments are read with FromJs and results are pushed with IntoJs */
+impl<T: Hookable> JsRuntime<T> {
+	pub fn arg<A: FromJs>(&self, argc: usize, index: usize) -> Result<A, JsError> {
+		if index >= argc {
+			return A::from_js(self, &SharedValue::new_undefined());
+		}
+		let jv = self.top(index as isize - argc as isize);
+		return A::from_js(self, &jv);
+	}
+
+	pub fn push_value<V: IntoJs>(&mut self, v: V) {
+		let jv = v.into_js(self);
+		self.push(jv);
+	}
+}
+
+impl FromJs for SharedValue {
+	fn from_js<T: Hookable>(_rt: &JsRuntime<T>, jv: &SharedValue) -> Result<Self, JsError> {
+		return Ok(jv.clone());
+	}
+}
+impl IntoJs for SharedValue {
+	fn into_js<T: Hookable>(self, _rt: &JsRuntime<T>) -> SharedValue {
+		return self;
+	}
+}
+
+impl IntoJs for () {
+	fn into_js<T: Hookable>(self, _rt: &JsRuntime<T>) -> SharedValue {
+		return SharedValue::new_undefined();
+	}
+}
+
+impl FromJs for bool {
+	fn from_js<T: Hookable>(rt: &JsRuntime<T>, jv: &SharedValue) -> Result<Self, JsError> {
+		if !jv.is_boolean() {
+			return Err(type_error(rt, "boolean", jv));
+		}
+		return Ok(jv.to_boolean());
+	}
+}
+impl IntoJs for bool {
+	fn into_js<T: Hookable>(self, _rt: &JsRuntime<T>) -> SharedValue {
+		return SharedValue::new_boolean(self);
+	}
+}
+
+macro_rules! convert_float {
+	($t:ty) => {
+		impl FromJs for $t {
+			fn from_js<T: Hookable>(rt: &JsRuntime<T>, jv: &SharedValue) -> Result<Self, JsError> {
+				if !jv.is_number() {
+					return Err(type_error(rt, "number", jv));
+				}
+				return Ok(jv.to_number() as $t);
+			}
+		}
+		impl IntoJs for $t {
+			fn into_js<T: Hookable>(self, _rt: &JsRuntime<T>) -> SharedValue {
+				return SharedValue::new_number(self as f64);
+			}
+		}
+	}
+}
+convert_float!(f32);
+convert_float!(f64);
+
+/* integers only accept numbers without fraction inside the range of the type */
+macro_rules! convert_integer {
+	($t:ty) => {
+		impl FromJs for $t {
+			fn from_js<T: Hookable>(rt: &JsRuntime<T>, jv: &SharedValue) -> Result<Self, JsError> {
+				if !jv.is_number() {
+					return Err(type_error(rt, stringify!($t), jv));
+				}
+				let v = jv.to_number();
+				if v.fract() != 0.0 || v < (<$t>::MIN as f64) || v >= (<$t>::MAX as f64) + 1.0 {
+					return Err(rt.new_type_error(format!("{} can not be converted to {}", v, stringify!($t))));
+				}
+				return Ok(v as $t);
+			}
+		}
+		impl IntoJs for $t {
+			fn into_js<T: Hookable>(self, _rt: &JsRuntime<T>) -> SharedValue {
+				return SharedValue::new_number(self as f64);
+			}
+		}
+	}
+}
+convert_integer!(i8);
+convert_integer!(i16);
+convert_integer!(i32);
+convert_integer!(i64);
+convert_integer!(isize);
+convert_integer!(u8);
+convert_integer!(u16);
+convert_integer!(u32);
+convert_integer!(u64);
+convert_integer!(usize);
+
+impl FromJs for String {
+	fn from_js<T: Hookable>(rt: &JsRuntime<T>, jv: &SharedValue) -> Result<Self, JsError> {
+		if !jv.is_string() {
+			return Err(type_error(rt, "string", jv));
+		}
+		return Ok(jv.to_string());
+	}
+}
+impl IntoJs for String {
+	fn into_js<T: Hookable>(self, rt: &JsRuntime<T>) -> SharedValue {
+		return rt.new_string(self);
+	}
+}
+impl IntoJs for &str {
+	fn into_js<T: Hookable>(self, rt: &JsRuntime<T>) -> SharedValue {
+		return rt.new_string(self.to_string());
+	}
+}
+
+/* undefined and null are None */
+impl<A: FromJs> FromJs for Option<A> {
+	fn from_js<T: Hookable>(rt: &JsRuntime<T>, jv: &SharedValue) -> Result<Self, JsError> {
+		if !jv.is_something() {
+			return Ok(None);
+		}
+		return Ok(Some(A::from_js(rt, jv)?));
+	}
+}
+impl<A: IntoJs> IntoJs for Option<A> {
+	fn into_js<T: Hookable>(self, rt: &JsRuntime<T>) -> SharedValue {
+		match self {
+			Some(v) => {
+				return v.into_js(rt);
+			},
+			None => {
+				return SharedValue::new_null();
+			}
+		}
+	}
+}
+
+fn array_items<T: Hookable>(rt: &JsRuntime<T>, jv: &SharedValue) -> Result<Vec<SharedValue>, JsError> {
+	if jv.is_object() {
+		let obj = jv.get_object();
+		let obj = obj.borrow();
+		if obj.is_array() {
+			return Ok(obj.get_array().clone());
+		}
+	}
+	return Err(type_error(rt, "array", jv));
+}
+
+impl<A: FromJs> FromJs for Vec<A> {
+	fn from_js<T: Hookable>(rt: &JsRuntime<T>, jv: &SharedValue) -> Result<Self, JsError> {
+		let items = array_items(rt, jv)?;
+		let mut result = Vec::new();
+		for item in items.iter() {
+			result.push(A::from_js(rt, item)?);
+		}
+		return Ok(result);
+	}
+}
+impl<A: IntoJs> IntoJs for Vec<A> {
+	fn into_js<T: Hookable>(self, rt: &JsRuntime<T>) -> SharedValue {
+		let items = self.into_iter().map(|v| v.into_js(rt)).collect();
+		return rt.new_array(items);
+	}
+}
+
+/* a map is a vanilla object, only own enumerable properties are read */
+impl<A: FromJs, S: BuildHasher + Default> FromJs for HashMap<String, A, S> {
+	fn from_js<T: Hookable>(rt: &JsRuntime<T>, jv: &SharedValue) -> Result<Self, JsError> {
+		if !jv.is_object() || !jv.get_object().borrow().is_vanilla() {
+			return Err(type_error(rt, "object", jv));
+		}
+		let obj = jv.get_object();
+		let props: Vec<(String, SharedValue)> = obj.borrow().own_properties()
+			.filter(|(_, prop)| prop.enumerable())
+			.map(|(k, prop)| (k.to_string(), prop.value.clone()))
+			.collect();
+
+		let mut result = HashMap::default();
+		for (k, v) in props {
+			let v = A::from_js(rt, &v)?;
+			result.insert(k, v);
+		}
+		return Ok(result);
+	}
+}
+impl<A: IntoJs, S: BuildHasher> IntoJs for HashMap<String, A, S> {
+	fn into_js<T: Hookable>(self, rt: &JsRuntime<T>) -> SharedValue {
+		let props = self.into_iter().map(|(k, v)| (k, v.into_js(rt))).collect();
+		return rt.new_object(props);
+	}
+}
+
+/* a tuple is an array with fixed length */
+macro_rules! convert_tuple {
+	($n:expr, $($name:ident : $idx:tt),+) => {
+		impl<$($name: FromJs),+> FromJs for ($($name,)+) {
+			fn from_js<T: Hookable>(rt: &JsRuntime<T>, jv: &SharedValue) -> Result<Self, JsError> {
+				let items = array_items(rt, jv)?;
+				if items.len() != $n {
+					return Err(rt.new_type_error(format!("expected an array of length {} but got {}", $n, items.len())));
+				}
+				return Ok(( $($name::from_js(rt, &items[$idx])?,)+ ));
+			}
+		}
+		impl<$($name: IntoJs),+> IntoJs for ($($name,)+) {
+			fn into_js<T: Hookable>(self, rt: &JsRuntime<T>) -> SharedValue {
+				let items = vec![ $(self.$idx.into_js(rt)),+ ];
+				return rt.new_array(items);
+			}
+		}
+	}
+}
+convert_tuple!(1, A:0);
+convert_tuple!(2, A:0, B:1);
+convert_tuple!(3, A:0, B:1, C:2);
+convert_tuple!(4, A:0, B:1, C:2, D:3);
+convert_tuple!(5, A:0, B:1, C:2, D:3, E:4);
+convert_tuple!(6, A:0, B:1, C:2, D:3, E:4, F:5);
@@ -0,0 +1,124 @@
+use std::fmt;
+
+use crate::value::*;
+
+/// The error type returned by every public entry of ezjs.
+/// Parsing errors carry the source location, runtime errors carry the thrown value.
+///
+pub enum JsError {
+	SyntaxError {
+		line:		u32,
+		column:		u32,
+		message:	String,
+	},
+	CompileError {
+		line:		u32,
+		message:	String,
+	},
+	Uncaught(SharedValue),
+	/* execution is stopped by the VM itself, script can't catch it */
+	Aborted(String),
+	/* the instruction budget of runtime is used up */
+	OutOfFuel,
+	/* stopped by an interrupt handle, script can't catch it */
+	Interrupted,
+	/* the memory limit of runtime is exceeded */
+	OutOfMemory,
+	/* bytecode loaded from bytes is malformed or built for other opcodes */
+	InvalidBytecode(String),
+}
+
+impl JsError {
+	pub fn syntax(line: u32, column: u32, message: String) -> JsError {
+		JsError::SyntaxError {
+			line: line,
+			column: column,
+			message: message,
+		}
+	}
+	pub fn compile(line: u32, message: String) -> JsError {
+		JsError::CompileError {
+			line: line,
+			message: message,
+		}
+	}
+
+	pub fn is_uncaught(&self) -> bool {
+		if let JsError::Uncaught(_) = self {
+			return true;
+		}
+		return false;
+	}
+
+	/* message of the error, for thrown exception objects it is the exception's message */
+	pub fn message(&self) -> String {
+		match self {
+			JsError::SyntaxError{message, ..} => {
+				return message.clone();
+			},
+			JsError::CompileError{message, ..} => {
+				return message.clone();
+			},
+			JsError::Uncaught(v) => {
+				if v.is_exception() {
+					return v.get_object().borrow().get_exception().msg;
+				}
+				return v.to_string();
+			},
+			JsError::Aborted(message) => {
+				return message.clone();
+			},
+			JsError::OutOfFuel => {
+				return "instruction budget is exhausted".to_string();
+			},
+			JsError::Interrupted => {
+				return "script is interrupted".to_string();
+			},
+			JsError::OutOfMemory => {
+				return "memory limit is exceeded".to_string();
+			},
+			JsError::InvalidBytecode(message) => {
+				return message.clone();
+			}
+		}
+	}
+}
+
+impl fmt::Display for JsError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			JsError::SyntaxError{line, column, message} => {
+				write!(f, "SyntaxError @ {}:{} : {}", line, column, message)
+			},
+			JsError::CompileError{line, message} => {
+				write!(f, "CompileError @ {} : {}", line, message)
+			},
+			JsError::Uncaught(_) => {
+				write!(f, "Uncaught: {}", self.message())
+			},
+			JsError::Aborted(message) => {
+				write!(f, "Aborted: {}", message)
+			},
+			JsError::OutOfFuel => {
+				write!(f, "OutOfFuel: {}", self.message())
+			},
+			JsError::Interrupted => {
+				write!(f, "Interrupted: {}", self.message())
+			},
+			JsError::OutOfMemory => {
+				write!(f, "OutOfMemory: {}", self.message())
+			},
+			JsError::InvalidBytecode(message) => {
+				write!(f, "InvalidBytecode: {}", message)
+			}
+		}
+	}
+}
+
+impl fmt::Debug for JsError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Display::fmt(self, f)
+	}
+}
+
+impl std::error::Error for JsError {}
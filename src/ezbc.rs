@@ -0,0 +1,289 @@
+use std::convert::TryInto;
+use std::rc::Rc;
+
+use crate::bytecode::*;
+use crate::error::*;
+
+/* Portable binary format of compiled functions, the content of .ezbc files.
+   All numbers are little endian, a file is the header and then the script function:
+
+	header:		"EZBC" format:u16 opcode_set:u16 opcode_count:u16
+	function:	script:u8 name:option<str> numparams:u32 numvars:u32 locals:vec<u8>
+				arguments:u8 scope:u8
+				code:vec<u16> num_tab:vec<f64> str_tab:vec<str> prop_tab:vec<u32>
+				imports:vec<binding> exports:vec<binding> func_tab:vec<function>
+	vec<T>:		len:u32 T*len
+	str:		len:u32 utf8
+	option<T>:	0:u8 | 1:u8 T
+	binding:	specifier:str name:str local:str
+*/
+pub const EZBC_MAGIC: &[u8; 4] = b"EZBC";
+pub const EZBC_FORMAT_VERSION: u16 = 4;
+
+/* nested functions are decoded recursively, deeper tables are rejected before overflowing the stack */
+const EZBC_DEPTH_LIMIT: usize = 256;
+
+fn bytecode_error(msg: &str) -> JsError {
+	return JsError::InvalidBytecode(msg.to_string());
+}
+
+struct Writer {
+	bytes: Vec<u8>,
+}
+
+impl Writer {
+	fn u8(&mut self, v: u8) {
+		self.bytes.push(v);
+	}
+	fn u16(&mut self, v: u16) {
+		self.bytes.extend_from_slice(&v.to_le_bytes());
+	}
+	fn u32(&mut self, v: usize) {
+		self.bytes.extend_from_slice(&(v as u32).to_le_bytes());
+	}
+	fn f64(&mut self, v: f64) {
+		self.bytes.extend_from_slice(&v.to_bits().to_le_bytes());
+	}
+	fn str(&mut self, s: &str) {
+		self.u32(s.len());
+		self.bytes.extend_from_slice(s.as_bytes());
+	}
+	fn bindings(&mut self, lst: &[VMModuleBinding]) {
+		self.u32(lst.len());
+		for b in lst {
+			self.str(&b.specifier);
+			self.str(&b.name);
+			self.str(&b.local);
+		}
+	}
+
+	fn function(&mut self, f: &VMFunction) {
+		self.u8(f.script as u8);
+		match f.name {
+			Some(ref name) => {
+				self.u8(1);
+				self.str(name);
+			},
+			None => self.u8(0),
+		}
+		self.u32(f.numparams);
+		self.u32(f.numvars);
+		self.u32(f.locals.len());
+		for l in f.locals.iter() {
+			self.u8(*l as u8);
+		}
+		self.u8(f.arguments as u8);
+		self.u8(f.scope as u8);
+
+		self.u32(f.code.len());
+		for c in f.code.iter() {
+			self.u16(*c);
+		}
+		self.u32(f.num_tab.len());
+		for n in f.num_tab.iter() {
+			self.f64(*n);
+		}
+		self.u32(f.str_tab.len());
+		for s in f.str_tab.iter() {
+			self.str(s);
+		}
+		self.u32(f.prop_tab.len());
+		for p in f.prop_tab.iter() {
+			self.u32(*p);
+		}
+		self.bindings(&f.imports);
+		self.bindings(&f.exports);
+
+		self.u32(f.func_tab.len());
+		for sub in f.func_tab.iter() {
+			self.function(sub);
+		}
+	}
+}
+
+struct Reader<'a> {
+	bytes: &'a [u8],
+	pos: usize,
+	depth: usize,
+}
+
+impl<'a> Reader<'a> {
+	fn take(&mut self, n: usize) -> Result<&'a [u8], JsError> {
+		if self.bytes.len() - self.pos < n {
+			return Err(bytecode_error("unexpected end of bytecode"));
+		}
+		let r = &self.bytes[self.pos..self.pos + n];
+		self.pos = self.pos + n;
+		return Ok(r);
+	}
+	fn u8(&mut self) -> Result<u8, JsError> {
+		return Ok(self.take(1)?[0]);
+	}
+	fn u16(&mut self) -> Result<u16, JsError> {
+		return Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()));
+	}
+	fn u32(&mut self) -> Result<usize, JsError> {
+		return Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize);
+	}
+	fn f64(&mut self) -> Result<f64, JsError> {
+		return Ok(f64::from_bits(u64::from_le_bytes(self.take(8)?.try_into().unwrap())));
+	}
+	fn str(&mut self) -> Result<String, JsError> {
+		let len = self.u32()?;
+		let raw = self.take(len)?;
+		match std::str::from_utf8(raw) {
+			Ok(s) => return Ok(s.to_string()),
+			Err(_) => return Err(bytecode_error("string is not utf-8")),
+		}
+	}
+	/* length of a table, each item takes at least min_size bytes so a bad length can't allocate too much */
+	fn flag(&mut self, msg: &str) -> Result<bool, JsError> {
+		match self.u8()? {
+			0 => return Ok(false),
+			1 => return Ok(true),
+			_ => return Err(bytecode_error(msg)),
+		}
+	}
+	fn len(&mut self, min_size: usize) -> Result<usize, JsError> {
+		let len = self.u32()?;
+		if len.saturating_mul(min_size) > self.bytes.len() - self.pos {
+			return Err(bytecode_error("table is longer than bytecode"));
+		}
+		return Ok(len);
+	}
+	fn bindings(&mut self) -> Result<Vec<VMModuleBinding>, JsError> {
+		let len = self.len(12)?;
+		let mut lst = Vec::with_capacity(len);
+		for _ in 0..len {
+			lst.push(VMModuleBinding {
+				specifier: self.str()?,
+				name: self.str()?,
+				local: self.str()?,
+			});
+		}
+		return Ok(lst);
+	}
+
+	fn function(&mut self) -> Result<VMFunction, JsError> {
+		if self.depth >= EZBC_DEPTH_LIMIT {
+			return Err(bytecode_error("functions are nested too deeply"));
+		}
+		self.depth = self.depth + 1;
+
+		let script = match self.u8()? {
+			0 => false,
+			1 => true,
+			_ => return Err(bytecode_error("bad script flag")),
+		};
+		let name = match self.u8()? {
+			0 => None,
+			1 => Some(self.str()?),
+			_ => return Err(bytecode_error("bad function name")),
+		};
+		let numparams = self.u32()?;
+		let numvars = self.u32()?;
+		let len = self.len(1)?;
+		let mut locals = Vec::with_capacity(len);
+		for _ in 0..len {
+			match self.u8()? {
+				0 => locals.push(false),
+				1 => locals.push(true),
+				_ => return Err(bytecode_error("bad local flag")),
+			}
+		}
+		let arguments = self.flag("bad arguments flag")?;
+		let scope = self.flag("bad scope flag")?;
+
+		let len = self.len(2)?;
+		let mut code = Vec::with_capacity(len);
+		for _ in 0..len {
+			code.push(self.u16()?);
+		}
+		let len = self.len(8)?;
+		let mut num_tab = Vec::with_capacity(len);
+		for _ in 0..len {
+			num_tab.push(self.f64()?);
+		}
+		let len = self.len(4)?;
+		let mut str_tab = Vec::with_capacity(len);
+		for _ in 0..len {
+			str_tab.push(self.str()?);
+		}
+		if numparams + numvars > str_tab.len() {
+			return Err(bytecode_error("variables are out of string table"));
+		}
+		let len = self.len(4)?;
+		let mut prop_tab = Vec::with_capacity(len);
+		for _ in 0..len {
+			prop_tab.push(self.u32()?);
+		}
+		let imports = self.bindings()?;
+		let exports = self.bindings()?;
+
+		let len = self.len(1)?;
+		let mut func_tab = Vec::with_capacity(len);
+		for _ in 0..len {
+			func_tab.push(Rc::new(Box::new(self.function()?)));
+		}
+
+		self.depth = self.depth - 1;
+		return Ok(VMFunction {
+			name: name,
+			script: script,
+			numparams: numparams,
+			numvars: numvars,
+			locals: locals,
+			arguments: arguments,
+			scope: scope,
+			code: code,
+			num_tab: num_tab,
+			str_tab: str_tab,
+			func_tab: func_tab,
+			prop_tab: prop_tab,
+			caches: InlineCaches::default(),
+			jumps: Vec::new(),
+			finally_code: 0,
+			imports: imports,
+			exports: exports,
+		});
+	}
+}
+
+impl VMFunction {
+	/* the function with its nested functions in the .ezbc format */
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut w = Writer {
+			bytes: Vec::new(),
+		};
+		w.bytes.extend_from_slice(EZBC_MAGIC);
+		w.u16(EZBC_FORMAT_VERSION);
+		w.u16(OPCODE_SET_VERSION);
+		w.u16(OpcodeType::OP_LAST as u16);
+		w.function(self);
+		return w.bytes;
+	}
+
+	/* Bytes written by to_bytes, checked for the header and a well formed layout.
+	   Bytecode of another opcode set is rejected, it has to be compiled again from source. */
+	pub fn from_bytes(bytes: &[u8]) -> Result<VMFunction, JsError> {
+		let mut r = Reader {
+			bytes: bytes,
+			pos: 0,
+			depth: 0,
+		};
+		if r.take(4).ok() != Some(&EZBC_MAGIC[..]) {
+			return Err(bytecode_error("not an ezbc file"));
+		}
+		if r.u16()? != EZBC_FORMAT_VERSION {
+			return Err(bytecode_error("unsupported ezbc format version"));
+		}
+		if r.u16()? != OPCODE_SET_VERSION || r.u16()? != OpcodeType::OP_LAST as u16 {
+			return Err(bytecode_error("bytecode is compiled for another opcode set"));
+		}
+		let f = r.function()?;
+		if r.pos != bytes.len() {
+			return Err(bytecode_error("trailing bytes after function"));
+		}
+		return Ok(f);
+	}
+}
@@ -0,0 +1,205 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+use crate::value::*;
+
+/* Cycle collector by trial deletion.
+   Every object of the thread is registered with a weak reference. A collection walks the graph
+   of objects, values and scopes reachable from the registered objects and counts the references
+   found inside the graph. A node with more strong references than that is held from outside,
+   by genv, cenv, the stack, the prototypes or the host, and everything reachable from such a
+   node is alive. The rest is unreachable garbage, only kept by cycles, and is emptied to break them. */
+
+struct JsObjectRegistry {
+	objects:	Vec<Weak<RefCell<JsObject>>>,
+	limit:		usize,
+}
+
+const JS_REGISTRY_MIN: usize = 1024;
+
+thread_local! {
+	static REGISTRY: RefCell<JsObjectRegistry> = const { RefCell::new(JsObjectRegistry {
+		objects: Vec::new(),
+		limit: JS_REGISTRY_MIN,
+	}) };
+}
+
+pub fn register(obj: &SharedObject) {
+	REGISTRY.with(|r| {
+		let mut r = r.borrow_mut();
+		/* dropped objects are pruned when the registry doubles */
+		if r.objects.len() >= r.limit {
+			r.objects.retain(|w| w.strong_count() > 0);
+			r.limit = std::cmp::max(JS_REGISTRY_MIN, r.objects.len() * 2);
+		}
+		r.objects.push(Rc::downgrade(obj));
+	});
+}
+
+fn registered_objects() -> Vec<SharedObject> {
+	return REGISTRY.with(|r| {
+		let mut r = r.borrow_mut();
+		r.objects.retain(|w| w.strong_count() > 0);
+		r.limit = std::cmp::max(JS_REGISTRY_MIN, r.objects.len() * 2);
+		r.objects.iter().filter_map(|w| w.upgrade()).collect()
+	});
+}
+
+#[allow(non_camel_case_types)]
+enum GcNode {
+	object(SharedObject),
+	value(Rc<RefCell<JsValue>>),
+	scope(SharedScope),
+}
+
+impl GcNode {
+	fn address(&self) -> usize {
+		match self {
+			GcNode::object(o) => Rc::as_ptr(o) as *const u8 as usize,
+			GcNode::value(v) => Rc::as_ptr(v) as *const u8 as usize,
+			GcNode::scope(s) => Rc::as_ptr(s) as *const u8 as usize,
+		}
+	}
+
+	fn strong_count(&self) -> usize {
+		match self {
+			GcNode::object(o) => Rc::strong_count(o),
+			GcNode::value(v) => Rc::strong_count(v),
+			GcNode::scope(s) => Rc::strong_count(s),
+		}
+	}
+
+	/* returns false when the node is borrowed, its children can't be walked */
+	fn children(&self, out: &mut Vec<GcNode>) -> bool {
+		match self {
+			GcNode::object(o) => {
+				let obj = match o.try_borrow() {
+					Ok(obj) => obj,
+					Err(_) => return false,
+				};
+				if let Some(ref proto) = obj.__proto__ {
+					out.push(GcNode::object(proto.clone()));
+				}
+				for (_, prop) in obj.own_properties() {
+					out.push(GcNode::value(prop.value.v.clone()));
+					if let Some(ref getter) = prop.getter {
+						out.push(GcNode::object(getter.clone()));
+					}
+					if let Some(ref setter) = prop.setter {
+						out.push(GcNode::object(setter.clone()));
+					}
+				}
+				match obj.value {
+					JsClass::array(ref v) => {
+						for jv in v.iter() {
+							out.push(GcNode::value(jv.v.clone()));
+						}
+					},
+					JsClass::function(ref f) => {
+						out.push(GcNode::scope(f.scope.clone()));
+					},
+					_ => {}
+				}
+			},
+			GcNode::value(v) => {
+				let v = match v.try_borrow() {
+					Ok(v) => v,
+					Err(_) => return false,
+				};
+				if let JsValue::JSObject(ref obj) = *v {
+					out.push(GcNode::object(obj.clone()));
+				}
+			},
+			GcNode::scope(s) => {
+				let scope = match s.try_borrow() {
+					Ok(scope) => scope,
+					Err(_) => return false,
+				};
+				out.push(GcNode::object(scope.variables.clone()));
+				if let Some(ref outer) = scope.outer {
+					out.push(GcNode::scope(outer.clone()));
+				}
+			}
+		}
+		return true;
+	}
+}
+
+/* returns the number of objects reclaimed */
+pub fn collect_cycles() -> usize {
+	let mut index: HashMap<usize, usize> = HashMap::new();
+	let mut nodes: Vec<GcNode> = Vec::new();
+	let mut edges: Vec<Vec<usize>> = Vec::new();
+	let mut internal: Vec<usize> = Vec::new();
+	let mut pinned: Vec<bool> = Vec::new();
+
+	fn add(node: GcNode, index: &mut HashMap<usize, usize>, nodes: &mut Vec<GcNode>,
+		   edges: &mut Vec<Vec<usize>>, internal: &mut Vec<usize>, pinned: &mut Vec<bool>) -> usize {
+		let addr = node.address();
+		if let Some(i) = index.get(&addr) {
+			return *i;
+		}
+		let i = nodes.len();
+		index.insert(addr, i);
+		nodes.push(node);
+		edges.push(Vec::new());
+		internal.push(0);
+		pinned.push(false);
+		return i;
+	}
+
+	for obj in registered_objects() {
+		add(GcNode::object(obj), &mut index, &mut nodes, &mut edges, &mut internal, &mut pinned);
+	}
+
+	/* build the graph and count references inside it */
+	let mut i = 0;
+	while i < nodes.len() {
+		let mut children = Vec::new();
+		if !nodes[i].children(&mut children) {
+			pinned[i] = true;
+		}
+		for child in children {
+			let k = add(child, &mut index, &mut nodes, &mut edges, &mut internal, &mut pinned);
+			internal[k] = internal[k] + 1;
+			edges[i].push(k);
+		}
+		i = i + 1;
+	}
+
+	/* mark from nodes referenced outside the graph, one strong reference is held by nodes */
+	let mut marked = vec![false; nodes.len()];
+	let mut pending: Vec<usize> = Vec::new();
+	for i in 0..nodes.len() {
+		if pinned[i] || nodes[i].strong_count() - 1 > internal[i] {
+			marked[i] = true;
+			pending.push(i);
+		}
+	}
+	while let Some(i) = pending.pop() {
+		for k in edges[i].iter() {
+			if !marked[*k] {
+				marked[*k] = true;
+				pending.push(*k);
+			}
+		}
+	}
+
+	/* empty unreachable objects, dropping the contents breaks the cycles */
+	let mut garbage: Vec<JsObject> = Vec::new();
+	for i in 0..nodes.len() {
+		if marked[i] {
+			continue;
+		}
+		if let GcNode::object(ref obj) = nodes[i] {
+			if let Ok(mut obj) = obj.try_borrow_mut() {
+				garbage.push(std::mem::replace(&mut *obj, JsObject::new()));
+			}
+		}
+	}
+	let collected = garbage.len();
+	drop(garbage);
+	drop(nodes);
+	return collected;
+}
@@ -4,30 +4,50 @@
 //! Whole design is inspired from https://mujs.com/, including including a simple bytecode virtual machine.
 //! You can added a extenstion hook type via Rust's generic features, and this hook type play key role in your DSL enviroment.
 //!
-//! All implementation including tokenlizer, AST builder, compiler, optimizer(TODO), and VM executer is simple and easy to debug, 
+//! All implementation including tokenlizer, AST builder, compiler, optimizer, and VM executer is simple and easy to debug, 
 //! so I called this library *ezjs*.  
 //! 
 //! An completed example is a repl ( src/bin/repl.rs), which including a hookable extention struct. 
+//!
+//! No script string can abort the host process: malformed source returns a JsError from
+//! build_function_from_code, and runtime faults come back from run_script as a catchable exception
+//! or JsError::Aborted. Parser recursion, AST depth, JS call depth and the native stack
+//! used by nested calls are limited to keep the native stack safe.
+//! The limits are sized for a native stack of 8MB, the default of the main thread on Linux: run scripts
+//! on a thread with at least that much stack, threads spawned by std get 2MB unless told otherwise.
 
 mod common;
+pub mod error;
 mod token;
 mod ast;
-mod bytecode;
+pub mod bytecode;
+mod ezbc;
+mod verifier;
+mod peephole;
 
 mod compile;
-mod optimizer;
+pub mod optimizer;
 
 pub mod value;
+mod shape;
+mod gc;
 pub mod runtime;
+pub mod convert;
+pub mod module;
+#[cfg(feature = "serde")]
+mod serde_value;
 mod builtin;
 mod builtin_script;
 
 use std::convert::TryFrom;
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 use crate::ast::*;
 use crate::bytecode::*;
 use crate::compile::*;
-
+use crate::optimizer::*;
+use crate::error::*;
 
 use crate::value::*;
 use crate::runtime::*;
@@ -35,17 +55,48 @@ use crate::builtin::*;
 
 
 /// The function compile string to bytecode of Virtual Machone.
-/// If some erros happens, return it with errpr message in a Resut.
+/// If some erros happens, return a JsError with the source location in a Resut.
 ///
-pub fn build_function_from_code(script: &str) -> Result<SharedFunction, String> {
-    let ast = build_ast_from_script(script);
-	if let Err(msg) = ast {
-		return Err(msg);
-	}
-	let ast = ast.unwrap();
+pub fn build_function_from_code(script: &str) -> Result<SharedFunction, JsError> {
+    return build_function_with_opt_level(script, OptLevel::default());
+}
+
+/// Compile like build_function_from_code with an optimization level,
+/// OptLevel::O0 compiles the script as it's written, which helps to debug the compiler.
+///
+pub fn build_function_with_opt_level(script: &str, level: OptLevel) -> Result<SharedFunction, JsError> {
+    let mut ast = build_ast_from_script(script)?;
+
+    optimize_ast(&mut ast, level);
+    let null = AstNode::null();
+    let mut func = compile_func(&null, &null, &ast, true)?;
+    optimize_code(&mut func, level);
+    return Ok(SharedFunction_new(func));
+}
+
+/// Compile a module, like a script with import and export items at top level.
+/// The module is linked and run by JsRuntime::import_module.
+///
+pub fn build_module_from_code(script: &str) -> Result<SharedFunction, JsError> {
+    let mut ast = build_ast_from_module(script)?;
+    optimize_ast(&mut ast, OptLevel::default());
 
     let null = AstNode::null();
-    let func = compile_func(&null, &null, &ast, true)?;
+    let mut func = compile_func(&null, &null, &ast, true)?;
+    optimize_code(&mut func, OptLevel::default());
+    return Ok(SharedFunction_new(func));
+}
+
+/// Load a function saved by VMFunction::to_bytes, like a .ezbc file, without parsing the source again.
+/// The bytecode is verified before it's returned, bytes of another format or opcode set
+/// and malformed code return JsError::InvalidBytecode.
+///
+pub fn build_function_from_bytes(bytes: &[u8]) -> Result<SharedFunction, JsError> {
+    let func = VMFunction::from_bytes(bytes)?;
+    if !func.script {
+        return Err(JsError::InvalidBytecode("the top function is not script code".to_string()));
+    }
+    func.verify()?;
     return Ok(SharedFunction_new(func));
 }
 
@@ -93,6 +144,7 @@ pub fn new_runtime<T: Hookable>(root: T) -> JsRuntime<T> {
 		array_prototype:		SharedObject_new(JsObject::new()),
 		function_prototype:		SharedObject_new(JsObject::new()),
 		exception_prototype:	SharedObject_new(JsObject::new()),
+		type_error_prototype:	SharedObject_new(JsObject::new()),
 	};
 
 	let genv = JsEnvironment::new();
@@ -104,6 +156,18 @@ pub fn new_runtime<T: Hookable>(root: T) -> JsRuntime<T> {
 		genv:		genv,
 		cenv:		cenv,
 		stack:		Vec::new(),
+		call_depth:	0,
+		native_base:	0,
+		fuel:		None,
+		interrupt:	Arc::new(AtomicBool::new(false)),
+		heap:		JsHeap::new(),
+		memory_limit:	None,
+		memory_over:	false,
+
+		module_loader:	None,
+		modules:	HashMap::new(),
+		module_ids:	HashMap::new(),
+		es_modules:	HashMap::new(),
 
 		hooks:		HashMap::new(),
 		hooks_id:	0,
@@ -118,10 +182,13 @@ pub fn new_runtime<T: Hookable>(root: T) -> JsRuntime<T> {
 }
 
 ///	Run bytecode of VMFunction with a runtime, and return the result sharedvalue.
+/// An uncaught exception is returned as JsError::Uncaught with the thrown value.
 /// See more info via repl example.
 ///
-pub fn run_script<T:Hookable>(rt: &mut JsRuntime<T>, vmf: SharedFunction) -> Result<SharedValue, String> {
-	assert!( vmf.script == true);
+pub fn run_script<T:Hookable>(rt: &mut JsRuntime<T>, vmf: SharedFunction) -> Result<SharedValue, JsError> {
+	if !vmf.script {
+		return Err(JsError::Aborted("run_script needs script code".to_string()));
+	}
 	let fobj = SharedObject_new(JsObject::new_function(vmf, rt.genv.clone(), rt.prototypes.function_prototype.clone()));
 	let thiz = rt.genv.borrow().target(); 
 
@@ -129,19 +196,39 @@ pub fn run_script<T:Hookable>(rt: &mut JsRuntime<T>, vmf: SharedFunction) -> Res
 	rt.push_object(thiz);	// this
 
 	let result = jscall(rt, 0);
-	if result.is_err() {
-		let err_msg = format!("Exceptions: {:?}", result.err().unwrap());		
+	if let Err(e) = result {
 		rt.stack.clear();
-		return Err(err_msg);
+		rt.finalize_hooks();
+		return Err(e);
 	}
 
 	if rt.stack.len() != 1 {
 		let err_msg = format!("stack len should be 1 but get {}", rt.stack.len());
-		panic!(err_msg);
+		rt.stack.clear();
+		return Err(JsError::Aborted(err_msg));
 	}
 
 	let value = rt.stack[0].clone();
 	rt.stack.clear();
+	rt.finalize_hooks();
 	return Ok(value);
 }
 
+///	Deserialize a Rust value from a SharedValue, such as a configuration object built by script.
+/// Only vanilla objects, arrays, strings, numbers, booleans and null/undefined are walked.
+/// A value with wrong shape, nested too deeply or cyclic is returned as a TypeError.
+/// The runtime builds that TypeError: it's a JS exception object made from the runtime's
+/// prototypes, so a host function can return it with `?` and script can catch it.
+///
+#[cfg(feature = "serde")]
+pub fn from_value<T: Hookable, D: serde::de::DeserializeOwned>(rt: &JsRuntime<T>, jv: &SharedValue) -> Result<D, JsError> {
+	return crate::serde_value::from_value(rt, jv);
+}
+
+///	Serialize a Rust value into a SharedValue, structs and maps become vanilla objects.
+/// The runtime is needed for the prototypes of new strings, arrays and objects.
+///
+#[cfg(feature = "serde")]
+pub fn to_value<T: Hookable, S: serde::Serialize + ?Sized>(rt: &JsRuntime<T>, v: &S) -> Result<SharedValue, JsError> {
+	return crate::serde_value::to_value(rt, v);
+}
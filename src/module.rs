@@ -0,0 +1,328 @@
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::*;
+use crate::value::*;
+use crate::runtime::*;
+
+/* Source of modules for require, ids returned by resolve are the keys of the module cache.
+   Errors are messages, they are thrown as catchable exceptions to the requiring script. */
+pub trait ModuleLoader {
+	/* id of a module, referrer is the id of the requiring module or "" at top level */
+	fn resolve(&mut self, specifier: &str, _referrer: &str) -> Result<String, String> {
+		return Ok(specifier.to_string());
+	}
+	fn load(&mut self, id: &str) -> Result<String, String>;
+}
+
+/* loads modules from files, relative specifiers are resolved from the directory of the referrer */
+pub struct FsModuleLoader {
+	pub root: PathBuf,
+}
+
+impl FsModuleLoader {
+	pub fn new<P: AsRef<Path>>(root: P) -> Self {
+		FsModuleLoader {
+			root: root.as_ref().to_path_buf(),
+		}
+	}
+}
+
+impl ModuleLoader for FsModuleLoader {
+	fn resolve(&mut self, specifier: &str, referrer: &str) -> Result<String, String> {
+		let base = if (specifier.starts_with("./") || specifier.starts_with("../")) && referrer != "" {
+			Path::new(referrer).parent().map(|p| p.to_path_buf()).unwrap_or_else(|| self.root.clone())
+		} else {
+			self.root.clone()
+		};
+
+		let mut path = PathBuf::new();
+		for c in base.join(specifier).components() {
+			match c {
+				Component::CurDir => {},
+				Component::ParentDir => {
+					if !path.pop() {
+						path.push("..");
+					}
+				},
+				_ => path.push(c.as_os_str()),
+			}
+		}
+		if path.extension().is_none() {
+			path.set_extension("js");
+		}
+		return Ok(path.to_string_lossy().to_string());
+	}
+
+	fn load(&mut self, id: &str) -> Result<String, String> {
+		return std::fs::read_to_string(id).map_err(|e| e.to_string());
+	}
+}
+
+/* the global require and the require of each module, modules are found by the builtin id */
+fn require<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsError> {
+	let specifier: String = rt.arg(argc, 0)?;
+	let fobj = rt.top(-2 - argc as isize).get_object();
+	let fid = fobj.borrow().get_builtin();
+	let referrer = rt.module_ids.get(&fid).cloned().unwrap_or_default();
+
+	let exports = rt.require(&specifier, &referrer)?;
+	rt.push(exports);
+	return Ok(());
+}
+
+pub fn module_init<T: Hookable>(rt: &mut JsRuntime<T>) {
+	let fobj = rt.new_builtin(JsBuiltinFunction::new(require));
+	rt.genv.borrow_mut().init_var("require", SharedValue::new_object(fobj));
+}
+
+impl<T: Hookable> JsRuntime<T> {
+	pub fn set_module_loader(&mut self, loader: Box<dyn ModuleLoader>) {
+		self.module_loader = Some(loader);
+	}
+
+	/* Load, run and cache a module, returns its module.exports.
+	   A module required again while it's running gets the exports filled so far, like Node. */
+	pub fn require(&mut self, specifier: &str, referrer: &str) -> Result<SharedValue, JsError> {
+		let id = self.resolve_module(specifier, referrer)?;
+		if let Some(module) = self.modules.get(&id) {
+			return Ok(module_exports(module));
+		}
+
+		let source = self.load_module(specifier, &id)?;
+		let vmf = match crate::build_function_from_code(&source) {
+			Ok(vmf) => vmf,
+			Err(e) => {
+				return Err(self.new_error(format!("{}: {}", id, e)));
+			}
+		};
+
+		let exports = SharedValue::new_vanilla(self.prototypes.object_prototype.clone());
+		let module = SharedValue::new_vanilla(self.prototypes.object_prototype.clone());
+		{
+			let mut prop = JsProperty::new();
+			prop.fill(SharedValue::new_sobject(exports.get_object()), JS_DEFAULT_ATTR, None, None);
+			module.get_object().borrow_mut().set_property("exports", prop);
+			let mut prop = JsProperty::new();
+			prop.fill(self.new_string(id.clone()), JS_READONLY_ATTR, None, None);
+			module.get_object().borrow_mut().set_property("id", prop);
+		}
+		self.modules.insert(id.clone(), module.clone());
+
+		if let Err(e) = self.run_module(&id, vmf, &module, &exports) {
+			self.modules.remove(&id);
+			return Err(e);
+		}
+		return Ok(module_exports(&module));
+	}
+
+	fn resolve_module(&mut self, specifier: &str, referrer: &str) -> Result<String, JsError> {
+		let loader = match self.module_loader.as_mut() {
+			Some(loader) => loader,
+			None => {
+				return Err(self.new_error(format!("Cannot find module '{}': no module loader", specifier)));
+			}
+		};
+		match loader.resolve(specifier, referrer) {
+			Ok(id) => return Ok(id),
+			Err(msg) => {
+				return Err(self.new_error(format!("Cannot find module '{}': {}", specifier, msg)));
+			}
+		}
+	}
+
+	fn load_module(&mut self, specifier: &str, id: &str) -> Result<String, JsError> {
+		match self.module_loader.as_mut().unwrap().load(id) {
+			Ok(source) => return Ok(source),
+			Err(msg) => {
+				return Err(self.new_error(format!("Cannot find module '{}': {}", specifier, msg)));
+			}
+		}
+	}
+
+	/* a module runs as a script in its own scope, with module, exports and require bound */
+	fn run_module(&mut self, id: &str, vmf: SharedFunction, module: &SharedValue, exports: &SharedValue) -> Result<(), JsError> {
+		let fobj = self.new_builtin(JsBuiltinFunction::new(require));
+		let fid = fobj.get_builtin();
+		self.module_ids.insert(fid, id.to_string());
+
+		let env = JsEnvironment::new_from(self.genv.clone());
+		env.borrow_mut().init_var("module", SharedValue::new_sobject(module.get_object()));
+		env.borrow_mut().init_var("exports", SharedValue::new_sobject(exports.get_object()));
+		env.borrow_mut().init_var("require", SharedValue::new_object(fobj));
+
+		let script = SharedObject_new(JsObject::new_function(vmf, env.clone(), self.prototypes.function_prototype.clone()));
+		let top = self.stack.len();
+		let old_env = std::mem::replace(&mut self.cenv, env);
+		self.push_object(script);
+		self.push(SharedValue::new_sobject(exports.get_object()));
+		let result = jscall(self, 0);
+		self.cenv = old_env;
+		self.stack.truncate(top);
+		return result;
+	}
+}
+
+fn module_exports(module: &SharedValue) -> SharedValue {
+	if let Some((prop, _own)) = module.get_object().borrow().query_property("exports") {
+		return prop.value.duplicate();
+	}
+	return SharedValue::new_undefined();
+}
+
+/* ES modules */
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JsModuleState {
+	Linking,
+	Evaluating,
+	Evaluated,
+}
+
+/* A module loaded by import. Its top level variables live in env, importers and the namespace
+   hold the same values as the variables, so assignments in the module are seen by them. */
+pub struct JsModule {
+	pub id: String,
+	pub state: JsModuleState,
+	pub namespace: SharedObject,
+	env: SharedScope,
+	vmf: SharedFunction,
+	/* resolved ids of the imported specifiers, in order of the import items */
+	requests: Vec<(String, String)>,
+}
+
+impl<T: Hookable> JsRuntime<T> {
+	/* Load, link and run a module with all the modules it imports, returns its namespace object.
+	   Modules failed to load, link or run are not cached, the imported ones which have run stay cached
+	   so they don't run again. */
+	pub fn import_module(&mut self, specifier: &str, referrer: &str) -> Result<SharedValue, JsError> {
+		let mut added: Vec<String> = Vec::new();
+		let result = self.import_graph(specifier, referrer, &mut added);
+		if result.is_err() {
+			for id in added.iter() {
+				if self.es_modules[id].state != JsModuleState::Evaluated {
+					self.es_modules.remove(id);
+				}
+			}
+		}
+		return result;
+	}
+
+	fn import_graph(&mut self, specifier: &str, referrer: &str, added: &mut Vec<String>) -> Result<SharedValue, JsError> {
+		let id = self.load_es_module(specifier, referrer, added)?;
+		for new_id in added.clone().iter() {
+			self.link_es_module(new_id)?;
+		}
+		self.evaluate_es_module(&id)?;
+		return Ok(SharedValue::new_sobject(self.es_modules[&id].namespace.clone()));
+	}
+
+	/* loads a module and its imports, variables and the namespace are created before any module is linked */
+	fn load_es_module(&mut self, specifier: &str, referrer: &str, added: &mut Vec<String>) -> Result<String, JsError> {
+		let id = self.resolve_module(specifier, referrer)?;
+		if self.es_modules.contains_key(&id) {
+			return Ok(id);
+		}
+		let source = self.load_module(specifier, &id)?;
+		let vmf = match crate::build_module_from_code(&source) {
+			Ok(vmf) => vmf,
+			Err(e) => {
+				return Err(self.new_error(format!("{}: {}", id, e)));
+			}
+		};
+
+		let env = JsEnvironment::new_from(self.genv.clone());
+		for i in 0..vmf.numvars {
+			env.borrow_mut().init_var(&vmf.str_tab[i], SharedValue::new_undefined());
+		}
+		let mut namespace = JsObject::new();
+		for export in vmf.exports.iter() {
+			env.borrow_mut().init_var(&export.local, SharedValue::new_undefined());
+			let mut prop = JsProperty::new();
+			prop.fill(env.borrow().get_variable(&export.local).value, JS_READONLY_ATTR, None, None);
+			prop.attr_enumerable = true;
+			namespace.set_property(&export.name, prop);
+		}
+		namespace.extensible = false;
+
+		let module = JsModule {
+			id: id.clone(),
+			state: JsModuleState::Linking,
+			namespace: SharedObject_new(namespace),
+			env: env,
+			vmf: vmf.clone(),
+			requests: Vec::new(),
+		};
+		self.es_modules.insert(id.clone(), module);
+		added.push(id.clone());
+
+		let mut requests: Vec<(String, String)> = Vec::new();
+		for import in vmf.imports.iter() {
+			if requests.iter().any(|(s, _)| *s == import.specifier) {
+				continue;
+			}
+			let rid = self.load_es_module(&import.specifier, &id, added)?;
+			requests.push((import.specifier.clone(), rid));
+		}
+		self.es_modules.get_mut(&id).unwrap().requests = requests;
+		return Ok(id);
+	}
+
+	/* imported names are bound to the variables of the exporting module, read only to the importer */
+	fn link_es_module(&mut self, id: &str) -> Result<(), JsError> {
+		let module = &self.es_modules[id];
+		let env = module.env.clone();
+		let vmf = module.vmf.clone();
+		let requests = module.requests.clone();
+
+		for import in vmf.imports.iter() {
+			if import.local.is_empty() {
+				continue;
+			}
+			let rid = &requests.iter().find(|(s, _)| *s == import.specifier).unwrap().1;
+			let target = self.es_modules[rid].namespace.clone();
+
+			let mut prop = JsProperty::new();
+			if import.name == "*" {
+				prop.fill(SharedValue::new_sobject(target), JS_READONLY_ATTR, None, None);
+			} else {
+				let value = match target.borrow().query_property(&import.name) {
+					Some((export, true)) => export.value.clone(),
+					_ => {
+						return Err(self.new_error(format!("{}: module '{}' has no export named '{}'", id, rid, import.name)));
+					}
+				};
+				prop.fill(value, JS_READONLY_ATTR, None, None);
+			}
+			env.borrow().set_variable(&import.local, prop);
+		}
+		return Ok(());
+	}
+
+	/* imported modules run first, a module imported again while it's running is skipped */
+	fn evaluate_es_module(&mut self, id: &str) -> Result<(), JsError> {
+		let module = self.es_modules.get_mut(id).unwrap();
+		if module.state != JsModuleState::Linking {
+			return Ok(());
+		}
+		module.state = JsModuleState::Evaluating;
+		let requests = module.requests.clone();
+		for (_, rid) in requests.iter() {
+			self.evaluate_es_module(rid)?;
+		}
+
+		let module = &self.es_modules[id];
+		let env = module.env.clone();
+		let script = SharedObject_new(JsObject::new_function(module.vmf.clone(), env.clone(), self.prototypes.function_prototype.clone()));
+		let top = self.stack.len();
+		let old_env = std::mem::replace(&mut self.cenv, env);
+		self.push_object(script);
+		self.push_undefined();
+		let result = jscall(self, 0);
+		self.cenv = old_env;
+		self.stack.truncate(top);
+		result?;
+
+		self.es_modules.get_mut(id).unwrap().state = JsModuleState::Evaluated;
+		return Ok(());
+	}
+}
@@ -0,0 +1,271 @@
+use crate::ast::*;
+use crate::bytecode::*;
+
+/*
+ * Optimizations of the AST, between build_ast_from_script and compile_func.
+ * Constants of numbers and strings are folded, if with a constant condition keeps only
+ * the taken branch, and statements after return/throw/break/continue are removed.
+ * Folding is done only where the VM gives the same result, a script runs the same
+ * at every level. Declarations are hoisted, so they are never removed.
+ */
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OptLevel {
+    O0,     /* compile the AST as it's parsed */
+    O1,     /* fold constants, remove dead code and the peephole pass over bytecode */
+}
+
+impl Default for OptLevel {
+    fn default() -> Self {
+        return OptLevel::O1;
+    }
+}
+
+pub(crate) fn optimize_ast(node: &mut AstNode, level: OptLevel) {
+    if level == OptLevel::O0 {
+        return;
+    }
+    optimize_node(node);
+}
+
+pub(crate) fn optimize_code(f: &mut VMFunction, level: OptLevel) {
+    if level == OptLevel::O0 {
+        return;
+    }
+    f.peephole();
+}
+
+fn optimize_node(node: &mut AstNode) {
+    if node.ast_type == AstType::AST_LIST {
+        optimize_list(node);
+        return;
+    }
+
+    for child in [&mut node.a, &mut node.b, &mut node.c, &mut node.d].iter_mut() {
+        if let Some(child) = child.as_mut() {
+            optimize_node(child);
+        }
+    }
+
+    match node.ast_type {
+        AstType::EXP_POS | AstType::EXP_NEG => fold_unary(node),
+        AstType::EXP_ADD | AstType::EXP_SUB | AstType::EXP_MUL | AstType::EXP_DIV | AstType::EXP_MOD |
+        AstType::EXP_LT | AstType::EXP_GT | AstType::EXP_LE | AstType::EXP_GE |
+        AstType::EXP_EQ | AstType::EXP_NE | AstType::EXP_STRICTEQ | AstType::EXP_STRICTNE => fold_binary(node),
+        AstType::STM_IF => fold_if(node),
+        _ => {},
+    }
+}
+
+/* Items are linked by b, the list is walked with a loop to keep the native stack flat */
+fn optimize_list(list: &mut AstNode) {
+    let mut item: &mut AstNode = list;
+    let mut reachable = true;
+    loop {
+        if let Some(stm) = item.a.as_mut() {
+            optimize_node(stm);
+            match stm.ast_type {
+                AstType::STM_RETURN | AstType::STM_THROW | AstType::STM_BREAK | AstType::STM_CONTINUE => {
+                    reachable = false;
+                },
+                _ => {},
+            }
+        }
+
+        /* unlink the following dead statements */
+        while !reachable && is_dead(item.b.as_deref()) {
+            let mut next = item.b.take().unwrap();
+            item.b = next.b.take();
+        }
+
+        if item.b.is_none() {
+            break;
+        }
+        item = item.b.as_mut().unwrap();
+    }
+}
+
+fn is_dead(item: Option<&AstNode>) -> bool {
+    match item {
+        Some(item) => return !has_declaration(item.a.as_ref().unwrap()),
+        None => return false,
+    }
+}
+
+/* var, function, import and export are hoisted or linked, even where they can't run */
+fn has_declaration(node: &AstNode) -> bool {
+    let mut nodes: Vec<&AstNode> = vec![node];
+    while let Some(node) = nodes.pop() {
+        match node.ast_type {
+            AstType::EXP_VAR | AstType::AST_FUNDEC |
+            AstType::STM_IMPORT | AstType::STM_EXPORT | AstType::STM_EXPORT_DEFAULT => {
+                return true;
+            },
+            AstType::EXP_FUN => {
+                continue;
+            },
+            _ => {},
+        }
+        for child in [&node.a, &node.b, &node.c, &node.d].iter() {
+            if let Some(child) = child.as_ref() {
+                nodes.push(child);
+            }
+        }
+    }
+    return false;
+}
+
+fn set_number(node: &mut AstNode, n: f64) {
+    /* the number table can't tell -0 from 0, leave it to the VM */
+    if n == 0.0 && n.is_sign_negative() {
+        return;
+    }
+    node.ast_type = AstType::EXP_NUMBER;
+    node.num_value = Some(n);
+    node.str_value = None;
+    node.a = None;
+    node.b = None;
+}
+
+fn set_string(node: &mut AstNode, s: String) {
+    node.ast_type = AstType::EXP_STRING;
+    node.num_value = None;
+    node.str_value = Some(s);
+    node.a = None;
+    node.b = None;
+}
+
+fn set_boolean(node: &mut AstNode, b: bool) {
+    node.ast_type = if b { AstType::EXP_TRUE } else { AstType::EXP_FALSE };
+    node.num_value = None;
+    node.str_value = None;
+    node.a = None;
+    node.b = None;
+}
+
+fn fold_unary(node: &mut AstNode) {
+    let a = node.a.as_ref().unwrap();
+    if a.ast_type != AstType::EXP_NUMBER {
+        return;
+    }
+    let n = a.num_value.unwrap();
+    match node.ast_type {
+        AstType::EXP_POS => set_number(node, n),
+        AstType::EXP_NEG => set_number(node, -n),
+        _ => {},
+    }
+}
+
+fn fold_binary(node: &mut AstNode) {
+    let a = node.a.as_ref().unwrap();
+    let b = node.b.as_ref().unwrap();
+
+    if a.ast_type == AstType::EXP_NUMBER && b.ast_type == AstType::EXP_NUMBER {
+        let x = a.num_value.unwrap();
+        let y = b.num_value.unwrap();
+        match node.ast_type {
+            AstType::EXP_ADD => set_number(node, x + y),
+            AstType::EXP_SUB => set_number(node, x - y),
+            AstType::EXP_MUL => set_number(node, x * y),
+            AstType::EXP_DIV => set_number(node, x / y),
+            AstType::EXP_MOD => set_number(node, x % y),
+            AstType::EXP_EQ | AstType::EXP_STRICTEQ => set_boolean(node, x == y),
+            AstType::EXP_NE | AstType::EXP_STRICTNE => set_boolean(node, x != y),
+            _ => {
+                /* the VM doesn't order NaN like the spec, keep the comparison */
+                if x.is_nan() || y.is_nan() {
+                    return;
+                }
+                fold_compare(node, x.partial_cmp(&y).unwrap());
+            },
+        }
+        return;
+    }
+
+    if a.ast_type == AstType::EXP_STRING && b.ast_type == AstType::EXP_STRING {
+        let x = a.str_value.as_ref().unwrap();
+        let y = b.str_value.as_ref().unwrap();
+        match node.ast_type {
+            AstType::EXP_ADD => {
+                let s = x.clone() + y;
+                set_string(node, s);
+            },
+            AstType::EXP_EQ | AstType::EXP_STRICTEQ => {
+                let r = x == y;
+                set_boolean(node, r);
+            },
+            AstType::EXP_NE | AstType::EXP_STRICTNE => {
+                let r = x != y;
+                set_boolean(node, r);
+            },
+            AstType::EXP_LT | AstType::EXP_GT | AstType::EXP_LE | AstType::EXP_GE => {
+                let r = x.cmp(y);
+                fold_compare(node, r);
+            },
+            _ => {},
+        }
+    }
+}
+
+fn fold_compare(node: &mut AstNode, r: std::cmp::Ordering) {
+    use std::cmp::Ordering;
+    match node.ast_type {
+        AstType::EXP_LT => set_boolean(node, r == Ordering::Less),
+        AstType::EXP_GT => set_boolean(node, r == Ordering::Greater),
+        AstType::EXP_LE => set_boolean(node, r != Ordering::Greater),
+        AstType::EXP_GE => set_boolean(node, r != Ordering::Less),
+        _ => {},
+    }
+}
+
+/* the value of a constant condition, when the VM agrees with the spec */
+fn constant_condition(node: &AstNode) -> Option<bool> {
+    match node.ast_type {
+        AstType::EXP_TRUE => return Some(true),
+        AstType::EXP_FALSE | AstType::EXP_NULL | AstType::EXP_UNDEF => return Some(false),
+        AstType::EXP_NUMBER => {
+            let n = node.num_value.unwrap();
+            if n.is_nan() {
+                return None;
+            }
+            return Some(n != 0.0);
+        },
+        AstType::EXP_STRING => {
+            if node.str_value.as_ref().unwrap().is_empty() {
+                return None;
+            }
+            return Some(true);
+        },
+        _ => return None,
+    }
+}
+
+fn fold_if(node: &mut AstNode) {
+    let cond = match constant_condition(node.a.as_ref().unwrap()) {
+        Some(cond) => cond,
+        None => return,
+    };
+
+    /* the branch not taken goes away, unless it declares variables */
+    let dropped = if cond { node.c.as_ref() } else { node.b.as_ref() };
+    if let Some(dropped) = dropped {
+        if has_declaration(dropped) {
+            return;
+        }
+    }
+
+    let taken = if cond { node.b.take() } else { node.c.take() };
+    match taken {
+        Some(taken) => {
+            /* in a block an expression statement isn't the completion value of a script, like in the if */
+            let line = node.src_line;
+            *node = AstNode::new_a(AstType::STM_BLOCK, line, AstNode::new_list(*taken));
+        },
+        None => {
+            node.ast_type = AstType::STM_EMPTY;
+            node.a = None;
+            node.b = None;
+            node.c = None;
+        },
+    }
+}
@@ -0,0 +1,241 @@
+use std::rc::Rc;
+
+use crate::bytecode::*;
+
+/* Peephole pass over the code of a compiled function, the AST optimizer can't see these:
+	OP_DUP OP_POP					removed
+	OP_TRUE/OP_FALSE OP_JTRUE/OP_JFALSE		OP_JUMP or removed
+	OP_JUMP to the next instruction			removed
+	jumps to OP_JUMP				jump to the end of the chain
+	OP_NUMBER of a small integer			OP_INTEGER
+   Code which can't be reached is dropped, then the code is compacted and the addresses of
+   jumps and OP_TRY are moved. The result passes the verifier like the compiled code. */
+
+struct PeepholeOp {
+	op: OpcodeType,
+	operand: usize,		// raw operand, or the index of the target instruction for jumps
+	width: usize,
+	live: bool,
+}
+
+fn is_branch(op: OpcodeType) -> bool {
+	match op {
+		OpcodeType::OP_JUMP | OpcodeType::OP_JTRUE | OpcodeType::OP_JFALSE |
+		OpcodeType::OP_JCASE | OpcodeType::OP_TRY => return true,
+		_ => return false,
+	}
+}
+
+/* the first live instruction from i, or the end of code */
+fn next_live(ops: &[PeepholeOp], i: usize) -> usize {
+	let mut i = i;
+	while i < ops.len() && !ops[i].live {
+		i = i + 1;
+	}
+	return i;
+}
+
+/* retarget jumps to live instructions and thread jumps to OP_JUMP */
+fn thread_jumps(ops: &mut [PeepholeOp]) {
+	for i in 0..ops.len() {
+		if !ops[i].live || !is_branch(ops[i].op) {
+			continue;
+		}
+		let mut target = next_live(ops, ops[i].operand);
+		if ops[i].op != OpcodeType::OP_TRY {
+			/* a loop of jumps is followed once around at most */
+			let mut steps = 0;
+			while target < ops.len() && ops[target].op == OpcodeType::OP_JUMP && steps < ops.len() {
+				target = next_live(ops, ops[target].operand);
+				steps = steps + 1;
+			}
+		}
+		ops[i].operand = target;
+	}
+}
+
+/* drop the instructions which no path reaches, same paths as the verifier */
+fn drop_unreachable(ops: &mut [PeepholeOp]) -> bool {
+	let mut reached = vec![false; ops.len()];
+	let mut work: Vec<usize> = vec![next_live(ops, 0)];
+	while let Some(i) = work.pop() {
+		if i >= ops.len() || reached[i] {
+			continue;
+		}
+		reached[i] = true;
+		let next = next_live(ops, i + 1);
+		match ops[i].op {
+			OpcodeType::OP_RETURN | OpcodeType::OP_THROW | OpcodeType::OP_DEBUG => {},
+			OpcodeType::OP_JUMP => work.push(ops[i].operand),
+			OpcodeType::OP_JTRUE | OpcodeType::OP_JFALSE | OpcodeType::OP_JCASE | OpcodeType::OP_TRY => {
+				work.push(ops[i].operand);
+				work.push(next);
+			},
+			_ => work.push(next),
+		}
+	}
+
+	let mut changed = false;
+	for i in 0..ops.len() {
+		if ops[i].live && !reached[i] {
+			ops[i].live = false;
+			changed = true;
+		}
+	}
+	return changed;
+}
+
+/* one forward scan of the patterns, an instruction entered by a jump can't be merged with the one before it */
+fn rewrite_patterns(ops: &mut [PeepholeOp]) -> bool {
+	let mut targets = vec![false; ops.len() + 1];
+	for i in 0..ops.len() {
+		if ops[i].live && is_branch(ops[i].op) {
+			targets[ops[i].operand] = true;
+		}
+		if ops[i].live && ops[i].op == OpcodeType::OP_TRY {
+			/* the handler is entered by exceptions */
+			targets[next_live(ops, i + 1)] = true;
+		}
+	}
+
+	let mut changed = false;
+	let mut i = next_live(ops, 0);
+	while i < ops.len() {
+		let j = next_live(ops, i + 1);
+		let second = if j < ops.len() && !targets[j] { Some(ops[j].op) } else { None };
+
+		match (ops[i].op, second) {
+			(OpcodeType::OP_DUP, Some(OpcodeType::OP_POP)) => {
+				ops[i].live = false;
+				ops[j].live = false;
+				changed = true;
+				i = next_live(ops, j + 1);
+				continue;
+			},
+			(OpcodeType::OP_TRUE, Some(OpcodeType::OP_JTRUE)) | (OpcodeType::OP_FALSE, Some(OpcodeType::OP_JFALSE)) => {
+				ops[i].live = false;
+				ops[j].op = OpcodeType::OP_JUMP;
+				changed = true;
+			},
+			(OpcodeType::OP_TRUE, Some(OpcodeType::OP_JFALSE)) | (OpcodeType::OP_FALSE, Some(OpcodeType::OP_JTRUE)) => {
+				ops[i].live = false;
+				ops[j].live = false;
+				changed = true;
+				i = next_live(ops, j + 1);
+				continue;
+			},
+			(OpcodeType::OP_JUMP, _) if ops[i].operand == j => {
+				ops[i].live = false;
+				changed = true;
+			},
+			_ => {},
+		}
+		i = j;
+	}
+	return changed;
+}
+
+impl VMFunction {
+	fn small_integer(&self, id: usize) -> Option<u16> {
+		let v = self.num_tab[id];
+		if v.fract() == 0.0 && (0.0..=65535.0).contains(&v) && !(v == 0.0 && v.is_sign_negative()) {
+			return Some(v as u16);
+		}
+		return None;
+	}
+
+	/* Rewrite the code of the function and its nested functions, the code is made by compile_func */
+	pub(crate) fn peephole(&mut self) {
+		let mut ops: Vec<PeepholeOp> = Vec::new();
+		let mut index = vec![usize::MAX; self.code.len() + 1];
+		let mut pc = 0;
+		while pc < self.code.len() {
+			let op = match self.decode(pc) {
+				Ok(op) => op,
+				Err(_) => return,
+			};
+			index[pc] = ops.len();
+			ops.push(PeepholeOp {
+				op: op.op,
+				operand: op.operand,
+				width: op.next - pc - 1,
+				live: true,
+			});
+			pc = op.next;
+		}
+		index[pc] = ops.len();
+		for op in ops.iter_mut() {
+			if is_branch(op.op) {
+				if op.operand >= index.len() || index[op.operand] == usize::MAX {
+					return;
+				}
+				op.operand = index[op.operand];
+			}
+		}
+
+		for op in ops.iter_mut() {
+			if op.op == OpcodeType::OP_NUMBER {
+				if let Some(v) = self.small_integer(op.operand) {
+					op.op = OpcodeType::OP_INTEGER;
+					op.operand = v as usize;
+				}
+			}
+		}
+		loop {
+			thread_jumps(&mut ops);
+			let dropped = drop_unreachable(&mut ops);
+			if !rewrite_patterns(&mut ops) && !dropped {
+				break;
+			}
+		}
+
+		/* only the numbers still used stay in the table */
+		let mut num_ids = vec![usize::MAX; self.num_tab.len()];
+		let mut num_tab = Vec::new();
+		for op in ops.iter_mut() {
+			if op.live && op.op == OpcodeType::OP_NUMBER {
+				if num_ids[op.operand] == usize::MAX {
+					num_ids[op.operand] = num_tab.len();
+					num_tab.push(self.num_tab[op.operand]);
+				}
+				op.operand = num_ids[op.operand];
+			}
+		}
+
+		/* new addresses, a dropped instruction moves to the next live one */
+		let mut address = vec![0; ops.len() + 1];
+		let mut pc = 0;
+		for i in 0..ops.len() {
+			address[i] = pc;
+			if ops[i].live {
+				pc = pc + 1 + ops[i].width;
+			}
+		}
+		address[ops.len()] = pc;
+
+		let mut code: Vec<u16> = Vec::with_capacity(pc);
+		for op in ops.iter() {
+			if !op.live {
+				continue;
+			}
+			code.push(op.op as u16);
+			match op.width {
+				2 => {
+					let addr = address[op.operand];
+					code.push((addr & 0xFFFF) as u16);
+					code.push(((addr >> 16) & 0xFFFF) as u16);
+				},
+				1 => code.push(op.operand as u16),
+				_ => {},
+			}
+		}
+		self.code = code;
+		self.num_tab = num_tab;
+
+		for sub in self.func_tab.iter_mut() {
+			if let Some(sub) = Rc::get_mut(sub) {
+				sub.peephole();
+			}
+		}
+	}
+}
@@ -1,10 +1,22 @@
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::cell::RefCell;
 use std::cmp;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::common::*;
+use crate::error::*;
 use crate::bytecode::*;
 use crate::value::*;
+use crate::shape::*;
+use crate::gc::*;
+use crate::module::*;
+
+/* limit of nested calls, deeper recursion throws an exception before overflowing the native stack */
+pub const JS_CALL_LIMIT: usize = 512;
+/* limit of native stack used by nested calls, a call through a getter or a builtin takes more of it than a plain call */
+pub const JS_NATIVE_STACK_LIMIT: usize = 4 * 1024 * 1024;
 
 #[allow(non_camel_case_types)]
 pub struct JsPrototype {
@@ -14,18 +26,81 @@ pub struct JsPrototype {
 	pub array_prototype:	SharedObject,
 	pub function_prototype: SharedObject,
 
-	/* prototype for exceptions */
+	/* prototype for exceptions, TypeError inherits Error */
 	pub exception_prototype: SharedObject,
+	pub type_error_prototype: SharedObject,
+}
+
+/* a handle to stop a running script from another thread or a signal handler,
+   the flag is dropped when the host starts the next script or call */
+#[derive(Clone)]
+pub struct JsInterruptHandle {
+	flag:	Arc<AtomicBool>,
+}
+
+impl JsInterruptHandle {
+	pub fn interrupt(&self) {
+		self.flag.store(true, Ordering::Relaxed);
+	}
 }
 
 pub trait Hookable : Clone + Sized {
 	fn name(&self) -> String;
+
+	/* called once when the hook object is unreachable or the runtime is dropped, before Drop of T */
+	fn finalize(&mut self) {
+	}
+
+	/* Interception of the hook object, the default methods fall back to the own properties of the object.
+	   The hook is taken out of the runtime while it runs, a nested access to the same hook isn't intercepted. */
+	fn get_property(&mut self, _rt: &mut JsRuntime<Self>, _name: &str) -> Result<Option<SharedValue>, JsError> {
+		return Ok(None);
+	}
+	/* returns true when the value is taken */
+	fn set_property(&mut self, _rt: &mut JsRuntime<Self>, _name: &str, _value: SharedValue) -> Result<bool, JsError> {
+		return Ok(false);
+	}
+	fn has_property(&mut self, _rt: &mut JsRuntime<Self>, _name: &str) -> Result<Option<bool>, JsError> {
+		return Ok(None);
+	}
+	fn delete_property(&mut self, _rt: &mut JsRuntime<Self>, _name: &str) -> Result<Option<bool>, JsError> {
+		return Ok(None);
+	}
+	/* keys enumerated by for-in */
+	fn own_keys(&mut self, _rt: &mut JsRuntime<Self>) -> Option<Vec<String>> {
+		return None;
+	}
+	/* called like a builtin function, the result must be pushed to the stack */
+	fn call(&mut self, rt: &mut JsRuntime<Self>, _argc: usize) -> Result<(), JsError> {
+		return Err(rt.new_type_error(format!("hook '{}' is not a function", self.name())));
+	}
+}
+
+/* a host closure, it can capture its own Rust state */
+pub type JsHostFunction<T> = Box<dyn FnMut(&mut JsRuntime<T>, usize) -> Result<(), JsError>>;
+
+/* a class defined by the host, instances are hook objects created by the constructor,
+   methods and getters get the hook of 'this' and push their result like a builtin */
+pub type JsClassConstructor<T> = fn(&mut JsRuntime<T>, usize) -> Result<T, JsError>;
+pub type JsClassMethod<T> = fn(&mut T, &mut JsRuntime<T>, usize) -> Result<(), JsError>;
+
+/* a hook type which holds instances of the host class C, like an enum of the classes of a runtime,
+   every Hookable holds itself */
+pub trait JsClassHook<C> : Hookable + From<C> {
+	fn class_mut(&mut self) -> Option<&mut C>;
+}
+
+impl<T: Hookable> JsClassHook<T> for T {
+	fn class_mut(&mut self) -> Option<&mut T> {
+		return Some(self);
+	}
 }
 
 #[allow(non_camel_case_types)]
 #[derive(Clone)]
-pub struct JsBuiltinFunction<T> where T: Hookable {
-	pub f:		fn(&mut JsRuntime<T>, usize),
+pub enum JsBuiltinFunction<T> where T: Hookable {
+	native(fn(&mut JsRuntime<T>, usize) -> Result<(), JsError>),
+	closure(Rc<RefCell<JsHostFunction<T>>>),
 }
 
 #[allow(non_camel_case_types)]
@@ -37,6 +112,24 @@ pub struct JsRuntime<T> where T: Hookable  {
 	pub cenv:			SharedScope,
 
 	pub stack:			Vec<SharedValue>,
+	pub call_depth:		usize,
+	/* address on the native stack of the outermost call */
+	pub(crate) native_base:	usize,
+	/* instruction budget, None means unlimited */
+	pub fuel:			Option<u64>,
+	pub interrupt:		Arc<AtomicBool>,
+	/* approximate memory of objects created by script, None limit means unlimited */
+	pub heap:			SharedHeap,
+	pub memory_limit:	Option<usize>,
+	/* usage stayed over the limit after a collection, the next one waits until it's under again */
+	pub(crate) memory_over:	bool,
+
+	/* modules loaded by require, keyed by resolved id, and the id of each module's require builtin */
+	pub module_loader:	Option<Box<dyn ModuleLoader>>,
+	pub modules:		HashMap<String, SharedValue>,
+	pub module_ids:		HashMap<usize, String>,
+	/* modules loaded by import, keyed by resolved id */
+	pub es_modules:		HashMap<String, JsModule>,
 
 	pub hooks:			HashMap<u64, T>,
 	pub hooks_id:		u64,
@@ -44,6 +137,17 @@ pub struct JsRuntime<T> where T: Hookable  {
 }
 
 
+/* remaining hooks are finalized with the runtime */
+impl<T: Hookable> Drop for JsRuntime<T> {
+	fn drop(&mut self) {
+		self.stack.clear();
+		self.finalize_hooks();
+		for (_, hook) in self.hooks.iter_mut() {
+			hook.finalize();
+		}
+	}
+}
+
 /* implementation for JsRuntime and jscall */
 impl<T: Hookable> JsRuntime<T> {
 	/* hooks */
@@ -52,12 +156,18 @@ impl<T: Hookable> JsRuntime<T> {
 		self.hooks_id = hid + 1;
 		self.hooks.insert(hid, hook);
 
-		JsObject {
+		let mut obj = JsObject {
 			extensible:	false,
 			__proto__: None,
-			properties: HashMap::new(),
+			shape: Shape::empty(),
+			slots: Vec::new(),
 			value: JsClass::hook(hid),
-		}
+			heap: None,
+			charged: 0,
+		};
+		/* the heap is also needed to release the hook when the object is dropped */
+		obj.charge(&self.heap, JS_OBJECT_COST);
+		return obj;
 	}
 	pub fn get_hook<'a>(&'a mut self, v: &SharedValue) -> &'a T {
 		let obj = v.get_object();
@@ -67,15 +177,97 @@ impl<T: Hookable> JsRuntime<T> {
 		return self.hooks.get(&hid).unwrap();
 	}
 
-	fn check_hook_replace(&mut self, v: &SharedValue) {
-		if v.is_object() {
-			if v.get_object().borrow().is_hook() {
-				if SharedObject::strong_count(&v.get_object()) == 2 {
-					let hid = v.get_object().borrow().get_hook();
-					self.hooks.remove(&hid);
+	/* runs f with the hook taken out of the runtime, the caller holds the hook object so it can't be finalized meanwhile */
+	pub(crate) fn with_hook<R, F: FnOnce(&mut T, &mut JsRuntime<T>) -> R>(&mut self, hid: u64, f: F) -> Option<R> {
+		let mut hook = self.hooks.remove(&hid)?;
+		let r = f(&mut hook, self);
+		self.hooks.insert(hid, hook);
+		return Some(r);
+	}
+
+	/* hooks of dropped objects are finalized here, outside of any borrow of the object graph */
+	pub fn finalize_hooks(&mut self) {
+		if self.heap.released_hooks.borrow().is_empty() {
+			return;
+		}
+		loop {
+			let hid = match self.heap.released_hooks.borrow_mut().pop() {
+				Some(hid) => hid,
+				None => break,
+			};
+			if let Some(mut hook) = self.hooks.remove(&hid) {
+				hook.finalize();
+			}
+		}
+	}
+
+	/* instruction budget */
+	pub fn set_fuel(&mut self, fuel: Option<u64>) {
+		self.fuel = fuel;
+	}
+	pub fn add_fuel(&mut self, n: u64) {
+		if let Some(fuel) = self.fuel {
+			self.fuel = Some(fuel.saturating_add(n));
+		}
+	}
+
+	/* interrupt */
+	pub fn interrupt_handle(&self) -> JsInterruptHandle {
+		JsInterruptHandle {
+			flag: self.interrupt.clone(),
+		}
+	}
+	/* the flag is cleared once it is reported, so the runtime can run again */
+	pub fn check_interrupt(&self) -> Result<(), JsError> {
+		if self.interrupt.load(Ordering::Relaxed) {
+			self.interrupt.store(false, Ordering::Relaxed);
+			return Err(JsError::Interrupted);
+		}
+		return Ok(());
+	}
+
+	/* memory accounting */
+	pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+		self.memory_limit = limit;
+		self.memory_over = false;
+	}
+	pub fn memory_usage(&self) -> usize {
+		return self.heap.used.get();
+	}
+	pub fn check_memory(&mut self) -> Result<(), JsError> {
+		if let Some(limit) = self.memory_limit {
+			if self.heap.used.get() <= limit {
+				self.memory_over = false;
+				return Ok(());
+			}
+			/* unreachable cycles are collected once when usage crosses the limit, before giving up */
+			if !self.memory_over {
+				self.gc();
+				if self.heap.used.get() <= limit {
+					return Ok(());
 				}
+				self.memory_over = true;
 			}
+			return Err(JsError::OutOfMemory);
 		}
+		return Ok(());
+	}
+
+	/* cycle collection, returns the number of objects reclaimed */
+	pub fn gc(&mut self) -> usize {
+		let n = collect_cycles();
+		self.heap.collections.set(self.heap.collections.get() + 1);
+		self.heap.collected.set(self.heap.collected.get() + n);
+		self.finalize_hooks();
+		return n;
+	}
+	pub fn heap_stats(&self) -> JsHeapStats {
+		return JsHeapStats {
+			objects: self.heap.objects.get(),
+			used: self.heap.used.get(),
+			collections: self.heap.collections.get(),
+			collected: self.heap.collected.get(),
+		};
 	}
 
 	/* builtins */
@@ -85,8 +277,11 @@ impl<T: Hookable> JsRuntime<T> {
 		JsObject {
 			extensible:	false,
 			__proto__: Some(self.prototypes.function_prototype.clone()),
-			properties: HashMap::new(),
+			shape: Shape::empty(),
+			slots: Vec::new(),
 			value: JsClass::builtin(fid),
+			heap: None,
+			charged: 0,
 		}
 	}
 
@@ -108,7 +303,7 @@ impl<T: Hookable> JsRuntime<T> {
 		}
 	}
 
-	fn getvariable(&mut self, name: &str) -> Result<bool, JsException> {
+	fn getvariable(&mut self, name: &str) -> Result<bool, JsError> {
 		let mut env: SharedScope = self.cenv.clone();
 		loop {
 			let r = env.borrow().query_variable(name);
@@ -125,13 +320,17 @@ impl<T: Hookable> JsRuntime<T> {
 		}
 	}
 
-	fn setvariable(&mut self, name: &str) -> Result<(), JsException> {
+	fn setvariable(&mut self, name: &str) -> Result<(), JsError> {
 		let mut env: SharedScope = self.cenv.clone();
 		loop {
 			let r = env.borrow().query_variable(name);
 			if r {
 				let mut prop = env.borrow().get_variable(name);
-				self.check_hook_replace(&prop.value);
+				/* imported bindings of a module are the only read only variables out of the global scope,
+				   globals like Object are replaced as before */
+				if !prop.writeable() && env.borrow().outer.is_some() {
+					return Err(self.new_type_error(format!("Assignment to read only variable '{}'", name)));
+				}
 				prop.value.replace(self.top(-1));
 				return Ok(());
 			}
@@ -145,7 +344,6 @@ impl<T: Hookable> JsRuntime<T> {
 		let value = self.top(-1);
 		self.cenv.borrow().put_variable(name);
 		let mut prop = self.cenv.borrow().get_variable(name);
-		self.check_hook_replace(&prop.value);
 		prop.value.replace(value);
 		self.cenv.borrow().set_variable(name, prop);
 
@@ -155,15 +353,21 @@ impl<T: Hookable> JsRuntime<T> {
 	/* properties operation */
     // make a new  or replace proptery o for object, following reler of object's attr
     fn defproperty(&mut self, target_: SharedObject, name: &str, value: SharedValue,
-		attr:JsPropertyAttr, getter: Option<SharedObject>, setter: Option<SharedObject>) -> Result<(), JsException> {
-		let mut target = target_.borrow_mut();
-
+		attr:JsPropertyAttr, getter: Option<SharedObject>, setter: Option<SharedObject>) -> Result<(), JsError> {
+		/* the target may be its own getter or setter, they are checked before it's borrowed */
+		let setter_callable = setter.as_ref().is_some_and(|setter| setter.borrow().callable());
+		let getter_callable = getter.as_ref().is_some_and(|getter| getter.borrow().callable());
 		// value/setter/getter/attr can't be conflicted
-		if getter.is_some() || setter.is_some() {
-			assert!( value.is_undefined() );
+		if (getter.is_some() || setter.is_some()) && !value.is_undefined() {
+			return Err(self.new_type_error(format!("property {} can't have both a value and an accessor", name)));
 		}
-		if attr.0 == false && value.is_undefined() {
-			assert!( setter.is_some() );
+		if attr.0 == false && value.is_undefined() && setter.is_none() {
+			return Err(self.new_type_error(format!("read-only property {} needs a value or a setter", name)));
+		}
+
+		let mut target = target_.borrow_mut();
+		if target.shape.slot(name).is_none() {
+			target.charge(&self.heap, JS_PROPERTY_COST + name.len());
 		}
 
 		if target.put_property(name) {
@@ -174,14 +378,14 @@ impl<T: Hookable> JsRuntime<T> {
 			if prop.configable() {
 				prop.fill_attr(attr);
 				if let Some(setter) = setter {
-					if setter.borrow().callable() {
+					if setter_callable {
 						prop.setter = Some(setter);
 					} else {
 						println!("setter should be callable");
 					}
 				}
 				if let Some(getter) = getter {
-					if getter.borrow().callable() {
+					if getter_callable {
 						prop.getter = Some(getter);
 					} else {
 						println!("getter should be callable");
@@ -191,22 +395,32 @@ impl<T: Hookable> JsRuntime<T> {
 			target.set_property(name, prop);
 			return Ok(());
 		} else {
-			return Err(JsException::new(format!("runtime TODO: {}", line!())));
+			return Err(self.new_error(format!("runtime TODO: {}", line!())));
 		}
 	}
 
 	// change value of the proptery for object
-	fn setproperty(&mut self, target_: SharedObject, name: &str, value: SharedValue) -> Result<(), JsException> {
+	fn setproperty(&mut self, target_: SharedObject, name: &str, value: SharedValue) -> Result<(), JsError> {
+		let hid = target_.borrow().hook_id();
+		if let Some(hid) = hid {
+			if let Some(r) = self.with_hook(hid, |hook, rt| hook.set_property(rt, name, value.clone())) {
+				if r? {
+					return Ok(());
+				}
+			}
+		}
 
 		if target_.borrow().is_array() {
 			if let Some(number) = str_to_integer(name) {
 				let number = number as usize;
 				let mut obj = target_.borrow_mut();
-				let array = obj.get_mut_array();
-				if number == array.len() {
-					array.push(value);
+				if number == obj.get_array().len() {
+					obj.get_mut_array().push(value);
+					obj.charge(&self.heap, JS_VALUE_COST);
 					return Ok(());
-				} else if number < array.len() {
+				}
+				let array = obj.get_mut_array();
+				if number < array.len() {
 					array[number] = value;
 					return Ok(());
 				}
@@ -214,23 +428,27 @@ impl<T: Hookable> JsRuntime<T> {
 		}
 
 		let prop_r = target_.borrow().query_property(name);
-		if let Some((mut prop, own)) = prop_r {
+		return self.setfound(target_, name, value, prop_r);
+	}
+
+	/* set a property found on target or its prototypes, a new one is created on target when it's not own */
+	fn setfound(&mut self, target_: SharedObject, name: &str, value: SharedValue, found: Option<(JsProperty, bool)>) -> Result<(), JsError> {
+		if let Some((mut prop, own)) = found {
 			if let Some(setter) = prop.setter {
 				self.push_object(setter.clone());
 				self.push_object(target_.clone());
 				self.push(value);
 				jscall(self, 1)?;
-				self.pop(1);
+				self.pop(1)?;
 				return Ok(());
 			}
             if own {
                 if prop.writeable() {
-                    self.check_hook_replace(&prop.value);
                     prop.value.replace(value);
                     return Ok(());
                 } else {
                     println!("Cant write property for specia object!");
-                    return Err(JsException::new(format!("TODO: {}", line!())));
+                    return Err(self.new_error(format!("TODO: {}", line!())));
                 }
             }
 		}
@@ -241,14 +459,23 @@ impl<T: Hookable> JsRuntime<T> {
 	}
 
 	// get value from the proptery of object
-	fn getproperty(&mut self, target: SharedObject, name: &str) -> Result<bool, JsException> {
+	fn getproperty(&mut self, target: SharedObject, name: &str) -> Result<bool, JsError> {
+		let hid = target.borrow().hook_id();
+		if let Some(hid) = hid {
+			if let Some(r) = self.with_hook(hid, |hook, rt| hook.get_property(rt, name)) {
+				if let Some(jv) = r? {
+					self.push(jv);
+					return Ok(true);
+				}
+			}
+		}
 
 		// get value from index
 		match target.borrow().value {
 			JsClass::string(ref s) => {
 				if let Ok(idx) = name.parse::<usize>() {
-					if idx < s.len() {
-						self.push_string( s[idx..idx+1].to_string() );
+					if let Some(c) = s.chars().nth(idx) {
+						self.push_string( c.to_string() );
 						return Ok(true);
 					}
 				}
@@ -264,7 +491,12 @@ impl<T: Hookable> JsRuntime<T> {
 			_ => {}
 		}
 		let prop_r = target.borrow().query_property(name);
-		if let Some((prop, _own)) = prop_r {
+		return self.getfound(target, prop_r);
+	}
+
+	/* push the value of a property found on target or its prototypes, undefined when it's not found */
+	fn getfound(&mut self, target: SharedObject, found: Option<(JsProperty, bool)>) -> Result<bool, JsError> {
+		if let Some((prop, _own)) = found {
 			if let Some(getter) = prop.getter {
 				self.push_object(getter.clone());
 				self.push_object(target);
@@ -278,14 +510,43 @@ impl<T: Hookable> JsRuntime<T> {
 		return Ok(false);
 	}
 
-    fn delproperty(&mut self, target_: SharedObject, name: &str) -> bool {
+	/* getproperty of OP_GETPROP_S, a hit of the inline cache reads the slot without looking up the prototypes */
+	fn getproperty_cached(&mut self, target: SharedObject, name: &str, caches: &InlineCaches, entry: usize) -> Result<bool, JsError> {
+		if let Some(found) = caches.lookup(entry, &target) {
+			return self.getfound(target, Some(found));
+		}
+		let r = self.getproperty(target.clone(), name)?;
+		caches.fill(entry, &target, name);
+		return Ok(r);
+	}
+
+	/* setproperty of OP_SETPROP_S with the inline cache */
+	fn setproperty_cached(&mut self, target: SharedObject, name: &str, value: SharedValue, caches: &InlineCaches, entry: usize) -> Result<(), JsError> {
+		if let Some(found) = caches.lookup(entry, &target) {
+			return self.setfound(target, name, value, Some(found));
+		}
+		self.setproperty(target.clone(), name, value)?;
+		caches.fill(entry, &target, name);
+		return Ok(());
+	}
+
+    fn delproperty(&mut self, target_: SharedObject, name: &str) -> Result<bool, JsError> {
+		let hid = target_.borrow().hook_id();
+		if let Some(hid) = hid {
+			if let Some(r) = self.with_hook(hid, |hook, rt| hook.delete_property(rt, name)) {
+				if let Some(b) = r? {
+					return Ok(b);
+				}
+			}
+		}
+
 		let mut target = target_.borrow_mut();
 
 		match target.value {
 			JsClass::object => {},
 			_ => {
 				println!("Cant delete property for specia object!");
-				return false;
+				return Ok(false);
 			}
 		}
 
@@ -294,37 +555,38 @@ impl<T: Hookable> JsRuntime<T> {
 			if own {
 				if prop.configable() {
 					target.drop_property(name);
-					return true;
+					return Ok(true);
 				}
 			}
 		}
-		return false;
+		return Ok(false);
 	}
 
 	/* item + item */
-	fn concat_add(&mut self) {
+	fn concat_add(&mut self) -> Result<(), JsError> {
 		let x = self.top(-2);
 		let y = self.top(-1);
-		self.pop(2);
+		self.pop(2)?;
 
 		if x.is_number() {
 			let x = x.to_number();
 			let y = y.to_number();
 			self.push_number(x+y);
-			return;
+			return Ok(());
 		}
 
 		let x = x.to_string();
 		let y = y.to_string();
 
 		self.push_string( x + &y);
+		return Ok(());
 	}
 
 	/* item op item */
-	fn equal(&mut self) -> bool {
+	fn equal(&mut self) -> Result<bool, JsError> {
 		let x = self.top(-2);
 		let y = self.top(-1);
-		self.pop(2);
+		self.pop(2)?;
 
 		// string with others
 		if x.is_string() {
@@ -332,48 +594,48 @@ impl<T: Hookable> JsRuntime<T> {
 			if y.is_string() {
 				let y_str = y.to_string();
 				if x_str == y_str {
-					return true;
+					return Ok(true);
 				} else {
-					return false;
+					return Ok(false);
 				}
 			} else if y.is_number() {
 				let y_str = y.to_number().to_string();
 				if x_str == y_str {
-					return true;
+					return Ok(true);
 				} else {
-					return false;
+					return Ok(false);
 				}
 			}
-			return false;
+			return Ok(false);
 		}
 
 		// null with defineded
 		if x.is_undefined() {
 			if y.is_undefined() {
-				return true;
+				return Ok(true);
 			}
 			if y.is_null() {
-				return true;
+				return Ok(true);
 			}
-			return false;
+			return Ok(false);
 		}
 
 		if x.is_null() {
 			if y.is_undefined() {
-				return true;
+				return Ok(true);
 			}
 			if y.is_null() {
-				return true;
+				return Ok(true);
 			}
-			return false;
+			return Ok(false);
 		}
 
 		// boolean with boolean
 		if x.is_boolean()  {
 			if y.is_boolean() {
-				return x.to_boolean() == y.to_boolean();
+				return Ok(x.to_boolean() == y.to_boolean());
 			}
-			return false;
+			return Ok(false);
 		}
 
 		// number with others
@@ -382,27 +644,27 @@ impl<T: Hookable> JsRuntime<T> {
 			if y.is_number() {
 				let y_num = y.to_number();
 				if x_num == y_num {
-					return true;
+					return Ok(true);
 				} else {
-					return false;
+					return Ok(false);
 				}
 			}
 			if y.is_string() {
 				let y_str = y.to_string();
 				if let Ok(y_num) = y_str.parse::<f64>() {
-					return x_num == y_num;
+					return Ok(x_num == y_num);
 				}
 			}
-			return false;
+			return Ok(false);
 		}
 
 		// object with object
 		let x_obj = x.get_object();
 		if y.is_object() {
 			let y_obj = y.get_object();
-			return Rc::ptr_eq(&x_obj, &y_obj);
+			return Ok(Rc::ptr_eq(&x_obj, &y_obj));
 		}
-		return false;
+		return Ok(false);
 
 	}
 
@@ -466,43 +728,43 @@ impl<T: Hookable> JsRuntime<T> {
 		return false;
 	}
 
-	fn compare_item(&mut self) -> Option<i32> {
+	fn compare_item(&mut self) -> Result<Option<i32>, JsError> {
 		let x = self.top(-2);
 		let y = self.top(-1);
-		self.pop(2);
+		self.pop(2)?;
 
 		if x.is_number() {
 			let x = x.to_number();
 			let y = y.to_number();
 			if x == f64::NAN || y == f64::NAN {
-				return None;
+				return Ok(None);
 			}
 			if x > y {
-				return Some(1);
+				return Ok(Some(1));
 			} else if x == y {
-				return Some(0);
+				return Ok(Some(0));
 			} else  {
-				return Some(-1);
+				return Ok(Some(-1));
 			}
 		}
 		if x.is_string() {
 			let x = x.to_string();
 			let y = y.to_string();
 			if x > y {
-				return Some(1);
+				return Ok(Some(1));
 			} else if x == y {
-				return Some(0);
+				return Ok(Some(0));
 			} else  {
-				return Some(-1);
+				return Ok(Some(-1));
 			}
 		}
-		return None;
+		return Ok(None);
 	}
 
-	fn in_operator(&mut self) -> Result<(), JsException> {
+	fn in_operator(&mut self) -> Result<(), JsError> {
 		let x = self.top(-2);
 		let y = self.top(-1);
-		self.pop(2);
+		self.pop(2)?;
 
 		if !y.is_object() {
 			println!("in: invalid operand");
@@ -511,7 +773,17 @@ impl<T: Hookable> JsRuntime<T> {
 		}
 
 		let propstr = x.to_string();
-		if let Some((_prop, _own)) = y.get_object().borrow().query_property(&propstr) {
+		let target = y.get_object();
+		let hid = target.borrow().hook_id();
+		if let Some(hid) = hid {
+			if let Some(r) = self.with_hook(hid, |hook, rt| hook.has_property(rt, &propstr)) {
+				if let Some(b) = r? {
+					self.push_boolean(b);
+					return Ok(());
+				}
+			}
+		}
+		if let Some((_prop, _own)) = target.borrow().query_property(&propstr) {
 			self.push_boolean(true);
 			return Ok(());
 		}
@@ -520,10 +792,10 @@ impl<T: Hookable> JsRuntime<T> {
 		return Ok(());
 	}
 
-	fn instanceof(&mut self) -> Result<(), JsException> {
+	fn instanceof(&mut self) -> Result<(), JsError> {
 		let x = self.top(-2);
 		let y = self.top(-1);
-		self.pop(2);
+		self.pop(2)?;
 
 		if !x.is_object() {
 			self.push_boolean(false);
@@ -544,7 +816,7 @@ impl<T: Hookable> JsRuntime<T> {
 
 		self.getproperty(y, "prototype")?;
 		let o = self.top(-1);
-		self.pop(1);
+		self.pop(1)?;
 		if !o.is_object() {
 			println!("instanceof: 'prototype' property is not an object");
 			self.push_boolean(false);
@@ -575,19 +847,69 @@ impl<T: Hookable> JsRuntime<T> {
 		let value = SharedValue::new_object(obj);
 		self.push(value);
 	}
+	pub fn new_error(&self, msg: String) -> JsError {
+		let obj = JsObject::new_exception(self.prototypes.exception_prototype.clone(), JsException::new(msg));
+		return JsError::Uncaught(SharedValue::new_object(obj));
+	}
+	/* an instance of TypeError, the message still starts with "TypeError: " */
+	pub fn new_type_error(&self, msg: String) -> JsError {
+		let e = JsException::new(format!("TypeError: {}", msg));
+		let obj = JsObject::new_exception(self.prototypes.type_error_prototype.clone(), e);
+		return JsError::Uncaught(SharedValue::new_object(obj));
+	}
+
+	/* call a JavaScript function from host, the stack is balanced on both success and exception */
+	pub fn call(&mut self, func: &SharedValue, thiz: SharedValue, args: &[SharedValue]) -> Result<SharedValue, JsError> {
+		let top = self.stack.len();
+		self.push(func.clone());
+		self.push(thiz);
+		for arg in args {
+			self.push(arg.clone());
+		}
+
+		let result = jscall(self, args.len());
+		if let Err(e) = result {
+			self.stack.truncate(top);
+			return Err(e);
+		}
+
+		let value = self.top(-1);
+		self.stack.truncate(top);
+		return Ok(value);
+	}
+
+	/* call a global function by name, 'this' is undefined like a plain call in script */
+	pub fn call_global(&mut self, name: &str, args: &[SharedValue]) -> Result<SharedValue, JsError> {
+		let top = self.stack.len();
+		let target = self.genv.borrow().target();
+		let found = self.getproperty(target, name);
+		let func = if self.stack.len() > top { Some(self.top(-1)) } else { None };
+		self.stack.truncate(top);
+
+		let func = match (found?, func) {
+			(true, Some(func)) => func,
+			_ => {
+				return Err(self.new_error(format!("'{}' is not defined", name)));
+			}
+		};
+		return self.call(&func, SharedValue::new_undefined(), args);
+	}
 
 	/* create new object */
-	fn new_call(&mut self, argc: usize) -> Result<(), JsException> {
-		let obj = self.top(-1 - argc as isize).get_object();
+	fn new_call(&mut self, argc: usize) -> Result<(), JsError> {
+		let obj = self.top(-1 - argc as isize);
+		if !obj.is_object() || !obj.get_object().borrow().callable() {
+			return Err(self.new_error("Can't new on none function value".to_string()));
+		}
+		let obj = obj.get_object();
 
 		/* built-in constructors create their own objects, give them a 'null' this */
 		if obj.borrow().is_builtin() {
 			self.push_null();
 			if argc > 0 {
-				self.rot(argc+1);
+				self.rot(argc+1)?;
 			}
-			jscall_builtin(self, argc);
-			return Ok(());
+			return jscall_builtin(self, argc);
 		}
 
 		/* extract the function object's prototype property */
@@ -599,15 +921,16 @@ impl<T: Hookable> JsRuntime<T> {
 			self.prototypes.object_prototype.clone()
 		};
 
-		self.pop(1);
+		self.pop(1)?;
 
 		/* create a new object with above prototype, and shift it into the 'this' slot */
 		let mut nobj = JsObject::new();
 		nobj.__proto__ = Some(proto);
+		nobj.charge(&self.heap, JS_OBJECT_COST);
 		let nobj = SharedObject_new(nobj);
 		self.push_object(nobj.clone());
 		if argc > 0 {
-			self.rot(argc+1);
+			self.rot(argc+1)?;
 		}
 
 		/* call the function */
@@ -615,14 +938,16 @@ impl<T: Hookable> JsRuntime<T> {
 
 		/* if result is not an object, return the original object we created */
 		if !self.top(-1).is_object() {
-			self.pop(1);
+			self.pop(1)?;
 			self.push_object(nobj);
 		}
 		return Ok(());
 	}
 
 	pub fn new_closure(&mut self, f: SharedFunction) {
-		let fobj = SharedObject_new(JsObject::new_function(f.clone(), self.cenv.clone(), self.prototypes.function_prototype.clone()));
+		let mut fobj = JsObject::new_function(f.clone(), self.cenv.clone(), self.prototypes.function_prototype.clone());
+		fobj.charge(&self.heap, JS_OBJECT_COST * 2 + JS_PROPERTY_COST * 2);
+		let fobj = SharedObject_new(fobj);
 
 		// prototype object self
 		let mut prop = JsProperty::new();
@@ -631,17 +956,46 @@ impl<T: Hookable> JsRuntime<T> {
 		let mut prototype_obj = JsObject::new();
     	prototype_obj.extensible = true;
 		prototype_obj.__proto__ = Some(self.prototypes.object_prototype.clone());
-		prototype_obj.properties.insert("constructor".to_string(), prop );
+		prototype_obj.set_property("constructor", prop);
 
 		// binding prototype to function object
 		let prototype_obj = SharedObject_new(prototype_obj);
 		let mut prop = JsProperty::new();
 		prop.value = SharedValue::new_sobject(prototype_obj.clone());
-		fobj.borrow_mut().properties.insert("prototype".to_string(), prop);
+		fobj.borrow_mut().set_property("prototype", prop);
 
 		self.push(SharedValue::new_sobject(fobj));
 	}
 
+	/* string values need the runtime's prototype */
+	pub fn new_string(&self, v: String) -> SharedValue {
+		let size = JS_OBJECT_COST + v.len();
+		let jclass = JsClass::string(v);
+		let mut jobj = JsObject::new_with(self.prototypes.string_prototype.clone(), jclass);
+		jobj.charge(&self.heap, size);
+		return SharedValue::new_object(jobj);
+	}
+
+	/* arrays and objects made from Rust values, charged to the heap like the ones made by scripts */
+	pub(crate) fn new_array(&self, items: Vec<SharedValue>) -> SharedValue {
+		let mut obj = JsObject::new_array(self.prototypes.array_prototype.clone());
+		obj.charge(&self.heap, JS_OBJECT_COST + JS_VALUE_COST * items.len());
+		*obj.get_mut_array() = items;
+		return SharedValue::new_object(obj);
+	}
+	pub(crate) fn new_object(&self, props: Vec<(String, SharedValue)>) -> SharedValue {
+		let mut obj = JsObject::new_with(self.prototypes.object_prototype.clone(), JsClass::object);
+		let mut size = JS_OBJECT_COST;
+		for (k, v) in props {
+			let mut prop = JsProperty::new();
+			prop.fill(v, JS_DEFAULT_ATTR, None, None);
+			size = size + JS_PROPERTY_COST + k.len();
+			obj.set_property(&k, prop);
+		}
+		obj.charge(&self.heap, size);
+		return SharedValue::new_object(obj);
+	}
+
 	/* stack operations */
 	pub fn top(&self, offset: isize) -> SharedValue {
 		if offset < 0 {
@@ -670,103 +1024,109 @@ impl<T: Hookable> JsRuntime<T> {
 		self.stack.push(jv);
 	}
 	pub fn push_string(&mut self, v:String) {
-		let jclass = JsClass::string(v);
-		let jobj = JsObject::new_with(self.prototypes.string_prototype.clone(), jclass);
-		let jv = SharedValue::new_object(jobj);
+		let jv = self.new_string(v);
 		self.stack.push(jv);
 	}
 	pub fn push_object(&mut self, target: SharedObject) {
 		let jv = SharedValue::new_sobject(target);
 		self.stack.push(jv);
 	}
-	fn push_from(&mut self, from: usize) {
+	fn push_from(&mut self, from: usize) -> Result<(), JsError> {
 		if from >= self.stack.len() {
-			panic!("stack underflow! @ push_from");
+			return Err(JsError::Aborted("stack underflow @ push_from".to_string()));
 		}
 		let jv = SharedValue::clone( &self.stack[from] );
 		self.stack.push(jv);
+		return Ok(());
 	}
 
 	/* opcode helper*/
-	fn pop(&mut self, mut n: usize) {
+	fn pop(&mut self, mut n: usize) -> Result<(), JsError> {
 		if n > self.stack.len() {
-			panic!("stack underflow! @ pop");
+			return Err(JsError::Aborted("stack underflow @ pop".to_string()));
 		}
 		while n > 0 {
 			self.stack.pop();
 			n = n - 1;
 		}
+		return Ok(());
 	}
-	fn dup(&mut self) {
+	fn dup(&mut self) -> Result<(), JsError> {
 		if self.stack.len() < 1 {
-			panic!("stack underflow! @ dup");
+			return Err(JsError::Aborted("stack underflow @ dup".to_string()));
 		}
 		let nv = self.top(-1);
 		self.stack.push(nv);
+		return Ok(());
 	}
-	fn dup2(&mut self) {
+	fn dup2(&mut self) -> Result<(), JsError> {
 		if self.stack.len() < 2 {
-			panic!("stack underflow! @ dup2");
+			return Err(JsError::Aborted("stack underflow @ dup2".to_string()));
 		}
 
 		let nv1: SharedValue = self.top(-2);
 		let nv2: SharedValue = self.top(-1);
 		self.stack.push(nv1);
 		self.stack.push(nv2);
+		return Ok(());
 	}
-	fn rot(&mut self, n: usize) {
+	fn rot(&mut self, n: usize) -> Result<(), JsError> {
 		if self.stack.len() < n {
-			panic!("stack underflow! @ rot");
+			return Err(JsError::Aborted("stack underflow @ rot".to_string()));
 		}
 		let top = self.stack.len();
 		for i in 0..n-1 {
 			self.stack.swap(top-1-i, top-2-i);
 		}
+		return Ok(());
 	}
-	fn rot2(&mut self) {
+	fn rot2(&mut self) -> Result<(), JsError> {
 		if self.stack.len() < 2 {
-			panic!("stack underflow! @ rot2");
+			return Err(JsError::Aborted("stack underflow @ rot2".to_string()));
 		}
 		/* A B -> B A */
 		let top = self.stack.len();
 		self.stack.swap(top-1, top-2);
+		return Ok(());
 	}
-	fn rot3(&mut self) {
+	fn rot3(&mut self) -> Result<(), JsError> {
 		if self.stack.len() < 3 {
-			panic!("stack underflow! @ rot3");
+			return Err(JsError::Aborted("stack underflow @ rot3".to_string()));
 		}
 		/* A B C -> C A B */
 		let top = self.stack.len();
 		self.stack.swap(top-1, top-2);
 		self.stack.swap(top-2, top-3);
+		return Ok(());
 	}
-	fn rot4(&mut self) {
+	fn rot4(&mut self) -> Result<(), JsError> {
 		if self.stack.len() < 4 {
-			panic!("stack underflow! @ rot4");
+			return Err(JsError::Aborted("stack underflow @ rot4".to_string()));
 		}
 		/* A B C D -> D A B C */
 		let top = self.stack.len();
 		self.stack.swap(top-1, top-2);
 		self.stack.swap(top-2, top-3);
 		self.stack.swap(top-3, top-4);
+		return Ok(());
 	}
-	fn rot3pop2(&mut self) {
+	fn rot3pop2(&mut self) -> Result<(), JsError> {
 		if self.stack.len() < 3 {
-			panic!("stack underflow! @ rot3pop2");
+			return Err(JsError::Aborted("stack underflow @ rot3pop2".to_string()));
 		}
 		/* A B C -> C */
 		let top = self.stack.len();
 		self.stack[top-3] = self.stack[top-1].clone();
-		self.pop(2);
+		return self.pop(2);
 	}
-	fn rot2pop1(&mut self) {
+	fn rot2pop1(&mut self) -> Result<(), JsError> {
 		if self.stack.len() < 2 {
-			panic!("stack underflow! @ rot3pop2");
+			return Err(JsError::Aborted("stack underflow @ rot2pop1".to_string()));
 		}
 		/* A B -> B */
 		let top = self.stack.len();
 		self.stack[top-2] = self.stack[top-1].clone();
-		self.pop(1);
+		return self.pop(1);
 	}
 
 	fn debugger(&mut self) {
@@ -776,21 +1136,25 @@ impl<T: Hookable> JsRuntime<T> {
 
 }
 
-fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Result<(), JsException> {
+fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize, locals: &mut [SharedValue]) -> Result<(), JsError> {
 	assert!(rt.stack.len() > 0);
 	let mut pc:usize = pc;
 	let bot:usize = rt.stack.len() - 1;
 
 	let mut with_exception = None;
-	let mut catch_scopes: Vec<(usize, usize)> = Vec::new();
+	let mut catch_scopes: Vec<(usize, usize, SharedScope)> = Vec::new();
 
 	macro_rules! handle_exception {
 		($e:ident) => {
-			if let Some((new_pc, new_top)) = catch_scopes.pop() {
+			let catched = if $e.is_uncaught() { catch_scopes.pop() } else { None };
+			if let Some((new_pc, new_top, env)) = catched {
 				let dropped = rt.stack.len() - new_top;
-				rt.pop(dropped);
+				rt.pop(dropped)?;
+				rt.cenv = env;
 
-				rt.new_exception($e);
+				if let JsError::Uncaught(ev) = $e {
+					rt.push(ev);
+				}
 				pc = new_pc;
 				continue;
 			} else {
@@ -801,25 +1165,46 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 	}
 
 	loop {
+		if let Some(fuel) = rt.fuel {
+			if fuel == 0 {
+				with_exception = Some(JsError::OutOfFuel);
+				break;
+			}
+			rt.fuel = Some(fuel - 1);
+		}
+		if let Err(e) = rt.check_interrupt() {
+			with_exception = Some(e);
+			break;
+		}
+		if let Err(e) = rt.check_memory() {
+			with_exception = Some(e);
+			break;
+		}
+		rt.finalize_hooks();
+
 		let opcode = func.opcode(&mut pc);
+		if rt.stack.len() < bot + 1 + opcode.stack_pops() {
+			with_exception = Some(JsError::Aborted(format!("stack underflow @ {:?}", opcode)));
+			break;
+		}
 		match opcode {
 			OpcodeType::OP_POP => {
-				rt.pop(1);
+				rt.pop(1)?;
 			},
 			OpcodeType::OP_DUP => {
-				rt.dup();
+				rt.dup()?;
 			},
 			OpcodeType::OP_DUP2 => {
-				rt.dup2();
+				rt.dup2()?;
 			},
 			OpcodeType::OP_ROT2 => {
-				rt.rot2();
+				rt.rot2()?;
 			},
 			OpcodeType::OP_ROT3 => {
-				rt.rot3();
+				rt.rot3()?;
 			},
 			OpcodeType::OP_ROT4 => {
-				rt.rot4();
+				rt.rot4()?;
 			},
 
 			OpcodeType::OP_UNDEF => {
@@ -854,11 +1239,13 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 				rt.new_closure(f);
 			},
 			OpcodeType::OP_NEWOBJECT => {
-				let obj = SharedValue::new_vanilla(rt.prototypes.object_prototype.clone());
-				rt.push(obj);
+				let mut obj = JsObject::new_with(rt.prototypes.object_prototype.clone(), JsClass::object);
+				obj.charge(&rt.heap, JS_OBJECT_COST);
+				rt.push(SharedValue::new_object(obj));
 			},
 			OpcodeType::OP_NEWARRAY => {
-				let obj = JsObject::new_array(rt.prototypes.array_prototype.clone());
+				let mut obj = JsObject::new_array(rt.prototypes.array_prototype.clone());
+				obj.charge(&rt.heap, JS_OBJECT_COST);
 				let jv = SharedValue::new_object(obj);
 				rt.push(jv);
 			},
@@ -866,14 +1253,14 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 			OpcodeType::OP_THIS => {
 				let thiz = rt.stack[bot].clone();
 				if thiz.is_object() {
-					rt.push_from(bot);
+					rt.push_from(bot)?;
 				} else {
 					let global = rt.genv.borrow().target();
 					rt.push_object(global);
 				}
 			},
 			OpcodeType::OP_CURRENT => {
-				rt.push_from(bot - 1);
+				rt.push_from(bot - 1)?;
 			},
 
 			OpcodeType::OP_GETVAR => {
@@ -884,8 +1271,7 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 						if br == true {
 							continue;
 						} else {
-							println!("'{}' is not defined", s);
-							JsException::new(format!("TODO: {}", line!()))
+							rt.new_error(format!("'{}' is not defined", s))
 						}
 					},
 					Err(e) => {
@@ -922,17 +1308,33 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 				let r = rt.delvariable(s);
 				rt.push_boolean(r);
 			},
+			OpcodeType::OP_GETLOCAL => {
+				let i = func.local(&mut pc);
+				rt.push(locals[i].clone());
+			},
+			OpcodeType::OP_SETLOCAL => {
+				let i = func.local(&mut pc);
+				locals[i].replace(rt.top(-1));
+			},
 
 			OpcodeType::OP_INITPROP => {
+				if !rt.top(-3).is_object() {
+					let e = rt.new_type_error("Init none object's property!".to_string());
+					handle_exception!(e);
+				}
 				let target = rt.top(-3).get_object();
 				let name = rt.top(-2).to_string();
 				let value = rt.top(-1);
 				if let Err(e) = rt.setproperty(target, &name, value) {
 					handle_exception!(e);
 				}
-				rt.pop(2);
+				rt.pop(2)?;
 			},
 			OpcodeType::OP_INITGETTER => {
+				if !rt.top(-3).is_object() {
+					let e = rt.new_type_error("Init none object's property!".to_string());
+					handle_exception!(e);
+				}
 				let target = rt.top(-3).get_object();
 				let name = rt.top(-2).to_string();
 				let func = rt.top(-1);
@@ -944,9 +1346,13 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 				} else {
 					println!("getter should be a object!");
 				}
-				rt.pop(2);
+				rt.pop(2)?;
 			},
 			OpcodeType::OP_INITSETTER => {
+				if !rt.top(-3).is_object() {
+					let e = rt.new_type_error("Init none object's property!".to_string());
+					handle_exception!(e);
+				}
 				let target = rt.top(-3).get_object();
 				let name = rt.top(-2).to_string();
 				let func = rt.top(-1);
@@ -958,53 +1364,78 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 				} else {
 					println!("setter should be a object!");
 				}
-				rt.pop(2);
+				rt.pop(2)?;
 			},
 
 			OpcodeType::OP_GETPROP => {
-				let target = rt.top(-2).get_object();
+				let target = rt.top(-2);
+				if !target.is_object() {
+					let e = rt.new_error("Access none objects's property!".to_string());
+					handle_exception!(e);
+				}
+				let target = target.get_object();
 				let name = rt.top(-1).to_string();
 				if let Err(e) = rt.getproperty(target, &name) {
 					handle_exception!(e);
 				}
-				rt.rot3pop2();
+				rt.rot3pop2()?;
 			},
 			OpcodeType::OP_GETPROP_S => {
 				let target = rt.top(-1);
 				if !target.is_object() {
-					let e = JsException::new("Access none objects's property!".to_string());
+					let e = rt.new_error("Access none objects's property!".to_string());
 					handle_exception!(e);
 				}
 				let target = target.get_object();
-				let name = func.string(&mut pc);
-				if let Err(e) = rt.getproperty(target, &name) {
+				let (entry, name) = func.property(&mut pc);
+				if let Err(e) = rt.getproperty_cached(target, name, &func.caches, entry) {
 					handle_exception!(e);
 				}
-				rt.rot2pop1();
+				rt.rot2pop1()?;
 			},
 			OpcodeType::OP_SETPROP => {
-				let target = rt.top(-3).get_object();
+				let target = rt.top(-3);
+				if !target.is_object() {
+					let e = rt.new_error("Set none objects's property!".to_string());
+					handle_exception!(e);
+				}
+				let target = target.get_object();
 				let name = rt.top(-2).to_string();
 				let value = rt.top(-1);
 				if let Err(e) = rt.setproperty(target, &name, value) {
 					handle_exception!(e);
 				}
-				rt.rot3pop2();
+				rt.rot3pop2()?;
 			},
 			OpcodeType::OP_SETPROP_S => {
-				let target = rt.top(-2).get_object();
+				let target = rt.top(-2);
+				if !target.is_object() {
+					let e = rt.new_error("Set none objects's property!".to_string());
+					handle_exception!(e);
+				}
+				let target = target.get_object();
 				let value = rt.top(-1);
-				let name = func.string(&mut pc);
-				if let Err(e) = rt.setproperty(target, &name, value) {
+				let (entry, name) = func.property(&mut pc);
+				if let Err(e) = rt.setproperty_cached(target, name, value, &func.caches, entry) {
 					handle_exception!(e);
 				}
-				rt.rot2pop1();
+				rt.rot2pop1()?;
 			},
 			OpcodeType::OP_DELPROP => {
-				let target = rt.top(-2).get_object();
+				let target = rt.top(-2);
+				if !target.is_object() {
+					let e = rt.new_error("Can't delete none object's proptery".to_string());
+					handle_exception!(e);
+				}
+				let target = target.get_object();
 				let name = rt.top(-1).to_string();
-				let b = rt.delproperty(target, &name);
-				rt.pop(2);
+				let b = match rt.delproperty(target, &name) {
+					Ok(b) => b,
+					Err(e) => {
+						handle_exception!(e);
+					}
+				};
+				rt.pop(2)?;
 				rt.push_boolean(b);
 			},
 			OpcodeType::OP_DELPROP_S => {
@@ -1012,11 +1443,16 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 				let target_value = rt.top(-1);
 				if target_value.is_object() {
 					let target = target_value.get_object();
-					let b = rt.delproperty(target, &name);
-					rt.pop(1);
+					let b = match rt.delproperty(target, &name) {
+						Ok(b) => b,
+						Err(e) => {
+							handle_exception!(e);
+						}
+					};
+					rt.pop(1)?;
 					rt.push_boolean(b);
 				} else {
-					let e = JsException::new("Can't delete none object's proptery".to_string());
+					let e = rt.new_error("Can't delete none object's proptery".to_string());
 					handle_exception!(e);
 				}
 			},
@@ -1024,10 +1460,17 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 			OpcodeType::OP_ITERATOR => {
 				if rt.top(-1).is_object() {
 					let target = rt.top(-1).get_object();
+					let hid = target.borrow().hook_id();
 					if target.borrow().is_vanilla() {
 						let iter = JsObject::new_iterator(target);
-						rt.pop(1);
+						rt.pop(1)?;
 						rt.push( SharedValue::new_object(iter) );
+					} else if let Some(hid) = hid {
+						if let Some(Some(keys)) = rt.with_hook(hid, |hook, rt| hook.own_keys(rt)) {
+							let iter = JsObject::new_iterator_with(JsIterator { keys: keys, index: 0 });
+							rt.pop(1)?;
+							rt.push( SharedValue::new_object(iter) );
+						}
 					}
 				}
 			},
@@ -1041,25 +1484,33 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 							rt.push_string(s);
 							rt.push_boolean(true);
 						} else {
-							rt.pop(1);
+							rt.pop(1)?;
 							rt.push_boolean(false);
 						}
 						continue;
 					}
 				}
-				rt.pop(1);
+				rt.pop(1)?;
 				rt.push_boolean(false);
 			},
 
 			/* Function calls */
 			OpcodeType::OP_CALL => {
 				let n = func.int(&mut pc) as usize;
+				if rt.stack.len() < bot + 1 + n + 2 {
+					with_exception = Some(JsError::Aborted("stack underflow @ OP_CALL".to_string()));
+					break;
+				}
 				if let Err(e) = jscall(rt, n) {
 					handle_exception!(e);
 				}
 			},
 			OpcodeType::OP_NEW => {
 				let n = func.int(&mut pc) as usize;
+				if rt.stack.len() < bot + 1 + n + 1 {
+					with_exception = Some(JsError::Aborted("stack underflow @ OP_NEW".to_string()));
+					break;
+				}
 				if let Err(e) = rt.new_call(n) {
 					handle_exception!(e);
 				}
@@ -1069,49 +1520,49 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 			OpcodeType::OP_TYPEOF => {
 				let target = rt.top(-1);
 				let str = target.type_string();
-				rt.pop(1);
+				rt.pop(1)?;
 				rt.push_string(str);
 			},
 
 			OpcodeType::OP_POS => {
 				let n = rt.top(-1).to_number();
-				rt.pop(1);
+				rt.pop(1)?;
 				rt.push_number(n);
 			},
 			OpcodeType::OP_NEG => {
 				let n = rt.top(-1).to_number();
-				rt.pop(1);
+				rt.pop(1)?;
 				rt.push_number(-n);
 			},
 			OpcodeType::OP_BITNOT => {
 				let n = rt.top(-1).to_number() as i32;
-				rt.pop(1);
+				rt.pop(1)?;
 				rt.push_number( (!n) as f64 );
 			},
 			OpcodeType::OP_LOGNOT => {
 				let n = rt.top(-1).to_boolean();
-				rt.pop(1);
+				rt.pop(1)?;
 				rt.push_boolean(!n);
 			},
 			OpcodeType::OP_INC => {
 				let n = rt.top(-1).to_number();
-				rt.pop(1);
+				rt.pop(1)?;
 				rt.push_number(n+1.0);
 			},
 			OpcodeType::OP_DEC => {
 				let n = rt.top(-1).to_number();
-				rt.pop(1);
+				rt.pop(1)?;
 				rt.push_number(n-1.0);
 			},
 			OpcodeType::OP_POSTINC => {
 				let n = rt.top(-1).to_number();
-				rt.pop(1);
+				rt.pop(1)?;
 				rt.push_number(n+1.0);
 				rt.push_number(n);
 			},
 			OpcodeType::OP_POSTDEC => {
 				let n = rt.top(-1).to_number();
-				rt.pop(1);
+				rt.pop(1)?;
 				rt.push_number(n-1.0);
 				rt.push_number(n);
 			},
@@ -1120,30 +1571,30 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 			OpcodeType::OP_MUL => {
 				let x = rt.top(-2).to_number();
 				let y = rt.top(-1).to_number();
-				rt.pop(2);
+				rt.pop(2)?;
 				rt.push_number(x * y);
 			},
 			OpcodeType::OP_DIV => {
 				let x = rt.top(-2).to_number();
 				let y = rt.top(-1).to_number();
-				rt.pop(2);
+				rt.pop(2)?;
 				rt.push_number(x / y);
 			},
 			OpcodeType::OP_MOD => {
 				let x = rt.top(-2).to_number();
 				let y = rt.top(-1).to_number();
-				rt.pop(2);
+				rt.pop(2)?;
 				rt.push_number(x % y);
 			},
 
 			/* Additive operators */
 			OpcodeType::OP_ADD => {
-				rt.concat_add();
+				rt.concat_add()?;
 			},
 			OpcodeType::OP_SUB => {
 				let x = rt.top(-2).to_number();
 				let y = rt.top(-1).to_number();
-				rt.pop(2);
+				rt.pop(2)?;
 				rt.push_number(x - y);
 			},
 
@@ -1151,7 +1602,7 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 			OpcodeType::OP_SHL => {
 				let x = rt.top(-2).to_number();
 				let y = rt.top(-1).to_number();
-				rt.pop(2);
+				rt.pop(2)?;
 				if x == f64::NAN || y == f64::NAN {
 					rt.push_number(0.0);
 				} else if x == f64::INFINITY || y == f64::INFINITY {
@@ -1167,7 +1618,7 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 			OpcodeType::OP_SHR => {
 				let x = rt.top(-2).to_number();
 				let y = rt.top(-1).to_number();
-				rt.pop(2);
+				rt.pop(2)?;
 				if x == f64::NAN || y == f64::NAN {
 					rt.push_number(0.0);
 				} else if x == f64::INFINITY || y == f64::INFINITY {
@@ -1183,7 +1634,7 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 			OpcodeType::OP_USHR => {
 				let x = rt.top(-2).to_number();
 				let y = rt.top(-1).to_number();
-				rt.pop(2);
+				rt.pop(2)?;
 				if x == f64::NAN || y == f64::NAN {
 					rt.push_number(0.0);
 				} else if x == f64::INFINITY || y == f64::INFINITY {
@@ -1199,7 +1650,7 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 
 			/* Relational operators */
 			OpcodeType::OP_LT => {
-				let r = rt.compare_item();
+				let r = rt.compare_item()?;
 				if let Some(b) = r {
 					rt.push_boolean( b < 0 );
 				} else {
@@ -1207,7 +1658,7 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 				}
 			},
 			OpcodeType::OP_GT => {
-				let r = rt.compare_item();
+				let r = rt.compare_item()?;
 				if let Some(b) = r {
 					rt.push_boolean( b > 0);
 				} else {
@@ -1215,7 +1666,7 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 				}
 			},
 			OpcodeType::OP_LE => {
-				let r = rt.compare_item();
+				let r = rt.compare_item()?;
 				if let Some(b) = r {
 					rt.push_boolean( b <= 0 );
 				} else {
@@ -1223,7 +1674,7 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 				}
 			},
 			OpcodeType::OP_GE => {
-				let r = rt.compare_item();
+				let r = rt.compare_item()?;
 				if let Some(b) = r {
 					rt.push_boolean( b >= 0);
 				} else {
@@ -1245,21 +1696,21 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 
 			/* Equality */
 			OpcodeType::OP_EQ => {
-				let b = rt.equal();
+				let b = rt.equal()?;
 				rt.push_boolean(b);
 			},
 			OpcodeType::OP_NE => {
-				let b = rt.equal();
+				let b = rt.equal()?;
 				rt.push_boolean(!b);
 			},
 			OpcodeType::OP_STRICTEQ => {
 				let b = rt.strict_equal();
-				rt.pop(2);
+				rt.pop(2)?;
 				rt.push_boolean(b);
 			},
 			OpcodeType::OP_STRICTNE => {
 				let b = rt.strict_equal();
-				rt.pop(2);
+				rt.pop(2)?;
 				rt.push_boolean(!b);
 			},
 
@@ -1267,7 +1718,7 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 			OpcodeType::OP_BITAND => {
 				let x = rt.top(-2).to_number();
 				let y = rt.top(-1).to_number();
-				rt.pop(2);
+				rt.pop(2)?;
 				if x == f64::NAN || y == f64::NAN {
 					rt.push_number(0.0);
 				} else if x == f64::INFINITY || y == f64::INFINITY {
@@ -1281,7 +1732,7 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 			OpcodeType::OP_BITXOR => {
 				let x = rt.top(-2).to_number();
 				let y = rt.top(-1).to_number();
-				rt.pop(2);
+				rt.pop(2)?;
 				if x == f64::NAN || y == f64::NAN {
 					rt.push_number(0.0);
 				} else if x == f64::INFINITY || y == f64::INFINITY {
@@ -1295,7 +1746,7 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 			OpcodeType::OP_BITOR => {
 				let x = rt.top(-2).to_number();
 				let y = rt.top(-1).to_number();
-				rt.pop(2);
+				rt.pop(2)?;
 				if x == f64::NAN || y == f64::NAN {
 					rt.push_number(0.0);
 				} else if x == f64::INFINITY || y == f64::INFINITY {
@@ -1310,7 +1761,7 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 			/* Try and Catch */
 			OpcodeType::OP_TRY => {
 				let catch_block = func.address(&mut pc);
-				catch_scopes.push((pc, rt.stack.len()));
+				catch_scopes.push((pc, rt.stack.len(), rt.cenv.clone()));
 				pc = catch_block;
 			},
 			OpcodeType::OP_ENDTRY => {
@@ -1319,7 +1770,7 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 			OpcodeType::OP_CATCH => {
 				let str = func.string(&mut pc);
 				let eobj = rt.top(-1);
-				rt.pop(1);
+				rt.pop(1)?;
 
 				let new_env = JsEnvironment::new_from(rt.cenv.clone());
 				new_env.borrow_mut().init_var(str, eobj);
@@ -1331,13 +1782,9 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 			},
 			OpcodeType::OP_THROW => {
 				let evalue = rt.top(-1);
-				rt.pop(1);
-				if evalue.is_exception() {
-					let e = evalue.get_object().borrow().get_exception();
-					handle_exception!(e);
-				} else {
-					panic!("Throw a none exception object!");
-				}
+				rt.pop(1)?;
+				let e = JsError::Uncaught(evalue);
+				handle_exception!(e);
 			},
 
 			/* Branching & Flow control */
@@ -1345,10 +1792,10 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 				let offset = func.address(&mut pc);
 				let b = rt.strict_equal();
 				if b {
-					rt.pop(2);
+					rt.pop(2)?;
 					pc = offset;
 				} else {
-					rt.pop(1);
+					rt.pop(1)?;
 				}
 			},
 			OpcodeType::OP_JUMP => {
@@ -1358,7 +1805,7 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 			OpcodeType::OP_JTRUE => {
 				let addr = func.address(&mut pc);
 				let b = rt.top(-1).to_boolean();
-				rt.pop(1);
+				rt.pop(1)?;
 				if b {
 					pc = addr;
 				}
@@ -1366,7 +1813,7 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 			OpcodeType::OP_JFALSE => {
 				let addr = func.address(&mut pc);
 				let b = rt.top(-1).to_boolean();
-				rt.pop(1);
+				rt.pop(1)?;
 				if !b {
 					pc = addr;
 				}
@@ -1377,7 +1824,8 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 
 			OpcodeType::OP_DEBUG => {
 				rt.debugger();
-				panic!("Exiting with debug");
+				with_exception = Some(JsError::Aborted("Exiting with debug".to_string()));
+				break;
 			},
 
 			/* do nothing */
@@ -1394,46 +1842,55 @@ fn jsrun<T: Hookable>(rt: &mut JsRuntime<T>, func: &VMFunction, pc: usize) -> Re
 	return Err( with_exception.unwrap() );
 }
 
-fn jscall_script<T:Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsException> {
+fn jscall_script<T:Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsError> {
 	let bot = rt.stack.len() - 1 - argc;
 
 	let fobj = rt.stack[bot-1].get_object();
-	let rfobj = fobj.borrow();
-	let vmf = &rfobj.get_func().vmf;
+	let vmf = fobj.borrow().get_func().vmf.clone();
 
-	/* init var in current env*/
+	/* init var in current env, declared variables keep their values like redeclared vars in JavaScript */
 	for i in 0..vmf.numvars {
-		let jv = SharedValue::new_undefined();
 		let var = &vmf.str_tab[i];
+		if rt.cenv.borrow().query_variable(var) {
+			continue;
+		}
+		let jv = SharedValue::new_undefined();
 		rt.cenv.borrow_mut().init_var(var, jv);
 	}
 
-	/* scripts take no arguments */
-	rt.pop(argc);
-	jsrun(rt, vmf, 0)?;
+	/* scripts take no arguments, and their variables are all in the scope.
+	   An error out of a catch block leaves its scope in cenv, the scope of the script is restored */
+	rt.pop(argc)?;
+	let old_env = rt.cenv.clone();
+	let result = jsrun(rt, &vmf, 0, &mut []);
+	rt.cenv = old_env;
+	result?;
 
 	/* clear stack */
 	let jv = rt.stack.pop().unwrap();
-	rt.pop(2);
+	rt.pop(2)?;
 	rt.push(jv);
 
 	return Ok(())
 }
 
-fn jscall_function<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsException> {
+fn jscall_function<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsError> {
 	let bot = rt.stack.len() - 1 - argc;
 
 	let fobj = rt.stack[bot-1].get_object();
-	let rfobj = fobj.borrow();
-	let vmf = &rfobj.get_func().vmf;
+	let vmf = fobj.borrow().get_func().vmf.clone();
 
-	/* create new scope */
-	let new_env = JsEnvironment::new_from(rfobj.get_func().scope.clone());
+	/* create new scope, a call which keeps everything in slots runs in the scope of the closure */
+	let scope = fobj.borrow().get_func().scope.clone();
 	let old_env = rt.cenv.clone();
-	rt.cenv = new_env;
+	if vmf.scope {
+		rt.cenv = JsEnvironment::new_from(scope);
+	} else {
+		rt.cenv = scope;
+	}
 
 	/* create arguments */
-	{
+	if vmf.arguments {
 		let arg_obj = JsObject::new_array( rt.prototypes.array_prototype.clone());
 		let arg_value = SharedValue::new_object(arg_obj);
         let arg_obj = arg_value.get_object();
@@ -1447,73 +1904,128 @@ fn jscall_function<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<()
 		rt.cenv.borrow_mut().init_var("arguments", arg_value);
 	}
 
-	/* setup remained arguments*/
+	/* setup remained arguments, then vars, in their slots or in current env */
 	let min_argc = cmp::min(argc, vmf.numparams);
-	for i in 0..min_argc {
-		let argv = rt.stack[i + 1 + bot].clone();
-		rt.cenv.borrow_mut().init_var(&vmf.str_tab[i], argv);
-	}
-	rt.pop(argc);
-
-	/* init var in current env*/
-	for i in min_argc..(vmf.numvars + vmf.numparams) {
-		let jv = SharedValue::new_undefined();
-		rt.cenv.borrow_mut().init_var(&vmf.str_tab[i], jv);
+	let mut locals: Vec<SharedValue> = Vec::with_capacity(vmf.numvars + vmf.numparams);
+	for i in 0..(vmf.numvars + vmf.numparams) {
+		let jv = if i < min_argc {
+			rt.stack[i + 1 + bot].clone()
+		} else {
+			SharedValue::new_undefined()
+		};
+		if !vmf.locals.get(i).copied().unwrap_or(false) {
+			rt.cenv.borrow_mut().init_var(&vmf.str_tab[i], jv.clone());
+		}
+		locals.push(jv);
 	}
+	rt.pop(argc)?;
 
 	/* for recurrent call function self, init a local variable into this */
 	if let Some(ref name) = vmf.name {
-		rt.cenv.borrow_mut().init_var(name, rt.stack[bot-1].clone());
+		if vmf.scope {
+			rt.cenv.borrow_mut().init_var(name, rt.stack[bot-1].clone());
+		}
 	}
 
+	let result = jsrun(rt, &vmf, 0, &mut locals);
 
-	jsrun(rt, vmf, 0)?;
+	/* restore old env */
+	rt.cenv = old_env;
+	result?;
 
 	/* clear stack */
 	let jv = rt.stack.pop().unwrap();
-	rt.pop(2);
+	rt.pop(2)?;
 	rt.push(jv);
 
-	/* restore old env */
-	rt.cenv = old_env;
-
 	return Ok(());
 }
 
-fn jscall_builtin<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) {
+fn jscall_builtin<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsError> {
 	let bot = rt.stack.len() - 1 - argc;
 	let fobj = rt.stack[bot-1].get_object();
 	let builtin = rt.builtins[fobj.borrow().get_builtin()].clone();
 
-	(builtin.f)(rt, argc);
+	match builtin {
+		JsBuiltinFunction::native(f) => {
+			f(rt, argc)?;
+		},
+		JsBuiltinFunction::closure(f) => {
+			/* a closure is borrowed while running, it can't be re-entered from script */
+			let mut f = match f.try_borrow_mut() {
+				Ok(f) => f,
+				Err(_) => {
+					return Err(rt.new_type_error("Host function can't be called recursively".to_string()));
+				}
+			};
+			(*f)(rt, argc)?;
+		}
+	}
 
 	let jv = rt.stack.pop().unwrap();
-	rt.pop(argc + 2);
+	rt.pop(argc + 2)?;
 	rt.push(jv);
+	return Ok(());
 }
 
-pub fn jscall<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsException> {
+fn jscall_hook<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsError> {
+	let bot = rt.stack.len() - 1 - argc;
+	let fobj = rt.stack[bot-1].get_object();
+	let hid = fobj.borrow().get_hook();
+
+	match rt.with_hook(hid, |hook, rt| hook.call(rt, argc)) {
+		Some(r) => r?,
+		None => {
+			return Err(rt.new_type_error("Hook can't be called recursively".to_string()));
+		}
+	}
+
+	let jv = rt.stack.pop().unwrap();
+	rt.pop(argc + 2)?;
+	rt.push(jv);
+	return Ok(());
+}
+
+pub fn jscall<T: Hookable>(rt: &mut JsRuntime<T>, argc: usize) -> Result<(), JsError> {
 	assert!(rt.stack.len() >= argc + 2);
 	let bot = rt.stack.len() - 1 - argc;
 
 	if !rt.stack[bot-1].is_object() {
-		return Err( JsException::new("Can't call on none function value".to_string()));
+		return Err( rt.new_error("Can't call on none function value".to_string()));
+	}
+	if rt.call_depth >= JS_CALL_LIMIT {
+		return Err( rt.new_error("Maximum call stack size exceeded".to_string()));
+	}
+	let here = 0u8;
+	let addr = &here as *const u8 as usize;
+	if rt.call_depth == 0 {
+		rt.native_base = addr;
+		/* an interrupt raised while no script was running doesn't stop the next one */
+		rt.interrupt.store(false, Ordering::Relaxed);
+	} else if rt.native_base.abs_diff(addr) > JS_NATIVE_STACK_LIMIT {
+		return Err( rt.new_error("Maximum call stack size exceeded".to_string()));
 	}
 
 	let fobj = rt.stack[bot-1].get_object();
-	if fobj.borrow().is_function() == true {
+	let is_function = fobj.borrow().is_function();
+	let is_builtin = fobj.borrow().is_builtin();
+	let is_hook = fobj.borrow().is_hook();
 
+	rt.call_depth = rt.call_depth + 1;
+	let result = if is_function {
 		if fobj.borrow().get_func().vmf.script {
-			jscall_script(rt, argc)?;
+			jscall_script(rt, argc)
 		} else {
-			jscall_function(rt, argc)?;
-		};
-
-	} else if fobj.borrow().is_builtin() == true {
-		jscall_builtin(rt, argc);
+			jscall_function(rt, argc)
+		}
+	} else if is_builtin {
+		jscall_builtin(rt, argc)
+	} else if is_hook {
+		jscall_hook(rt, argc)
 	} else {
-        panic!("Can't call none function object");
-	}
+		Err( rt.new_error("Can't call none function object".to_string()))
+	};
+	rt.call_depth = rt.call_depth - 1;
 
-	return Ok(());
+	return result;
 }
@@ -0,0 +1,525 @@
+use std::fmt;
+
+use serde::ser::{self, Serialize};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+
+use crate::error::*;
+use crate::value::*;
+use crate::runtime::*;
+
+/* serde needs its own error type, it becomes a TypeError when leaving this module */
+#[derive(Debug)]
+pub struct SerdeError {
+	msg:	String,
+}
+
+impl fmt::Display for SerdeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.msg)
+	}
+}
+impl std::error::Error for SerdeError {}
+impl ser::Error for SerdeError {
+	fn custom<M: fmt::Display>(msg: M) -> Self {
+		SerdeError{ msg: msg.to_string() }
+	}
+}
+impl de::Error for SerdeError {
+	fn custom<M: fmt::Display>(msg: M) -> Self {
+		SerdeError{ msg: msg.to_string() }
+	}
+}
+
+pub fn from_value<T: Hookable, D: DeserializeOwned>(rt: &JsRuntime<T>, jv: &SharedValue) -> Result<D, JsError> {
+	let deserializer = ValueDeserializer{ value: jv.clone(), depth: 0 };
+	match D::deserialize(deserializer) {
+		Ok(v) => {
+			return Ok(v);
+		},
+		Err(e) => {
+			return Err(rt.new_type_error(e.msg));
+		}
+	}
+}
+
+pub fn to_value<T: Hookable, S: Serialize + ?Sized>(rt: &JsRuntime<T>, v: &S) -> Result<SharedValue, JsError> {
+	let serializer = ValueSerializer{ rt: rt };
+	match v.serialize(serializer) {
+		Ok(v) => {
+			return Ok(v);
+		},
+		Err(e) => {
+			return Err(rt.new_type_error(e.msg));
+		}
+	}
+}
+
+/* Rust -> JavaScript, enums are tagged externally like JSON: "Unit" or { "Variant": value } */
+struct ValueSerializer<'a, T: Hookable> {
+	rt:		&'a JsRuntime<T>,
+}
+
+impl<'a, T: Hookable> Clone for ValueSerializer<'a, T> {
+	fn clone(&self) -> Self {
+		*self
+	}
+}
+impl<'a, T: Hookable> Copy for ValueSerializer<'a, T> {}
+
+struct SeqSerializer<'a, T: Hookable> {
+	ser:		ValueSerializer<'a, T>,
+	variant:	Option<&'static str>,
+	items:		Vec<SharedValue>,
+}
+
+struct MapSerializer<'a, T: Hookable> {
+	ser:		ValueSerializer<'a, T>,
+	variant:	Option<&'static str>,
+	key:		Option<String>,
+	props:		Vec<(String, SharedValue)>,
+}
+
+impl<'a, T: Hookable> SeqSerializer<'a, T> {
+	fn push<V: Serialize + ?Sized>(&mut self, v: &V) -> Result<(), SerdeError> {
+		let jv = v.serialize(self.ser)?;
+		self.items.push(jv);
+		return Ok(());
+	}
+	fn finish(self) -> Result<SharedValue, SerdeError> {
+		let array = self.ser.rt.new_array(self.items);
+		if let Some(variant) = self.variant {
+			return Ok(self.ser.rt.new_object(vec![(variant.to_string(), array)]));
+		}
+		return Ok(array);
+	}
+}
+
+impl<'a, T: Hookable> MapSerializer<'a, T> {
+	fn finish(self) -> Result<SharedValue, SerdeError> {
+		let object = self.ser.rt.new_object(self.props);
+		if let Some(variant) = self.variant {
+			return Ok(self.ser.rt.new_object(vec![(variant.to_string(), object)]));
+		}
+		return Ok(object);
+	}
+}
+
+impl<'a, T: Hookable> ser::Serializer for ValueSerializer<'a, T> {
+	type Ok = SharedValue;
+	type Error = SerdeError;
+
+	type SerializeSeq = SeqSerializer<'a, T>;
+	type SerializeTuple = SeqSerializer<'a, T>;
+	type SerializeTupleStruct = SeqSerializer<'a, T>;
+	type SerializeTupleVariant = SeqSerializer<'a, T>;
+	type SerializeMap = MapSerializer<'a, T>;
+	type SerializeStruct = MapSerializer<'a, T>;
+	type SerializeStructVariant = MapSerializer<'a, T>;
+
+	fn serialize_bool(self, v: bool) -> Result<SharedValue, SerdeError> {
+		return Ok(SharedValue::new_boolean(v));
+	}
+	fn serialize_i8(self, v: i8) -> Result<SharedValue, SerdeError> {
+		return self.serialize_f64(v as f64);
+	}
+	fn serialize_i16(self, v: i16) -> Result<SharedValue, SerdeError> {
+		return self.serialize_f64(v as f64);
+	}
+	fn serialize_i32(self, v: i32) -> Result<SharedValue, SerdeError> {
+		return self.serialize_f64(v as f64);
+	}
+	fn serialize_i64(self, v: i64) -> Result<SharedValue, SerdeError> {
+		return self.serialize_f64(v as f64);
+	}
+	fn serialize_u8(self, v: u8) -> Result<SharedValue, SerdeError> {
+		return self.serialize_f64(v as f64);
+	}
+	fn serialize_u16(self, v: u16) -> Result<SharedValue, SerdeError> {
+		return self.serialize_f64(v as f64);
+	}
+	fn serialize_u32(self, v: u32) -> Result<SharedValue, SerdeError> {
+		return self.serialize_f64(v as f64);
+	}
+	fn serialize_u64(self, v: u64) -> Result<SharedValue, SerdeError> {
+		return self.serialize_f64(v as f64);
+	}
+	fn serialize_f32(self, v: f32) -> Result<SharedValue, SerdeError> {
+		return self.serialize_f64(v as f64);
+	}
+	fn serialize_f64(self, v: f64) -> Result<SharedValue, SerdeError> {
+		return Ok(SharedValue::new_number(v));
+	}
+	fn serialize_char(self, v: char) -> Result<SharedValue, SerdeError> {
+		return Ok(self.rt.new_string(v.to_string()));
+	}
+	fn serialize_str(self, v: &str) -> Result<SharedValue, SerdeError> {
+		return Ok(self.rt.new_string(v.to_string()));
+	}
+	fn serialize_bytes(self, v: &[u8]) -> Result<SharedValue, SerdeError> {
+		let items = v.iter().map(|b| SharedValue::new_number(*b as f64)).collect();
+		return Ok(self.rt.new_array(items));
+	}
+	fn serialize_none(self) -> Result<SharedValue, SerdeError> {
+		return Ok(SharedValue::new_null());
+	}
+	fn serialize_some<V: Serialize + ?Sized>(self, v: &V) -> Result<SharedValue, SerdeError> {
+		return v.serialize(self);
+	}
+	fn serialize_unit(self) -> Result<SharedValue, SerdeError> {
+		return Ok(SharedValue::new_null());
+	}
+	fn serialize_unit_struct(self, _name: &'static str) -> Result<SharedValue, SerdeError> {
+		return Ok(SharedValue::new_null());
+	}
+	fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<SharedValue, SerdeError> {
+		return self.serialize_str(variant);
+	}
+	fn serialize_newtype_struct<V: Serialize + ?Sized>(self, _name: &'static str, v: &V) -> Result<SharedValue, SerdeError> {
+		return v.serialize(self);
+	}
+	fn serialize_newtype_variant<V: Serialize + ?Sized>(self, _name: &'static str, _index: u32, variant: &'static str, v: &V) -> Result<SharedValue, SerdeError> {
+		let jv = v.serialize(self)?;
+		return Ok(self.rt.new_object(vec![(variant.to_string(), jv)]));
+	}
+	fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, SerdeError> {
+		return Ok(SeqSerializer{ ser: self, variant: None, items: Vec::with_capacity(len.unwrap_or(0)) });
+	}
+	fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, SerdeError> {
+		return self.serialize_seq(Some(len));
+	}
+	fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeTupleStruct, SerdeError> {
+		return self.serialize_seq(Some(len));
+	}
+	fn serialize_tuple_variant(self, _name: &'static str, _index: u32, variant: &'static str, len: usize) -> Result<Self::SerializeTupleVariant, SerdeError> {
+		return Ok(SeqSerializer{ ser: self, variant: Some(variant), items: Vec::with_capacity(len) });
+	}
+	fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, SerdeError> {
+		return Ok(MapSerializer{ ser: self, variant: None, key: None, props: Vec::new() });
+	}
+	fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct, SerdeError> {
+		return self.serialize_map(Some(len));
+	}
+	fn serialize_struct_variant(self, _name: &'static str, _index: u32, variant: &'static str, _len: usize) -> Result<Self::SerializeStructVariant, SerdeError> {
+		return Ok(MapSerializer{ ser: self, variant: Some(variant), key: None, props: Vec::new() });
+	}
+}
+
+impl<'a, T: Hookable> ser::SerializeSeq for SeqSerializer<'a, T> {
+	type Ok = SharedValue;
+	type Error = SerdeError;
+	fn serialize_element<V: Serialize + ?Sized>(&mut self, v: &V) -> Result<(), SerdeError> {
+		return self.push(v);
+	}
+	fn end(self) -> Result<SharedValue, SerdeError> {
+		return self.finish();
+	}
+}
+impl<'a, T: Hookable> ser::SerializeTuple for SeqSerializer<'a, T> {
+	type Ok = SharedValue;
+	type Error = SerdeError;
+	fn serialize_element<V: Serialize + ?Sized>(&mut self, v: &V) -> Result<(), SerdeError> {
+		return self.push(v);
+	}
+	fn end(self) -> Result<SharedValue, SerdeError> {
+		return self.finish();
+	}
+}
+impl<'a, T: Hookable> ser::SerializeTupleStruct for SeqSerializer<'a, T> {
+	type Ok = SharedValue;
+	type Error = SerdeError;
+	fn serialize_field<V: Serialize + ?Sized>(&mut self, v: &V) -> Result<(), SerdeError> {
+		return self.push(v);
+	}
+	fn end(self) -> Result<SharedValue, SerdeError> {
+		return self.finish();
+	}
+}
+impl<'a, T: Hookable> ser::SerializeTupleVariant for SeqSerializer<'a, T> {
+	type Ok = SharedValue;
+	type Error = SerdeError;
+	fn serialize_field<V: Serialize + ?Sized>(&mut self, v: &V) -> Result<(), SerdeError> {
+		return self.push(v);
+	}
+	fn end(self) -> Result<SharedValue, SerdeError> {
+		return self.finish();
+	}
+}
+
+impl<'a, T: Hookable> ser::SerializeMap for MapSerializer<'a, T> {
+	type Ok = SharedValue;
+	type Error = SerdeError;
+	/* keys become property names, so any key serializing to a primitive is accepted */
+	fn serialize_key<K: Serialize + ?Sized>(&mut self, key: &K) -> Result<(), SerdeError> {
+		let jv = key.serialize(self.ser)?;
+		if jv.is_object() && !jv.is_string() {
+			return Err(SerdeError{ msg: "map key must be a string or a number".to_string() });
+		}
+		self.key = Some(jv.to_string());
+		return Ok(());
+	}
+	fn serialize_value<V: Serialize + ?Sized>(&mut self, v: &V) -> Result<(), SerdeError> {
+		let key = match self.key.take() {
+			Some(key) => key,
+			None => {
+				return Err(SerdeError{ msg: "map value without key".to_string() });
+			}
+		};
+		let jv = v.serialize(self.ser)?;
+		self.props.push((key, jv));
+		return Ok(());
+	}
+	fn end(self) -> Result<SharedValue, SerdeError> {
+		return self.finish();
+	}
+}
+impl<'a, T: Hookable> ser::SerializeStruct for MapSerializer<'a, T> {
+	type Ok = SharedValue;
+	type Error = SerdeError;
+	fn serialize_field<V: Serialize + ?Sized>(&mut self, key: &'static str, v: &V) -> Result<(), SerdeError> {
+		let jv = v.serialize(self.ser)?;
+		self.props.push((key.to_string(), jv));
+		return Ok(());
+	}
+	fn end(self) -> Result<SharedValue, SerdeError> {
+		return self.finish();
+	}
+}
+impl<'a, T: Hookable> ser::SerializeStructVariant for MapSerializer<'a, T> {
+	type Ok = SharedValue;
+	type Error = SerdeError;
+	fn serialize_field<V: Serialize + ?Sized>(&mut self, key: &'static str, v: &V) -> Result<(), SerdeError> {
+		let jv = v.serialize(self.ser)?;
+		self.props.push((key.to_string(), jv));
+		return Ok(());
+	}
+	fn end(self) -> Result<SharedValue, SerdeError> {
+		return self.finish();
+	}
+}
+
+/* JavaScript -> Rust, walks vanilla objects, arrays, strings, numbers and booleans.
+   Objects and arrays deeper than JS_SERDE_DEPTH are refused, a cyclic value would never end. */
+const JS_SERDE_DEPTH: usize = 128;
+
+struct ValueDeserializer {
+	value:	SharedValue,
+	depth:	usize,		// objects and arrays around the value
+}
+
+impl ValueDeserializer {
+	fn check_depth(&self) -> Result<(), SerdeError> {
+		if self.depth >= JS_SERDE_DEPTH {
+			return Err(SerdeError{ msg: format!("value is nested deeper than {} levels or is cyclic", JS_SERDE_DEPTH) });
+		}
+		return Ok(());
+	}
+}
+
+/* own enumerable properties of a vanilla object */
+fn object_entries(jv: &SharedValue) -> Option<Vec<(String, SharedValue)>> {
+	if !jv.is_object() {
+		return None;
+	}
+	let obj = jv.get_object();
+	let obj = obj.borrow();
+	if !obj.is_vanilla() {
+		return None;
+	}
+	let entries = obj.own_properties()
+		.filter(|(_, prop)| prop.enumerable())
+		.map(|(k, prop)| (k.to_string(), prop.value.clone()))
+		.collect();
+	return Some(entries);
+}
+
+fn array_items(jv: &SharedValue) -> Option<Vec<SharedValue>> {
+	if !jv.is_object() {
+		return None;
+	}
+	let obj = jv.get_object();
+	let obj = obj.borrow();
+	if !obj.is_array() {
+		return None;
+	}
+	return Some(obj.get_array().clone());
+}
+
+struct SeqAccess {
+	items:	std::vec::IntoIter<SharedValue>,
+	depth:	usize,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess {
+	type Error = SerdeError;
+	fn next_element_seed<S: de::DeserializeSeed<'de>>(&mut self, seed: S) -> Result<Option<S::Value>, SerdeError> {
+		match self.items.next() {
+			Some(value) => {
+				return seed.deserialize(ValueDeserializer{ value: value, depth: self.depth }).map(Some);
+			},
+			None => {
+				return Ok(None);
+			}
+		}
+	}
+	fn size_hint(&self) -> Option<usize> {
+		return Some(self.items.len());
+	}
+}
+
+struct MapAccess {
+	entries:	std::vec::IntoIter<(String, SharedValue)>,
+	value:		Option<SharedValue>,
+	depth:		usize,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess {
+	type Error = SerdeError;
+	fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, SerdeError> {
+		match self.entries.next() {
+			Some((k, v)) => {
+				self.value = Some(v);
+				let key: de::value::StringDeserializer<SerdeError> = k.into_deserializer();
+				return seed.deserialize(key).map(Some);
+			},
+			None => {
+				return Ok(None);
+			}
+		}
+	}
+	fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, SerdeError> {
+		match self.value.take() {
+			Some(value) => {
+				return seed.deserialize(ValueDeserializer{ value: value, depth: self.depth });
+			},
+			None => {
+				return Err(SerdeError{ msg: "map value without key".to_string() });
+			}
+		}
+	}
+}
+
+struct EnumAccess {
+	variant:	String,
+	value:		Option<SharedValue>,
+	depth:		usize,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+	type Error = SerdeError;
+	type Variant = Self;
+	fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), SerdeError> {
+		let key: de::value::StrDeserializer<SerdeError> = self.variant.as_str().into_deserializer();
+		let v = seed.deserialize(key)?;
+		return Ok((v, self));
+	}
+}
+
+impl<'de> de::VariantAccess<'de> for EnumAccess {
+	type Error = SerdeError;
+	fn unit_variant(self) -> Result<(), SerdeError> {
+		if let Some(value) = self.value {
+			if value.is_something() {
+				return Err(SerdeError{ msg: format!("unit variant {} should not have a value", self.variant) });
+			}
+		}
+		return Ok(());
+	}
+	fn newtype_variant_seed<S: de::DeserializeSeed<'de>>(self, seed: S) -> Result<S::Value, SerdeError> {
+		match self.value {
+			Some(value) => {
+				return seed.deserialize(ValueDeserializer{ value: value, depth: self.depth });
+			},
+			None => {
+				return Err(SerdeError{ msg: format!("variant {} needs a value", self.variant) });
+			}
+		}
+	}
+	fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, SerdeError> {
+		match self.value {
+			Some(value) => {
+				return de::Deserializer::deserialize_seq(ValueDeserializer{ value: value, depth: self.depth }, visitor);
+			},
+			None => {
+				return Err(SerdeError{ msg: format!("variant {} needs an array", self.variant) });
+			}
+		}
+	}
+	fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, SerdeError> {
+		match self.value {
+			Some(value) => {
+				return de::Deserializer::deserialize_map(ValueDeserializer{ value: value, depth: self.depth }, visitor);
+			},
+			None => {
+				return Err(SerdeError{ msg: format!("variant {} needs an object", self.variant) });
+			}
+		}
+	}
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer {
+	type Error = SerdeError;
+
+	fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+		let depth = self.depth + 1;
+		let jv = self.value.clone();
+		if !jv.is_something() {
+			return visitor.visit_unit();
+		}
+		if jv.is_boolean() {
+			return visitor.visit_bool(jv.to_boolean());
+		}
+		if jv.is_number() {
+			/* integral numbers are visited as integers, so integer fields can accept them */
+			let v = jv.to_number();
+			if v.fract() == 0.0 && v >= (i64::MIN as f64) && v < (i64::MAX as f64) {
+				return visitor.visit_i64(v as i64);
+			}
+			return visitor.visit_f64(v);
+		}
+		if jv.is_string() {
+			return visitor.visit_string(jv.to_string());
+		}
+		if let Some(items) = array_items(&jv) {
+			self.check_depth()?;
+			return visitor.visit_seq(SeqAccess{ items: items.into_iter(), depth: depth });
+		}
+		if let Some(entries) = object_entries(&jv) {
+			self.check_depth()?;
+			return visitor.visit_map(MapAccess{ entries: entries.into_iter(), value: None, depth: depth });
+		}
+		return Err(SerdeError{ msg: format!("can't deserialize a {} value", jv.type_string()) });
+	}
+
+	fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+		if !self.value.is_something() {
+			return visitor.visit_none();
+		}
+		return visitor.visit_some(self);
+	}
+
+	fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, SerdeError> {
+		return visitor.visit_newtype_struct(self);
+	}
+
+	fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value, SerdeError> {
+		let depth = self.depth + 1;
+		let jv = self.value.clone();
+		if jv.is_string() {
+			return visitor.visit_enum(EnumAccess{ variant: jv.to_string(), value: None, depth: depth });
+		}
+		if let Some(mut entries) = object_entries(&jv) {
+			if entries.len() == 1 {
+				self.check_depth()?;
+				let (variant, value) = entries.remove(0);
+				return visitor.visit_enum(EnumAccess{ variant: variant, value: Some(value), depth: depth });
+			}
+		}
+		return Err(SerdeError{ msg: "enum should be a string or an object with one property".to_string() });
+	}
+
+	serde::forward_to_deserialize_any! {
+		bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+		bytes byte_buf unit unit_struct seq tuple
+		tuple_struct map struct identifier ignored_any
+	}
+}
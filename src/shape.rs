@@ -0,0 +1,238 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+use crate::bytecode::*;
+use crate::value::*;
+
+/* Shapes of objects, also called hidden classes.
+   A shape maps the keys of an object to the slots of its property vector, in insertion order.
+   Objects which got the same keys in the same order share a shape, it's found by the transitions
+   from the empty shape: (shape, key) -> shape. An object which drops a key, grows past
+   JS_SHAPE_KEYS or adds a key after a shape with JS_SHAPE_TRANSITIONS live transitions gets
+   a shape of its own, a dictionary changed in place with holes in the slots.
+   The id of a shape changes with its keys, the inline caches of property access keep ids. */
+pub(crate) type ShapeId = usize;
+pub(crate) type SharedShape = Rc<Shape>;
+
+const JS_SHAPE_KEYS: usize = 64;
+const JS_SHAPE_HOLES: usize = 16;		// a dictionary with more holes than keys and this is compacted
+const JS_SHAPE_TRANSITIONS: usize = 1024;	// live shapes after one shape, objects used as maps become dictionaries
+const JS_SHAPE_PRUNE_MIN: usize = 16;
+
+/* shapes after a shape by the added key, they are weak and the dead ones are pruned when the table doubles */
+#[derive(Clone, Default)]
+struct Transitions {
+	shapes:		HashMap<Rc<str>, Weak<Shape>>,
+	prune_at:	usize,
+}
+
+#[derive(Clone)]
+pub(crate) struct Shape {
+	pub(crate) id:	ShapeId,
+	keys:			HashMap<Rc<str>, usize>,	// slot of each key
+	names:			Vec<Option<Rc<str>>>,		// key of each slot, none for a hole of a dictionary
+	own:			bool,
+	transitions:	RefCell<Transitions>,
+}
+
+thread_local! {
+	static SHAPE_ID: Cell<ShapeId> = const { Cell::new(0) };
+	static EMPTY_SHAPE: SharedShape = Rc::new(Shape::new(next_id(), HashMap::new(), Vec::new(), false));
+}
+
+fn next_id() -> ShapeId {
+	return SHAPE_ID.with(|id| {
+		id.set(id.get() + 1);
+		return id.get();
+	});
+}
+
+impl Shape {
+	fn new(id: ShapeId, keys: HashMap<Rc<str>, usize>, names: Vec<Option<Rc<str>>>, own: bool) -> Self {
+		return Shape {
+			id: id,
+			keys: keys,
+			names: names,
+			own: own,
+			transitions: RefCell::new(Transitions::default()),
+		};
+	}
+
+	pub(crate) fn empty() -> SharedShape {
+		return EMPTY_SHAPE.with(|s| s.clone());
+	}
+
+	pub(crate) fn slot(&self, key: &str) -> Option<usize> {
+		return self.keys.get(key).copied();
+	}
+
+	/* slots and their keys in insertion order, holes are skipped */
+	pub(crate) fn slots(&self) -> impl Iterator<Item = (usize, &str)> {
+		return self.names.iter().enumerate().filter_map(|(i, name)| name.as_ref().map(|name| (i, name.as_ref())));
+	}
+
+	/* the shared shape with key after the keys of this one, none when this one has too many */
+	fn transition(&self, key: &str) -> Option<SharedShape> {
+		let mut transitions = self.transitions.borrow_mut();
+		if let Some(next) = transitions.shapes.get(key).and_then(|next| next.upgrade()) {
+			return Some(next);
+		}
+		if transitions.shapes.len() >= transitions.prune_at {
+			transitions.shapes.retain(|_, next| next.strong_count() > 0);
+			transitions.prune_at = std::cmp::max(JS_SHAPE_PRUNE_MIN, transitions.shapes.len() * 2);
+		}
+		if transitions.shapes.len() >= JS_SHAPE_TRANSITIONS {
+			/* a full table is pruned again after as many misses as it has shapes */
+			transitions.prune_at = transitions.prune_at - 1;
+			return None;
+		}
+
+		let key: Rc<str> = Rc::from(key);
+		let mut keys = self.keys.clone();
+		keys.insert(key.clone(), self.names.len());
+		let mut names = self.names.clone();
+		names.push(Some(key.clone()));
+		let next = Rc::new(Shape::new(next_id(), keys, names, false));
+		transitions.shapes.insert(key, Rc::downgrade(&next));
+		return Some(next);
+	}
+
+	/* the dictionary of an object, a shared shape is copied first, the id changes with every change */
+	fn own_mut(shape: &mut SharedShape) -> &mut Shape {
+		if !shape.own {
+			*shape = Rc::new(Shape::new(0, shape.keys.clone(), shape.names.clone(), true));
+		}
+		let own = Rc::make_mut(shape);
+		own.id = next_id();
+		return own;
+	}
+
+	/* add a key to the shape of an object, it gets the slot after the last one */
+	pub(crate) fn add_key(shape: &mut SharedShape, key: &str) -> usize {
+		let slot = shape.names.len();
+		if !shape.own && shape.keys.len() < JS_SHAPE_KEYS {
+			if let Some(next) = shape.transition(key) {
+				*shape = next;
+				return slot;
+			}
+		}
+		let own = Shape::own_mut(shape);
+		let key: Rc<str> = Rc::from(key);
+		own.keys.insert(key.clone(), slot);
+		own.names.push(Some(key));
+		return slot;
+	}
+
+	/* remove a key from the shape of an object, its slot becomes a hole */
+	pub(crate) fn remove_key(shape: &mut SharedShape, key: &str) -> Option<usize> {
+		let slot = shape.slot(key)?;
+		let own = Shape::own_mut(shape);
+		own.keys.remove(key);
+		own.names[slot] = None;
+		return Some(slot);
+	}
+
+	/* drop the holes of a dictionary when they are many, the slots of the object are kept in the same order */
+	pub(crate) fn compact(shape: &mut SharedShape) -> bool {
+		let holes = shape.names.len() - shape.keys.len();
+		if !shape.own || holes <= JS_SHAPE_HOLES || holes <= shape.keys.len() {
+			return false;
+		}
+		let own = Shape::own_mut(shape);
+		own.names.retain(|name| name.is_some());
+		for (i, name) in own.names.iter().enumerate() {
+			own.keys.insert(name.clone().unwrap(), i);
+		}
+		return true;
+	}
+}
+
+/* longest chain of prototypes kept by a cache */
+const JS_CACHE_DEPTH: usize = 8;
+
+/* What an instruction found for objects of a shape. The prototypes are weak, a cache doesn't keep
+   them alive, they are only compared with the live chain of the object. */
+#[derive(Clone)]
+pub(crate) enum PropertyCache {
+	Empty,
+	Own(ShapeId, usize),
+	Proto(ShapeId, Vec<(Weak<RefCell<JsObject>>, ShapeId)>, usize),	// prototypes down to the one with the key, and its slot
+}
+
+impl Default for PropertyCache {
+	fn default() -> Self {
+		return PropertyCache::Empty;
+	}
+}
+
+impl InlineCaches {
+	/* the property and if it's own, when the cache of entry holds for target */
+	pub(crate) fn lookup(&self, entry: usize, target: &SharedObject) -> Option<(JsProperty, bool)> {
+		let entries = self.entries.borrow();
+		let cache = entries.get(entry)?;
+		let obj = target.borrow();
+		if obj.is_hook() {
+			return None;
+		}
+		match cache {
+			PropertyCache::Own(shape, slot) if obj.shape.id == *shape => {
+				return Some((obj.slot_property(*slot)?, true));
+			},
+			PropertyCache::Proto(shape, chain, slot) if obj.shape.id == *shape => {
+				let mut proto = obj.__proto__.clone();
+				let last = chain.len() - 1;
+				for (i, (cached, shape)) in chain.iter().enumerate() {
+					let p = proto?;
+					let pobj = p.borrow();
+					if Rc::as_ptr(&p) != cached.as_ptr() || pobj.shape.id != *shape {
+						return None;
+					}
+					if i == last {
+						return Some((pobj.slot_property(*slot)?, false));
+					}
+					proto = pobj.__proto__.clone();
+				}
+				return None;
+			},
+			_ => return None,
+		}
+	}
+
+	/* remember where name is found for target now, names of array items are never cached */
+	pub(crate) fn fill(&self, entry: usize, target: &SharedObject, name: &str) {
+		if name.parse::<usize>().is_ok() || target.borrow().is_hook() {
+			return;
+		}
+		let obj = target.borrow();
+		let cache = if let Some(slot) = obj.shape.slot(name) {
+			PropertyCache::Own(obj.shape.id, slot)
+		} else {
+			let mut chain = Vec::new();
+			let mut proto = obj.__proto__.clone();
+			let mut found = None;
+			while let Some(p) = proto {
+				if chain.len() >= JS_CACHE_DEPTH {
+					break;
+				}
+				let pobj = p.borrow();
+				chain.push((Rc::downgrade(&p), pobj.shape.id));
+				found = pobj.shape.slot(name);
+				if found.is_some() {
+					break;
+				}
+				proto = pobj.__proto__.clone();
+			}
+			match found {
+				Some(slot) => PropertyCache::Proto(obj.shape.id, chain, slot),
+				None => return,
+			}
+		};
+
+		let mut entries = self.entries.borrow_mut();
+		if entries.len() <= entry {
+			entries.resize(entry + 1, PropertyCache::Empty);
+		}
+		entries[entry] = cache;
+	}
+}
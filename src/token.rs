@@ -1,6 +1,7 @@
 use std::char;
 use std::collections::LinkedList;
 use crate::common::*;
+use crate::error::*;
 
 /* token stuff */
 #[allow(non_camel_case_types)]
@@ -105,6 +106,7 @@ pub struct Token {
     pub tk_type:    TokenType,
     pub tk_value:   Option<String>,
     pub src_line:   u32,
+    pub src_col:    u32,
 }
 
 #[allow(non_camel_case_types)]
@@ -229,7 +231,8 @@ fn next_general_token (script: &str, cursor: usize) -> Result<(GeneralToken, usi
 	loop {
         let chr = chars.next();
 		let ct = check_ct(chr);
-        pos = pos + 1;
+        let clen = if let Some(ch) = chr { ch.len_utf8() } else { 1 };
+        pos = pos + clen;
 
         // state handler
         if ps == ps::PS_NULL {
@@ -282,7 +285,7 @@ fn next_general_token (script: &str, cursor: usize) -> Result<(GeneralToken, usi
                 ct::CT_NEWLN => {
                     let value = tkbuf.into_iter().collect();
                     let symbol = GeneralToken::new_with(GeneralTokenType::TK_SYMBOL_, value);
-                    return Ok((symbol, pos - 1));
+                    return Ok((symbol, pos - clen));
                 },
                 ct::CT_LETTER => {
                     tkbuf.push( chr.unwrap());
@@ -291,7 +294,7 @@ fn next_general_token (script: &str, cursor: usize) -> Result<(GeneralToken, usi
                 ct::CT_PUNCT => {
                     let value = tkbuf.into_iter().collect();
                     let symbol = GeneralToken::new_with(GeneralTokenType::TK_SYMBOL_, value);
-                    return Ok((symbol, pos-1));
+                    return Ok((symbol, pos - clen));
                 }
             }
         }
@@ -360,7 +363,7 @@ fn next_general_token (script: &str, cursor: usize) -> Result<(GeneralToken, usi
                 ct::CT_NEWLN => {
                     let value = tkbuf.into_iter().collect();
                     let comment = GeneralToken::new_with(GeneralTokenType::TK_COMMENT_, value);
-                    return Ok((comment, pos-1));
+                    return Ok((comment, pos - clen));
                 },
                 ct::CT_EOF => {
                     let value = tkbuf.into_iter().collect();
@@ -385,19 +388,19 @@ fn next_general_token (script: &str, cursor: usize) -> Result<(GeneralToken, usi
                 ct::CT_NEWLN => {
                     let value = tkbuf.into_iter().collect();
                     let punct = GeneralToken::new_with(GeneralTokenType::TK_PUNCT_, value);
-                    return Ok((punct, pos-1));
+                    return Ok((punct, pos - clen));
                 },
                 ct::CT_LETTER => {
                     let value = tkbuf.into_iter().collect();
                     let punct = GeneralToken::new_with(GeneralTokenType::TK_PUNCT_, value);
-                    return Ok((punct, pos-1));
+                    return Ok((punct, pos - clen));
                 },
                 ct::CT_PUNCT => {
                     let ch = chr.unwrap();
                     if ch == ';' {
                         let value = tkbuf.into_iter().collect();
                         let punct = GeneralToken::new_with(GeneralTokenType::TK_PUNCT_, value);
-                        return Ok((punct, pos-1));
+                        return Ok((punct, pos - clen));
                     }
                     {
                         // check is valid multiple punctuators
@@ -423,7 +426,7 @@ fn next_general_token (script: &str, cursor: usize) -> Result<(GeneralToken, usi
                     }
                     let value = tkbuf.into_iter().collect();
                     let punct = GeneralToken::new_with(GeneralTokenType::TK_PUNCT_, value);
-                    return Ok((punct, pos-1));
+                    return Ok((punct, pos - clen));
                 }
             }
         }
@@ -431,19 +434,21 @@ fn next_general_token (script: &str, cursor: usize) -> Result<(GeneralToken, usi
 }
 
 impl Token {
-    fn new(tt: TokenType, line:u32) -> Self {
+    fn new(tt: TokenType, line:u32, col:u32) -> Self {
         Token {
             tk_type: tt,
             tk_value: None,
             src_line: line,
+            src_col: col,
         }
     }
 
-    fn new_with(tt: TokenType, value: String, line:u32) -> Self {
+    fn new_with(tt: TokenType, value: String, line:u32, col:u32) -> Self {
         Token {
             tk_type: tt,
             tk_value: Some(value),
-            src_line: line
+            src_line: line,
+            src_col: col,
         }
     }
 
@@ -499,10 +504,39 @@ fn get_keyword(symbol: &str) -> Option<TokenType> {
     }
 }
 
+/* char column of a position in script, counted on from the last position asked so each char is counted once */
+struct Column {
+    pos: usize,
+    col: u32,
+}
+
+impl Column {
+    fn new() -> Self {
+        return Column {
+            pos: 0,
+            col: 1,
+        }
+    }
+
+    fn at(&mut self, script: &str, pos: usize) -> u32 {
+        if pos < self.pos {
+            *self = Column::new();
+        }
+        let text = &script[self.pos..pos];
+        if let Some(begin) = text.rfind('\n') {
+            self.col = 1 + text[begin + 1..].chars().count() as u32;
+        } else {
+            self.col = self.col + text.chars().count() as u32;
+        }
+        self.pos = pos;
+        return self.col;
+    }
+}
+
 ///
 /// Parsing script to tokens
 ///
-fn get_next_token(script: &str,  cursor: usize, line: u32) -> Result<(Token, (usize, u32)), String> {
+fn get_next_token(script: &str,  cursor: usize, line: u32, column: &mut Column) -> Result<(Token, (usize, u32)), JsError> {
     fn count_line(comment: &str) -> u32 {
         let mut chars = comment.chars();
         let mut line_count: u32 = 0;
@@ -576,7 +610,7 @@ fn get_next_token(script: &str,  cursor: usize, line: u32) -> Result<(Token, (us
             ">>>" => Some(TokenType::TK_USHR),
             "<<=" => Some(TokenType::TK_SHL_ASS),
             ">>=" => Some(TokenType::TK_SHR_ASS),
-            ">>>>=" => Some(TokenType::TK_USHR_ASS),
+            ">>>=" => Some(TokenType::TK_USHR_ASS),
             _ => None
         }
     }
@@ -611,38 +645,57 @@ fn get_next_token(script: &str,  cursor: usize, line: u32) -> Result<(Token, (us
         return -1;
     }
 
+    // column of the first none space char from cursor
+    fn get_column(script: &str, cursor: usize, column: &mut Column) -> u32 {
+        if cursor >= script.len() {
+            return 0;
+        }
+        let mut start = cursor;
+        for ch in script[cursor..].chars() {
+            if ch != ' ' && ch != '\t' && ch != '\r' {
+                break;
+            }
+            start = start + 1;
+        }
+        return column.at(script, start);
+    }
+
     let mut line = line;
     let mut cursor = cursor;
 
     // handling general token
     loop {
+        let col = get_column(script, cursor, column);
         let next = next_general_token(&script, cursor);
         if let Err(msg) = next {
-            let err_msg = format!("Parsing error @ {} : {}", line, msg);
-            return Err(err_msg);
+            return Err(JsError::syntax(line, col, msg.to_string()));
         }
 
         let (tk, pos) = next.unwrap();
         cursor = pos;
         match tk.tk_type {
             GeneralTokenType::TK_EOF_ => {
-                let eof = Token::new(TokenType::TK_EOF, line);
+                let eof = Token::new(TokenType::TK_EOF, line, col);
                 return Ok((eof, (cursor, line)));
             },
             GeneralTokenType::TK_PUNCT_  => {
                 let value = tk.tk_value.unwrap();
-                let tkt = get_token_type(&value).unwrap();
+                let tkt = get_token_type(&value);
+                if tkt.is_none() {
+                    return Err(JsError::syntax(line, col, format!("Unexpected token '{}'", value)));
+                }
+                let tkt = tkt.unwrap();
                 if tkt == TokenType::TK_NEWLN {
                     line = line + 1;
                 }
-                let ntk = Token::new(tkt, line);
+                let ntk = Token::new(tkt, line, col);
                 return Ok((ntk, (cursor, line)));
             },
             GeneralTokenType::TK_STRING_ => {
                 let value = tk.tk_value.unwrap();
                 line = line + count_line(&value);
 
-                let ntk = Token::new_with(TokenType::TK_STRING, value, line);
+                let ntk = Token::new_with(TokenType::TK_STRING, value, line, col);
                 return Ok((ntk, (cursor, line)));
             },
             GeneralTokenType::TK_COMMENT_ => {
@@ -656,15 +709,15 @@ fn get_next_token(script: &str,  cursor: usize, line: u32) -> Result<(Token, (us
                 let isnum = check_number(&value);
                 if isnum == -1 {
                     if let Some(tkt) = get_keyword(&value) {
-                        let ntk = Token::new(tkt, line);
+                        let ntk = Token::new(tkt, line, col);
                         return Ok((ntk, (cursor, line)));
                     } else {
-                        let ntk = Token::new_with(TokenType::TK_IDENTIFIER, value, line);
+                        let ntk = Token::new_with(TokenType::TK_IDENTIFIER, value, line, col);
                         return Ok((ntk, (cursor, line)));
                     }
                 }
                 if isnum == 1 {
-                    let ntk = Token::new_with(TokenType::TK_NUMBER, value, line);
+                    let ntk = Token::new_with(TokenType::TK_NUMBER, value, line, col);
                     return Ok((ntk, (cursor, line)));
                 }
 
@@ -679,7 +732,7 @@ fn get_next_token(script: &str,  cursor: usize, line: u32) -> Result<(Token, (us
                                     let value3 = tk3.tk_value.unwrap();
                                     let value_all = format!("{}{}{}", value, value2, value3);
                                     if value_all.parse::<f64>().is_ok() {
-                                        let ntk = Token::new_with(TokenType::TK_NUMBER, value_all, line);
+                                        let ntk = Token::new_with(TokenType::TK_NUMBER, value_all, line, col);
                                         cursor = pos3;
                                         return Ok((ntk, (cursor, line)));
                                     }
@@ -697,10 +750,12 @@ pub struct Tokenlizer<'a> {
     script : &'a str,
     cursor : usize,
     line : u32,
+    column : Column,
     forward_: LinkedList<(Token, bool)>,
 
     // help state variable for AST
     pub notin: bool, 
+    pub depth: usize,
 }
 
 impl<'a> Tokenlizer<'a> {
@@ -709,13 +764,15 @@ impl<'a> Tokenlizer<'a> {
             script: script,
             cursor: 0,
             line: 1,
+            column: Column::new(),
             forward_: LinkedList::new(),
 
             notin: false,
+            depth: 0,
         }
     }
 
-    pub fn next(&mut self) -> Result<Token, String> {
+    pub fn next(&mut self) -> Result<Token, JsError> {
         if self.forward_.len() > 0 {
             let n = self.forward_.pop_front().unwrap().0;
             return Ok(n);
@@ -727,7 +784,7 @@ impl<'a> Tokenlizer<'a> {
         return Ok(n);
     }
 
-    pub fn forward(&mut self) -> Result<Token, String> {
+    pub fn forward(&mut self) -> Result<Token, JsError> {
         if self.forward_.len() > 0 {
             let n = self.forward_.front().unwrap().0.clone();
             return Ok(n);
@@ -739,7 +796,7 @@ impl<'a> Tokenlizer<'a> {
         return Ok(n);
     }
 
-    pub fn new_line(&mut self) -> Result<bool, String> {
+    pub fn new_line(&mut self) -> Result<bool, JsError> {
         if self.forward_.len() > 0 {
             let n = self.forward_.front().unwrap().1;
             return Ok(n);
@@ -759,6 +816,7 @@ impl<'a> Tokenlizer<'a> {
         assert!(token.tk_type == TokenType::TK_IDENTIFIER);
 
         let src_line = token.src_line;
+        let mut src_col = token.src_col;
         let ident = token.tk_value.unwrap();
 
         let ids : Vec<String> = ident.replace(".", " . ").split_whitespace().map(|x| x.to_string()).collect();
@@ -766,12 +824,13 @@ impl<'a> Tokenlizer<'a> {
             let id = &ids[i];
             if id != "." {
                 let tk = if let Some(tkt) = get_keyword(id) {
-                    Token::new(tkt, src_line)
+                    Token::new(tkt, src_line, src_col)
                 } else {                        
                     Token {
                         tk_type: TokenType::TK_IDENTIFIER,
                         tk_value: Some(id.to_string()),
                         src_line: src_line,
+                        src_col: src_col,
                     }
                 };
 
@@ -781,16 +840,18 @@ impl<'a> Tokenlizer<'a> {
                     tk_type: TokenType::TK_POINT,
                     tk_value: None,
                     src_line: src_line,
+                    src_col: src_col,
                 };
                 self.forward_.push_back((tk, new_line && i == 0));
             }
+            src_col = src_col + id.chars().count() as u32;
         }
     }
 
-    fn fetch_next(&mut self) -> Result<(), String> {
+    fn fetch_next(&mut self) -> Result<(), JsError> {
         let mut new_line = false;
         loop {            
-            let result = get_next_token(self.script, self.cursor, self.line);
+            let result = get_next_token(self.script, self.cursor, self.line, &mut self.column);
             if result.is_ok() {
                 let (token, (cursor, line)) = result.unwrap();
                 if token.tk_type != TokenType::TK_EOF {
@@ -1,15 +1,16 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
 use std::convert::TryFrom;
 use std::rc::Rc;
 
 use crate::common::*;
 use crate::bytecode::*;
+use crate::shape::*;
 
 /* definement for VMFunction/SharedValue/JsValue/JsObject */
 pub type SharedFunction = Rc<Box<VMFunction>>;
 pub type SharedScope = Rc<RefCell<JsEnvironment>>;
 pub type SharedObject = Rc<RefCell<JsObject>>;
+pub type SharedHeap = Rc<JsHeap>;
 
 #[allow(non_snake_case)]
 pub fn SharedScope_new(scope: JsEnvironment) -> SharedScope {
@@ -18,7 +19,9 @@ pub fn SharedScope_new(scope: JsEnvironment) -> SharedScope {
 
 #[allow(non_snake_case)]
 pub fn SharedObject_new(obj: JsObject) -> SharedObject {
-	Rc::new(RefCell::new(obj))
+	let obj = Rc::new(RefCell::new(obj));
+	crate::gc::register(&obj);
+	obj
 }
 #[allow(non_snake_case)]
 pub fn SharedFunction_new(vmf: VMFunction) -> SharedFunction {
@@ -77,10 +80,42 @@ pub enum JsClass {
 pub struct JsObject {
 	pub __proto__:	Option<SharedObject>,
 	pub extensible:	bool,
-	pub properties: HashMap<String, JsProperty>,
+	/* own properties, the shape has their keys and the slots their values in insertion order.
+	   They are changed with set_property/put_property/drop_property, so both go together. */
+	pub(crate) shape:	SharedShape,
+	pub(crate) slots:	Vec<Option<JsProperty>>,
 	pub value:	JsClass,
+
+	/* memory accounting, bytes charged to a runtime's heap are released on drop */
+	pub(crate) heap:	Option<SharedHeap>,
+	pub(crate) charged:	usize,
 }
 
+/* approximate allocation size of a runtime */
+#[allow(non_camel_case_types)]
+pub struct JsHeap {
+	pub used:	Cell<usize>,
+	pub objects:	Cell<usize>,
+	pub collections:	Cell<usize>,
+	pub collected:	Cell<usize>,
+	/* ids of dropped hook objects, finalized by the runtime */
+	pub(crate) released_hooks:	RefCell<Vec<u64>>,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug)]
+pub struct JsHeapStats {
+	pub objects:	usize,		// live objects charged to this runtime
+	pub used:		usize,		// approximate bytes charged to this runtime
+	pub collections:	usize,	// cycle collections run by this runtime
+	pub collected:	usize,		// objects reclaimed by these collections
+}
+
+/* approximate costs used by memory accounting, the Rc header is two words */
+pub const JS_OBJECT_COST: usize = std::mem::size_of::<RefCell<JsObject>>() + 16;
+pub const JS_PROPERTY_COST: usize = std::mem::size_of::<JsProperty>() + std::mem::size_of::<RefCell<JsValue>>() + 16;
+pub const JS_VALUE_COST: usize = std::mem::size_of::<SharedValue>() + std::mem::size_of::<RefCell<JsValue>>() + 16;
+
 #[allow(non_camel_case_types)]
 #[derive(Clone)]
 pub struct JsProperty {
@@ -97,6 +132,7 @@ pub struct JsProperty {
 pub type JsPropertyAttr = (bool, bool, bool);	//writable, enumerable, configurable
 pub const JS_DEFAULT_ATTR: JsPropertyAttr = (true, true, true);
 pub const JS_READONLY_ATTR: JsPropertyAttr = (false, false, false);
+const JS_TOSTRING_LIMIT: usize = 1000;
 
 #[allow(non_camel_case_types)]
 pub struct JsEnvironment {
@@ -113,12 +149,20 @@ impl VMFunction {
             script: false,
             numparams: 0,
             numvars: 0,
+            locals:     Vec::new(),
+            arguments:  false,
+            scope:      false,
             code:       Vec::new(),
             num_tab:    Vec::new(),
             str_tab:    Vec::new(),
             func_tab:   Vec::new(),
+            prop_tab:   Vec::new(),
+            caches:     InlineCaches::default(),
 
             jumps:      Vec::new(),
+            finally_code: 0,
+            imports:    Vec::new(),
+            exports:    Vec::new(),
         };
 		anonymous.code.push( OpcodeType::OP_UNDEF as u16);
 		anonymous.code.push( OpcodeType::OP_RETURN as u16);
@@ -167,6 +211,18 @@ impl VMFunction {
 		*pc = *pc + 1;
 		return &self.str_tab[id];
 	}
+	pub fn property(&self, pc:&mut usize) -> (usize, &str) {
+		if *pc >= self.code.len() {
+			panic!("fetch raw out of code");
+		}
+		let id = self.code[*pc] as usize;
+		if id >= self.prop_tab.len() {
+			panic!("property out of vm");
+		}
+
+		*pc = *pc + 1;
+		return (id, &self.str_tab[self.prop_tab[id]]);
+	}
 	pub fn function(&self, pc:&mut usize) -> SharedFunction {
 		if *pc >= self.code.len() {
 			panic!("fetch function out of code");
@@ -178,6 +234,17 @@ impl VMFunction {
 		*pc = *pc + 1;
 		return self.func_tab[id].clone();
 	}
+	pub fn local(&self, pc:&mut usize) -> usize {
+		if *pc >= self.code.len() {
+			panic!("fetch raw out of code");
+		}
+		let id = self.code[*pc] as usize;
+		if id >= self.numparams + self.numvars {
+			panic!("local out of vm");
+		}
+		*pc = *pc + 1;
+		return id;
+	}
 	pub fn address(&self, pc:&mut usize) -> usize {
 		let addr = self.code[*pc] as usize + (self.code[*pc+1] as usize) * 65536;
 		*pc = *pc + 2;
@@ -393,6 +460,11 @@ impl SharedValue {
 		return false;
 	}
 	pub fn to_string(&self) -> String {
+		let mut visited = Vec::new();
+		return self.to_string_visited(&mut visited);
+	}
+	/* nested arrays are joined with a visited list, a cyclic or too deep element is printed as empty */
+	fn to_string_visited(&self, visited: &mut Vec<SharedObject>) -> String {
 		let v = self.v.borrow();
 		match &*v {
 			JsValue::JSUndefined => {
@@ -415,15 +487,20 @@ impl SharedValue {
 				if obj.borrow().is_string() {
 					return obj.borrow().get_string();
 				} else if obj.borrow().is_array() {
+					if visited.len() >= JS_TOSTRING_LIMIT || visited.iter().any(|x| Rc::ptr_eq(x, obj)) {
+						return "".to_string();
+					}
+					visited.push(obj.clone());
 					let obj_ = obj.borrow();
 					let mut result = String::new();
 					let v = obj_.get_array();
 					for i in 0..v.len() {
-						result.push_str( &v[i].to_string() );
+						result.push_str( &v[i].to_string_visited(visited) );
 						if i != v.len() - 1 {
 							result.push_str(", ");
 						}
 					}
+					visited.pop();
 					return result;
 				} else {
 					return format!("[object:_{}_]", obj.borrow().type_string());
@@ -490,8 +567,8 @@ impl JsIterator {
 		let target = target_.borrow();
 
 		let mut keys: Vec<String> = Vec::new();
-		for x in (*target).properties.keys() {
-			if target.properties.get(x).unwrap().enumerable() {
+		for (x, prop) in target.own_properties() {
+			if prop.enumerable() {
 				keys.push(x.to_string());
 			}
 		}
@@ -510,21 +587,79 @@ impl JsIterator {
 	}
 }
 
+/* Release children with a work list, the default recursive drop overflows the stack
+   with a long chain of objects, like a linked list built in script. */
+impl Drop for JsObject {
+	fn drop(&mut self) {
+		if let Some(heap) = self.heap.take() {
+			heap.used.set(heap.used.get().saturating_sub(self.charged));
+			heap.objects.set(heap.objects.get() - 1);
+			if let JsClass::hook(hid) = self.value {
+				heap.released_hooks.borrow_mut().push(hid);
+			}
+		}
+
+		let mut pending: Vec<SharedObject> = Vec::new();
+		self.take_children(&mut pending);
+		while let Some(obj) = pending.pop() {
+			if let Ok(cell) = Rc::try_unwrap(obj) {
+				let mut obj = cell.into_inner();
+				obj.take_children(&mut pending);
+			}
+		}
+	}
+}
+
 impl JsObject {
+	fn take_children(&mut self, pending: &mut Vec<SharedObject>) {
+		fn take_value(jv: SharedValue, pending: &mut Vec<SharedObject>) {
+			if let Ok(cell) = Rc::try_unwrap(jv.v) {
+				if let JsValue::JSObject(obj) = cell.into_inner() {
+					pending.push(obj);
+				}
+			}
+		}
+
+		if let Some(proto) = self.__proto__.take() {
+			pending.push(proto);
+		}
+		self.shape = Shape::empty();
+		for prop in self.slots.drain(..).flatten() {
+			take_value(prop.value, pending);
+			if let Some(getter) = prop.getter {
+				pending.push(getter);
+			}
+			if let Some(setter) = prop.setter {
+				pending.push(setter);
+			}
+		}
+		if let JsClass::array(ref mut v) = self.value {
+			for jv in v.drain(..) {
+				take_value(jv, pending);
+			}
+		}
+	}
+
     pub fn new() -> JsObject {
         JsObject {
 			extensible:	true,
             __proto__: None,
-            properties: HashMap::new(),
+            shape: Shape::empty(),
+            slots: Vec::new(),
             value: JsClass::object,
+            heap: None,
+            charged: 0,
         }
 	}
 	pub fn new_with(prototype: SharedObject, value: JsClass) -> JsObject {
         JsObject {
 			extensible:	true,
             __proto__: Some(prototype),
-            properties: HashMap::new(),
-            value: value
+            shape: Shape::empty(),
+            slots: Vec::new(),
+            value: value,
+            heap: None,
+            charged: 0,
         }
 	}
 
@@ -532,8 +667,11 @@ impl JsObject {
 		JsObject {
 			extensible:	false,
 			__proto__: Some(prototype),
-			properties: HashMap::new(),
+			shape: Shape::empty(),
+			slots: Vec::new(),
 			value: JsClass::exception(e),
+			heap: None,
+			charged: 0,
 		}
 	}
 
@@ -541,18 +679,27 @@ impl JsObject {
 		JsObject {
 			extensible:	false,
 			__proto__: Some(prototype),
-			properties: HashMap::new(),
+			shape: Shape::empty(),
+			slots: Vec::new(),
 			value: JsClass::array(Vec::new()),
+			heap: None,
+			charged: 0,
 		}
 	}
 
 	pub fn new_iterator(target_: SharedObject) -> JsObject {
 		let it = JsIterator::new(target_);
+		return JsObject::new_iterator_with(it);
+	}
+	pub fn new_iterator_with(it: JsIterator) -> JsObject {
 		JsObject {
 			extensible:	false,
 			__proto__: None,
-			properties: HashMap::new(),
+			shape: Shape::empty(),
+			slots: Vec::new(),
 			value: JsClass::iterator(it),
+			heap: None,
+			charged: 0,
 		}
 	}
 
@@ -564,8 +711,11 @@ impl JsObject {
 		JsObject {
 			extensible:	true,
 			__proto__: Some(prototype),
-			properties: HashMap::new(),
+			shape: Shape::empty(),
+			slots: Vec::new(),
 			value: fvalue,
+			heap: None,
+			charged: 0,
 		}
 	}
 
@@ -616,6 +766,12 @@ impl JsObject {
 		}
 		panic!("Object can't be a hook!")
 	}
+	pub fn hook_id(&self) -> Option<u64> {
+		if let JsClass::hook(hid) = self.value {
+			return Some(hid);
+		}
+		return None;
+	}
 	pub fn is_exception(&self) -> bool {
 		if let JsClass::exception(_e) = &self.value {
 			return true;
@@ -703,40 +859,87 @@ impl JsObject {
 
 	/* property's help functions */
 	pub fn query_property(&self, name: &str) -> Option<(JsProperty, bool)> {
-		let r = self.properties.get(name);
-		if r.is_some() {
-			return Some((r.unwrap().clone(), true));
+		if let Some(prop) = self.own_property(name) {
+			return Some((prop.clone(), true));
 		}
 
-		if self.__proto__.is_some() {
-			let proto = self.__proto__.as_ref().unwrap().borrow();
-			let result = proto.query_property(name);
-			if result.is_some() {
-				return Some((result.unwrap().0, false));
+		/* prototypes are walked by a loop, a long chain doesn't use the native stack */
+		let mut proto = self.__proto__.clone();
+		while let Some(p) = proto {
+			let pobj = p.borrow();
+			if let Some(prop) = pobj.own_property(name) {
+				return Some((prop.clone(), false));
 			}
-			return None;
+			proto = pobj.__proto__.clone();
 		}
 		return None;
 	}
 	pub fn get_property(&self, name: &str) -> JsProperty {
-		return self.properties.get(name).unwrap().clone();
+		return self.own_property(name).unwrap().clone();
 	}
 	pub fn set_property(&mut self, name: &str, prop: JsProperty) {
-		self.properties.insert(name.to_string(), prop);
+		match self.shape.slot(name) {
+			Some(slot) => {
+				self.slots[slot] = Some(prop);
+			},
+			None => {
+				Shape::add_key(&mut self.shape, name);
+				self.slots.push(Some(prop));
+			},
+		}
 	}
 	pub fn put_property(&mut self, name: &str) -> bool {
-		let result = self.properties.get(name);
-		if result.is_some() {
+		if self.shape.slot(name).is_some() {
 			return true;
 		}
 		if self.extensible == false {
 			return false;
 		}
-		self.properties.insert(name.to_string(), JsProperty::new());
+		Shape::add_key(&mut self.shape, name);
+		self.slots.push(Some(JsProperty::new()));
 		return true;
 	}
 	pub fn drop_property(&mut self, name: &str) {
-		self.properties.remove(name);
+		if let Some(slot) = Shape::remove_key(&mut self.shape, name) {
+			self.slots[slot] = None;
+			if Shape::compact(&mut self.shape) {
+				self.slots.retain(|prop| prop.is_some());
+			}
+		}
+	}
+	pub(crate) fn own_property(&self, name: &str) -> Option<&JsProperty> {
+		let slot = self.shape.slot(name)?;
+		return self.slots[slot].as_ref();
+	}
+	pub(crate) fn slot_property(&self, slot: usize) -> Option<JsProperty> {
+		return self.slots.get(slot)?.clone();
+	}
+	/* own keys and properties in insertion order */
+	pub(crate) fn own_properties(&self) -> impl Iterator<Item = (&str, &JsProperty)> {
+		return self.shape.slots().filter_map(move |(slot, key)| self.slots[slot].as_ref().map(|prop| (key, prop)));
+	}
+
+	pub(crate) fn charge(&mut self, heap: &SharedHeap, n: usize) {
+		if self.heap.is_none() {
+			self.heap = Some(heap.clone());
+			heap.objects.set(heap.objects.get() + 1);
+		}
+		if let Some(heap) = &self.heap {
+			heap.used.set(heap.used.get() + n);
+		}
+		self.charged = self.charged + n;
+	}
+}
+
+impl JsHeap {
+	pub fn new() -> SharedHeap {
+		Rc::new(JsHeap {
+			used: Cell::new(0),
+			objects: Cell::new(0),
+			collections: Cell::new(0),
+			collected: Cell::new(0),
+			released_hooks: RefCell::new(Vec::new()),
+		})
 	}
 }
 
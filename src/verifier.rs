@@ -0,0 +1,236 @@
+use std::convert::TryFrom;
+
+use crate::bytecode::*;
+use crate::error::*;
+
+/* Checks a VMFunction before it runs, so bytecode from elsewhere can't make jsrun panic:
+   opcodes decode, operands index their tables, jumps land on instructions, every path ends
+   with return/throw/jump, and the stack depth and the try/catch nesting agree where paths merge. */
+
+#[derive(Clone, PartialEq, Debug)]
+struct VerifyState {
+	stack: usize,		// values above the base of the frame
+	tries: Vec<usize>,	// stack depth at each active OP_TRY, an exception drops the stack back to it
+	catches: usize,		// scopes pushed by OP_CATCH
+}
+
+/* an instruction decoded by the first pass, the peephole pass decodes with it too */
+pub(crate) struct DecodedOp {
+	pub op: OpcodeType,
+	pub operand: usize,		// table index, raw integer or jump address
+	pub next: usize,
+}
+
+fn verify_error(pc: usize, msg: String) -> JsError {
+	return JsError::InvalidBytecode(format!("{} @ {}", msg, pc));
+}
+
+/* values pushed by an opcode, the pops are OpcodeType::stack_pops */
+fn stack_pushes(op: OpcodeType) -> usize {
+	match op {
+		OpcodeType::OP_POP => 0,
+		OpcodeType::OP_DUP | OpcodeType::OP_ROT2 => 2,
+		OpcodeType::OP_ROT3 => 3,
+		OpcodeType::OP_DUP2 | OpcodeType::OP_ROT4 => 4,
+		OpcodeType::OP_POSTINC | OpcodeType::OP_POSTDEC => 2,
+
+		OpcodeType::OP_NOP | OpcodeType::OP_EVAL | OpcodeType::OP_DEBUG | OpcodeType::OP_LAST |
+		OpcodeType::OP_TRY | OpcodeType::OP_ENDTRY | OpcodeType::OP_CATCH | OpcodeType::OP_ENDCATCH |
+		OpcodeType::OP_THROW | OpcodeType::OP_JUMP | OpcodeType::OP_JTRUE | OpcodeType::OP_JFALSE |
+		OpcodeType::OP_RETURN | OpcodeType::OP_JCASE => 0,
+
+		_ => 1,
+	}
+}
+
+impl VMFunction {
+	pub(crate) fn decode(&self, pc: usize) -> Result<DecodedOp, JsError> {
+		let op = match OpcodeType::try_from(self.code[pc]) {
+			Ok(op) => op,
+			Err(_) => return Err(verify_error(pc, format!("unknown opcode {}", self.code[pc]))),
+		};
+		let width = match op {
+			OpcodeType::OP_TRY | OpcodeType::OP_JCASE | OpcodeType::OP_JUMP |
+			OpcodeType::OP_JTRUE | OpcodeType::OP_JFALSE => 2,
+
+			OpcodeType::OP_INTEGER | OpcodeType::OP_NUMBER | OpcodeType::OP_STRING | OpcodeType::OP_CLOSURE |
+			OpcodeType::OP_HASVAR | OpcodeType::OP_GETVAR | OpcodeType::OP_SETVAR | OpcodeType::OP_DELVAR |
+			OpcodeType::OP_GETPROP_S | OpcodeType::OP_SETPROP_S | OpcodeType::OP_DELPROP_S |
+			OpcodeType::OP_GETLOCAL | OpcodeType::OP_SETLOCAL |
+			OpcodeType::OP_CALL | OpcodeType::OP_NEW | OpcodeType::OP_CATCH => 1,
+
+			_ => 0,
+		};
+		if pc + 1 + width > self.code.len() {
+			return Err(verify_error(pc, format!("operand of {:?} is out of code", op)));
+		}
+
+		let operand = match width {
+			2 => self.code[pc + 1] as usize + (self.code[pc + 2] as usize) * 65536,
+			1 => self.code[pc + 1] as usize,
+			_ => 0,
+		};
+		let table = match op {
+			OpcodeType::OP_NUMBER => Some(("number", self.num_tab.len())),
+			OpcodeType::OP_CLOSURE => Some(("function", self.func_tab.len())),
+			OpcodeType::OP_STRING | OpcodeType::OP_HASVAR | OpcodeType::OP_GETVAR | OpcodeType::OP_SETVAR |
+			OpcodeType::OP_DELVAR | OpcodeType::OP_DELPROP_S | OpcodeType::OP_CATCH => Some(("string", self.str_tab.len())),
+			OpcodeType::OP_GETPROP_S | OpcodeType::OP_SETPROP_S => Some(("property", self.prop_tab.len())),
+			/* scripts keep all variables in the scope */
+			OpcodeType::OP_GETLOCAL | OpcodeType::OP_SETLOCAL => {
+				Some(("local", if self.script { 0 } else { self.numparams + self.numvars }))
+			},
+			_ => None,
+		};
+		if let Some((name, len)) = table {
+			if operand >= len {
+				return Err(verify_error(pc, format!("{:?} uses {} {} of {}", op, name, operand, len)));
+			}
+		}
+
+		return Ok(DecodedOp {
+			op: op,
+			operand: operand,
+			next: pc + 1 + width,
+		});
+	}
+
+	/* Verify the function and all its nested functions */
+	pub fn verify(&self) -> Result<(), JsError> {
+		if self.numparams + self.numvars > self.str_tab.len() {
+			return Err(verify_error(0, "variables are out of string table".to_string()));
+		}
+		if self.prop_tab.iter().any(|id| *id >= self.str_tab.len()) {
+			return Err(verify_error(0, "property names are out of string table".to_string()));
+		}
+		if !self.locals.is_empty() && (self.script || self.locals.len() != self.numparams + self.numvars) {
+			return Err(verify_error(0, "slots don't match the variables".to_string()));
+		}
+		/* without a scope the call would put variables in the scope of the closure */
+		if !self.script && !self.scope && (self.arguments || self.numparams + self.numvars != self.locals.len() || self.locals.contains(&false)) {
+			return Err(verify_error(0, "variables out of slots need a scope".to_string()));
+		}
+
+		/* first pass, decode all instructions in order */
+		let mut ops: Vec<Option<DecodedOp>> = Vec::new();
+		ops.resize_with(self.code.len(), || None);
+		let mut pc = 0;
+		while pc < self.code.len() {
+			let op = self.decode(pc)?;
+			let next = op.next;
+			ops[pc] = Some(op);
+			pc = next;
+		}
+		for (pc, op) in ops.iter().enumerate() {
+			if let Some(ref op) = op {
+				match op.op {
+					OpcodeType::OP_TRY | OpcodeType::OP_JCASE | OpcodeType::OP_JUMP |
+					OpcodeType::OP_JTRUE | OpcodeType::OP_JFALSE => {
+						if op.operand >= ops.len() || ops[op.operand].is_none() {
+							return Err(verify_error(pc, format!("{:?} jumps to {}, not an instruction", op.op, op.operand)));
+						}
+					},
+					_ => {},
+				}
+			}
+		}
+
+		/* second pass, follow every path with the stack depth and the nesting of try and catch */
+		let mut states: Vec<Option<VerifyState>> = vec![None; self.code.len()];
+		let mut work: Vec<(usize, VerifyState)> = vec![(0, VerifyState { stack: 0, tries: Vec::new(), catches: 0 })];
+		while let Some((pc, state)) = work.pop() {
+			if pc >= self.code.len() {
+				return Err(verify_error(pc, "execution runs out of code".to_string()));
+			}
+			if let Some(ref old) = states[pc] {
+				if *old != state {
+					return Err(verify_error(pc, format!("paths merge with different states {:?} and {:?}", old, state)));
+				}
+				continue;
+			}
+			states[pc] = Some(state.clone());
+
+			let op = ops[pc].as_ref().unwrap();
+			let mut pops = op.op.stack_pops();
+			if op.op == OpcodeType::OP_CALL || op.op == OpcodeType::OP_NEW {
+				pops = pops + op.operand;
+			}
+			if state.stack < pops {
+				return Err(verify_error(pc, format!("stack underflow @ {:?}", op.op)));
+			}
+			/* values under the depth of a try are out of reach until OP_ENDTRY */
+			if state.stack - pops < state.tries.last().copied().unwrap_or(0) {
+				return Err(verify_error(pc, format!("{:?} pops below the depth of OP_TRY", op.op)));
+			}
+			let mut next = state.clone();
+			next.stack = state.stack - pops + stack_pushes(op.op);
+
+			match op.op {
+				OpcodeType::OP_RETURN | OpcodeType::OP_THROW | OpcodeType::OP_DEBUG => {},
+				OpcodeType::OP_JUMP => {
+					work.push((op.operand, next));
+				},
+				OpcodeType::OP_JTRUE | OpcodeType::OP_JFALSE => {
+					work.push((op.operand, next.clone()));
+					work.push((op.next, next));
+				},
+				OpcodeType::OP_JCASE => {
+					/* a matched case pops the value and the case, otherwise only the case */
+					if state.stack < 2 || state.stack - 2 < state.tries.last().copied().unwrap_or(0) {
+						return Err(verify_error(pc, "stack underflow @ OP_JCASE".to_string()));
+					}
+					work.push((op.operand, VerifyState { stack: state.stack - 2, ..state.clone() }));
+					work.push((op.next, VerifyState { stack: state.stack - 1, ..state }));
+				},
+				OpcodeType::OP_TRY => {
+					/* the block is at the address, the handler follows with the exception pushed */
+					let mut block = state.clone();
+					block.tries.push(state.stack);
+					work.push((op.operand, block));
+					work.push((op.next, VerifyState { stack: state.stack + 1, ..state }));
+				},
+				OpcodeType::OP_ENDTRY => {
+					if next.tries.pop().is_none() {
+						return Err(verify_error(pc, "OP_ENDTRY without OP_TRY".to_string()));
+					}
+					work.push((op.next, next));
+				},
+				OpcodeType::OP_CATCH => {
+					next.catches = state.catches + 1;
+					work.push((op.next, next));
+				},
+				OpcodeType::OP_ENDCATCH => {
+					if state.catches == 0 {
+						return Err(verify_error(pc, "OP_ENDCATCH without OP_CATCH".to_string()));
+					}
+					next.catches = state.catches - 1;
+					work.push((op.next, next));
+				},
+				OpcodeType::OP_NEXTITER => {
+					/* pushes a name and true for the loop body, or replaces the iterator with false */
+					let jfalse = match ops.get(op.next) {
+						Some(Some(jfalse)) if jfalse.op == OpcodeType::OP_JFALSE => jfalse,
+						_ => return Err(verify_error(pc, "OP_NEXTITER must be followed by OP_JFALSE".to_string())),
+					};
+					let after = VerifyState { stack: state.stack + 2, ..state.clone() };
+					if let Some(ref old) = states[op.next] {
+						if *old != after {
+							return Err(verify_error(op.next, "paths merge with different states".to_string()));
+						}
+					}
+					states[op.next] = Some(after);
+					work.push((jfalse.operand, VerifyState { stack: state.stack - 1, ..state.clone() }));
+					work.push((jfalse.next, VerifyState { stack: state.stack + 1, ..state }));
+				},
+				_ => {
+					work.push((op.next, next));
+				},
+			}
+		}
+
+		for sub in self.func_tab.iter() {
+			sub.verify()?;
+		}
+		return Ok(());
+	}
+}
@@ -0,0 +1,299 @@
+// Compiled functions saved to bytes and loaded back, like .ezbc files, and verified before they run.
+
+mod common;
+
+use std::rc::Rc;
+
+use ezjs::bytecode::{InlineCaches, OpcodeType, VMFunction};
+use ezjs::error::JsError;
+use ezjs::runtime::JsRuntime;
+use ezjs::value::SharedValue;
+
+use common::NoHook;
+
+const SCRIPT: &str = "
+var rules = [];
+function rule(name, limit) {
+    rules.push(function(v) { return v > limit ? name : ''; });
+}
+rule('hot', 30.5);
+rule('warm', 20);
+var fired = '';
+for (var i = 0; i < rules.length; i++) {
+    try { fired = fired + rules[i](25) + ';'; } catch(e) { fired = 'error'; }
+}
+fired + 'π';
+";
+
+fn run(bytes: &[u8]) -> Result<SharedValue, JsError> {
+    let mut rt = ezjs::new_runtime(NoHook);
+    let vmf = ezjs::build_function_from_bytes(bytes)?;
+    return ezjs::run_script(&mut rt, vmf);
+}
+
+fn invalid(bytes: &[u8]) -> bool {
+    return matches!(ezjs::build_function_from_bytes(bytes), Err(JsError::InvalidBytecode(_)));
+}
+
+#[test]
+fn round_trip_runs_the_same() {
+    let vmf = ezjs::build_function_from_code(SCRIPT).unwrap();
+    let bytes = vmf.to_bytes();
+    assert_eq!(&bytes[0..4], b"EZBC");
+
+    let mut rt: JsRuntime<NoHook> = ezjs::new_runtime(NoHook);
+    let expected = ezjs::run_script(&mut rt, vmf).unwrap().to_string();
+    assert_eq!(expected, ";warm;π");
+    assert_eq!(run(&bytes).unwrap().to_string(), expected);
+
+    // loading and saving again gives the same bytes
+    let loaded = ezjs::build_function_from_bytes(&bytes).unwrap();
+    assert_eq!(loaded.to_bytes(), bytes);
+}
+
+#[test]
+fn module_items_are_kept() {
+    let vmf = ezjs::build_module_from_code("import { a } from 'a.js'; export var b = a; export default 1;").unwrap();
+    let loaded = ezjs::build_function_from_bytes(&vmf.to_bytes()).unwrap();
+    assert_eq!(loaded.imports, vmf.imports);
+    assert_eq!(loaded.exports, vmf.exports);
+}
+
+#[test]
+fn bad_header_is_rejected() {
+    let bytes = ezjs::build_function_from_code("1 + 2;").unwrap().to_bytes();
+    assert!(invalid(b""));
+    assert!(invalid(b"EZB"));
+
+    let mut wrong = bytes.clone();
+    wrong[0] = b'X';
+    assert!(invalid(&wrong));
+
+    // format version, then opcode set version
+    for pos in [4, 6, 8].iter() {
+        let mut wrong = bytes.clone();
+        wrong[*pos] = wrong[*pos].wrapping_add(1);
+        assert!(invalid(&wrong));
+    }
+}
+
+#[test]
+fn truncated_or_padded_bytes_are_rejected() {
+    let bytes = ezjs::build_function_from_code(SCRIPT).unwrap().to_bytes();
+    for len in 0..bytes.len() {
+        assert!(invalid(&bytes[0..len]));
+    }
+    let mut padded = bytes.clone();
+    padded.push(0);
+    assert!(invalid(&padded));
+}
+
+#[test]
+fn huge_table_length_is_rejected() {
+    let mut bytes = ezjs::build_function_from_code("1;").unwrap().to_bytes();
+    // header, script flag, no name, numparams and numvars, then the length of locals
+    let pos = 10 + 2 + 8;
+    bytes[pos..pos + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(invalid(&bytes));
+}
+
+fn function(code: Vec<OpcodeType>, raw: &[(usize, u16)]) -> VMFunction {
+    let mut code: Vec<u16> = code.into_iter().map(|op| op as u16).collect();
+    for (pos, v) in raw {
+        code[*pos] = *v;
+    }
+    return VMFunction {
+        name: None,
+        script: true,
+        numparams: 0,
+        numvars: 0,
+        locals: Vec::new(),
+        arguments: false,
+        scope: true,
+        code: code,
+        num_tab: vec![1.5],
+        str_tab: vec!["x".to_string()],
+        func_tab: Vec::new(),
+        prop_tab: Vec::new(),
+        caches: InlineCaches::default(),
+        jumps: Vec::new(),
+        finally_code: 0,
+        imports: Vec::new(),
+        exports: Vec::new(),
+    };
+}
+
+fn rejected(f: &VMFunction) -> String {
+    match f.verify() {
+        Err(JsError::InvalidBytecode(msg)) => return msg,
+        _ => panic!("bytecode should be rejected"),
+    }
+}
+
+#[test]
+fn compiled_scripts_verify() {
+    for file in ["test_builtin.js", "test_closure.js", "test_language.js", "test_loop.js"].iter() {
+        let source = std::fs::read_to_string(format!("tests/{}", file)).unwrap();
+        let vmf = ezjs::build_function_from_code(&source).unwrap();
+        assert!(vmf.verify().is_ok(), "{}", file);
+    }
+    let vmf = ezjs::build_function_from_code(SCRIPT).unwrap();
+    assert!(vmf.verify().is_ok());
+    let vmf = ezjs::build_function_from_code("
+        for (var k in { a: 1 }) { if (k) break; continue; }
+        function f(o) { for (var k in o) { try { return k; } finally { k = 0; } } }
+        switch (f({ b: 2 })) { case 'a': 1; break; case 'b': 2; default: 3; }
+        try { throw 1; } catch (e) { e; } finally { 0; }
+        function g() { for (;;) { try { return 1; } finally { break; } } for (;;) { try { throw 1; } finally { break; } } try { throw 2; } catch (e) { return e; } finally { return 3; } }
+    ").unwrap();
+    assert!(vmf.verify().is_ok());
+}
+
+#[test]
+fn bad_opcodes_and_operands_are_rejected() {
+    use OpcodeType::*;
+    assert!(function(vec![OP_UNDEF, OP_RETURN], &[]).verify().is_ok());
+    assert!(rejected(&function(vec![OP_UNDEF, OP_RETURN], &[(0, 999)])).starts_with("unknown opcode 999"));
+    assert!(rejected(&function(vec![OP_NUMBER, OP_NOP, OP_RETURN], &[(1, 1)])).contains("number 1 of 1"));
+    assert!(rejected(&function(vec![OP_GETVAR, OP_NOP, OP_RETURN], &[(1, 7)])).contains("string 7 of 1"));
+    assert!(rejected(&function(vec![OP_CLOSURE, OP_NOP, OP_RETURN], &[(1, 0)])).contains("function 0 of 0"));
+    assert!(rejected(&function(vec![OP_UNDEF, OP_GETPROP_S, OP_NOP, OP_RETURN], &[(2, 0)])).contains("property 0 of 0"));
+    let mut prop = function(vec![OP_UNDEF, OP_GETPROP_S, OP_NOP, OP_RETURN], &[(2, 0)]);
+    prop.prop_tab.push(1);
+    assert!(rejected(&prop).contains("property names are out of string table"));
+    assert!(rejected(&function(vec![OP_UNDEF, OP_STRING], &[])).contains("out of code"));
+
+    // the nested functions are verified too
+    let mut outer = function(vec![OP_CLOSURE, OP_NOP, OP_RETURN], &[(1, 0)]);
+    outer.func_tab.push(Rc::new(Box::new(function(vec![OP_POP, OP_RETURN], &[]))));
+    assert!(rejected(&outer).contains("stack underflow"));
+}
+
+#[test]
+fn bad_control_flow_is_rejected() {
+    use OpcodeType::*;
+    // jump into the operand of OP_NUMBER, and past the end
+    assert!(rejected(&function(vec![OP_JUMP, OP_NOP, OP_NOP, OP_NUMBER, OP_NOP, OP_RETURN], &[(1, 4), (2, 0), (4, 0)])).contains("not an instruction"));
+    assert!(rejected(&function(vec![OP_JUMP, OP_NOP, OP_NOP, OP_UNDEF, OP_RETURN], &[(1, 0), (2, 1)])).contains("not an instruction"));
+    // running off the end of code
+    assert!(rejected(&function(vec![OP_UNDEF, OP_POP], &[])).contains("runs out of code"));
+    assert!(rejected(&function(vec![OP_RETURN], &[])).contains("stack underflow"));
+    assert!(rejected(&function(vec![OP_CALL, OP_NOP, OP_UNDEF, OP_RETURN], &[(1, 0)])).contains("stack underflow"));
+}
+
+#[test]
+fn merge_points_and_try_nesting_are_checked() {
+    use OpcodeType::*;
+    // if (true) push one value, else push two, then both paths meet
+    let code = vec![OP_TRUE, OP_JFALSE, OP_NOP, OP_NOP, OP_UNDEF, OP_JUMP, OP_NOP, OP_NOP, OP_UNDEF, OP_UNDEF, OP_RETURN];
+    let msg = rejected(&function(code, &[(2, 8), (3, 0), (6, 10), (7, 0)]));
+    assert!(msg.contains("paths merge with different states"));
+
+    assert!(rejected(&function(vec![OP_ENDTRY, OP_UNDEF, OP_RETURN], &[])).contains("OP_ENDTRY without OP_TRY"));
+    assert!(rejected(&function(vec![OP_ENDCATCH, OP_UNDEF, OP_RETURN], &[])).contains("OP_ENDCATCH without OP_CATCH"));
+
+    // try { } without OP_ENDTRY meets the handler's path
+    let code = vec![OP_TRY, OP_NOP, OP_NOP, OP_POP, OP_JUMP, OP_NOP, OP_NOP, OP_UNDEF, OP_RETURN];
+    let msg = rejected(&function(code.clone(), &[(1, 7), (2, 0), (5, 7), (6, 0)]));
+    assert!(msg.contains("paths merge with different states"));
+    let code = vec![OP_TRY, OP_NOP, OP_NOP, OP_POP, OP_JUMP, OP_NOP, OP_NOP, OP_ENDTRY, OP_UNDEF, OP_RETURN];
+    assert!(function(code, &[(1, 7), (2, 0), (5, 8), (6, 0)]).verify().is_ok());
+
+    // the try block pops a value pushed before OP_TRY
+    let code = vec![OP_UNDEF, OP_TRY, OP_NOP, OP_NOP, OP_RETURN, OP_POP, OP_UNDEF, OP_ENDTRY, OP_RETURN];
+    assert!(rejected(&function(code, &[(2, 5), (3, 0)])).contains("pops below the depth of OP_TRY"));
+}
+
+#[test]
+fn locals_are_checked() {
+    use OpcodeType::*;
+    let vmf = ezjs::build_function_from_code("function f(a) { var b = a; function g() { return a; } return b + g(); } f(2);").unwrap();
+    let f = &vmf.func_tab[0];
+    assert_eq!(f.str_tab[0..3], ["a".to_string(), "b".to_string(), "g".to_string()]);
+    // a is used by g, b and g are only used by f
+    assert_eq!(f.locals, vec![false, true, true]);
+    assert!(f.code.contains(&(OP_GETLOCAL as u16)));
+    assert_eq!(run(&vmf.to_bytes()).unwrap().to_number(), 4.0);
+
+    let mut local = function(vec![OP_GETLOCAL, OP_NOP, OP_RETURN], &[(1, 0)]);
+    assert!(rejected(&local).contains("local 0 of 0"));
+    local.script = false;
+    local.numvars = 1;
+    assert!(local.verify().is_ok());
+    local.locals = vec![true, false];
+    assert!(rejected(&local).contains("slots don't match"));
+}
+
+#[test]
+fn scopes_are_created_when_needed() {
+    let vmf = ezjs::build_function_from_code("
+        function plain(a) { var b = a; function g(b) { return b; } return g(b); }
+        function args() { return arguments.length; }
+        function named(n) { return n > 0 ? named(n - 1) : 0; }
+        function undeclared() { made = 1; }
+        function captured(a) { return function() { return a; }; }
+        plain(2);
+    ").unwrap();
+    let flags: Vec<(bool, bool)> = vmf.func_tab.iter().map(|f| (f.arguments, f.scope)).collect();
+    assert_eq!(flags, vec![(false, false), (true, true), (false, true), (false, true), (false, true)]);
+    assert_eq!(vmf.func_tab[0].locals, vec![true, true, true]);
+    assert_eq!(run(&vmf.to_bytes()).unwrap().to_number(), 2.0);
+
+    let mut f = function(vec![OpcodeType::OP_UNDEF, OpcodeType::OP_RETURN], &[]);
+    f.script = false;
+    f.scope = false;
+    assert!(f.verify().is_ok());
+    f.arguments = true;
+    assert!(rejected(&f).contains("need a scope"));
+    f.arguments = false;
+    f.numvars = 1;
+    assert!(rejected(&f).contains("need a scope"));
+}
+
+#[test]
+fn loading_bytes_verifies_them() {
+    use OpcodeType::*;
+    let f = function(vec![OP_POP, OP_UNDEF, OP_RETURN], &[]);
+    assert!(VMFunction::from_bytes(&f.to_bytes()).is_ok());
+    assert!(invalid(&f.to_bytes()));
+
+    // only script code runs at the top
+    let mut f = function(vec![OP_UNDEF, OP_RETURN], &[]);
+    f.script = false;
+    assert!(invalid(&f.to_bytes()));
+    let mut rt = ezjs::new_runtime(NoHook);
+    assert!(ezjs::run_script(&mut rt, Rc::new(Box::new(f))).is_err());
+}
+
+const MUTATED: &str = "
+var o = { a: 1, get b() { return this.a; }, set c(v) { this.a = v; } };
+var keys = '';
+for (var k in o) { keys = keys + k; }
+function f(n) { try { if (n > 1) throw n; return o.b; } catch(e) { return e; } finally { o.c = n; } }
+f(1) + f(2);
+";
+
+// each byte of saved scripts changed a few ways, whatever still loads must run without a panic
+#[test]
+fn mutated_bytes_never_panic() {
+    let t = std::thread::Builder::new().stack_size(8 * 1024 * 1024).spawn(|| {
+        for script in [SCRIPT, MUTATED].iter() {
+            let bytes = ezjs::build_function_from_code(script).unwrap().to_bytes();
+            for pos in 0..bytes.len() {
+                for delta in [1u8, 2, 0x10, 0x7f, 0x80, 0xff].iter() {
+                    let mut wrong = bytes.clone();
+                    wrong[pos] = wrong[pos].wrapping_add(*delta);
+                    let result = std::panic::catch_unwind(|| {
+                        if let Ok(vmf) = ezjs::build_function_from_bytes(&wrong) {
+                            let mut rt = ezjs::new_runtime(NoHook);
+                            rt.set_fuel(Some(10000));
+                            let _ = ezjs::run_script(&mut rt, vmf);
+                        }
+                    });
+                    assert!(result.is_ok(), "byte {} changed by {}", pos, delta);
+                }
+            }
+        }
+    }).unwrap();
+    t.join().unwrap();
+}
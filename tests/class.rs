@@ -0,0 +1,88 @@
+// Classes defined by the host, instances are hook objects with a prototype.
+
+use std::collections::HashMap;
+
+use ezjs::error::JsError;
+use ezjs::runtime::{Hookable, JsClassMethod, JsRuntime};
+use ezjs::value::SharedValue;
+
+#[derive(Clone)]
+enum Host {
+    Root,
+    Sensor { id: u32, reads: u32 },
+}
+
+impl Hookable for Host {
+    fn name(&self) -> String {
+        return "host".to_string();
+    }
+}
+
+fn sensor_new(rt: &mut JsRuntime<Host>, argc: usize) -> Result<Host, JsError> {
+    let id: u32 = rt.arg(argc, 0)?;
+    return Ok(Host::Sensor { id: id, reads: 0 });
+}
+
+fn sensor_read(hook: &mut Host, rt: &mut JsRuntime<Host>, _argc: usize) -> Result<(), JsError> {
+    if let Host::Sensor { id, reads } = hook {
+        *reads = *reads + 1;
+        rt.push_value(*id * 10 + *reads);
+        return Ok(());
+    }
+    return Err(rt.new_type_error("not a sensor".to_string()));
+}
+
+fn sensor_id(hook: &mut Host, rt: &mut JsRuntime<Host>, _argc: usize) -> Result<(), JsError> {
+    if let Host::Sensor { id, .. } = hook {
+        rt.push_value(*id);
+        return Ok(());
+    }
+    return Err(rt.new_type_error("not a sensor".to_string()));
+}
+
+fn new_runtime() -> JsRuntime<Host> {
+    let mut rt = ezjs::new_runtime(Host::Root);
+    let mut methods: HashMap<String, JsClassMethod<Host>> = HashMap::new();
+    methods.insert("read".to_string(), sensor_read);
+    let mut getters: HashMap<String, JsClassMethod<Host>> = HashMap::new();
+    getters.insert("id".to_string(), sensor_id);
+    rt.define_class("Sensor", sensor_new, methods, getters);
+    return rt;
+}
+
+fn eval(rt: &mut JsRuntime<Host>, script: &str) -> Result<SharedValue, JsError> {
+    let vmf = ezjs::build_function_from_code(script)?;
+    return ezjs::run_script(rt, vmf);
+}
+
+#[test]
+fn new_instance_calls_methods() {
+    let mut rt = new_runtime();
+    assert_eq!(eval(&mut rt, "new Sensor(3).read();").unwrap().to_number(), 31.0);
+    assert_eq!(eval(&mut rt, "var s = new Sensor(4); s.read(); s.read();").unwrap().to_number(), 42.0);
+    assert_eq!(eval(&mut rt, "s.id;").unwrap().to_number(), 4.0);
+    assert!(eval(&mut rt, "s instanceof Sensor;").unwrap().to_boolean());
+    assert!(!eval(&mut rt, "({}) instanceof Sensor;").unwrap().to_boolean());
+    assert!(eval(&mut rt, "s.constructor === Sensor;").unwrap().to_boolean());
+}
+
+#[test]
+fn constructor_errors_are_catchable() {
+    let mut rt = new_runtime();
+    let msg = eval(&mut rt, "var r; try { new Sensor('x'); } catch(e) { r = e.message(); } r;").unwrap();
+    assert_eq!(msg.to_string(), "TypeError: expected u32 but got string");
+}
+
+#[test]
+fn method_checks_receiver() {
+    let mut rt = new_runtime();
+    let msg = eval(&mut rt, "var r; try { Sensor.prototype.read.call({}); } catch(e) { r = e.message(); } r;").unwrap();
+    assert_eq!(msg.to_string(), "TypeError: Sensor.prototype.read called on incompatible receiver");
+}
+
+#[test]
+fn instances_release_hooks() {
+    let mut rt = new_runtime();
+    eval(&mut rt, "for (var i = 0; i < 10; i++) { new Sensor(i).read(); }").unwrap();
+    assert!(rt.hooks.is_empty());
+}
@@ -0,0 +1,27 @@
+// Helpers shared by the integration tests, a test file takes them with `mod common;`.
+#![allow(dead_code)]
+
+use ezjs::error::JsError;
+use ezjs::runtime::{Hookable, JsRuntime};
+use ezjs::value::SharedValue;
+
+#[derive(Clone)]
+pub struct NoHook;
+
+impl Hookable for NoHook {
+    fn name(&self) -> String {
+        return "none".to_string();
+    }
+}
+
+/* runs a script in rt, its completion value is returned */
+pub fn eval(rt: &mut JsRuntime<NoHook>, script: &str) -> Result<SharedValue, JsError> {
+    let vmf = ezjs::build_function_from_code(script)?;
+    return ezjs::run_script(rt, vmf);
+}
+
+/* runs a script in a new runtime, its completion value is returned as a string */
+pub fn eval_string(script: &str) -> String {
+    let mut rt: JsRuntime<NoHook> = ezjs::new_runtime(NoHook);
+    return eval(&mut rt, script).unwrap().to_string();
+}
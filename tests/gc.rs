@@ -0,0 +1,96 @@
+// Cycle collection, unreachable cycles are reclaimed and reachable values are kept intact.
+
+mod common;
+
+use ezjs::error::JsError;
+
+use common::{eval, NoHook};
+
+#[test]
+fn self_reference_is_collected() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    rt.gc();
+    let before = rt.heap_stats();
+
+    eval(&mut rt, "function f() { var a = {}; a.self = a; var b = [1]; b.push(b); } for (var i = 0; i < 100; i++) { f(); }").unwrap();
+    let leaked = rt.heap_stats();
+    assert!(leaked.used > before.used);
+
+    let n = rt.gc();
+    assert!(n >= 200);
+    let after = rt.heap_stats();
+    assert!(after.objects < leaked.objects);
+    assert!(after.used < leaked.used);
+    assert_eq!(after.collections, before.collections + 1);
+    assert_eq!(after.collected, before.collected + n);
+}
+
+#[test]
+fn closure_scope_cycle_is_collected() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    eval(&mut rt, "function make() { var x = 1; function inner() { return x; } return inner; }").unwrap();
+    rt.gc();
+    let before = rt.heap_stats();
+
+    eval(&mut rt, "for (var i = 0; i < 100; i++) { make(); }").unwrap();
+    assert!(rt.gc() >= 100);
+    assert!(rt.heap_stats().objects <= before.objects + 1);
+}
+
+#[test]
+fn reachable_values_are_kept() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    eval(&mut rt, "var a = { n: 42 }; a.self = a; function counter() { var c = 0; return function() { c++; return c; }; } var next = counter();").unwrap();
+    let held = eval(&mut rt, "(function() { var t = { v: 7 }; t.self = t; return t; })();").unwrap();
+
+    rt.gc();
+    assert_eq!(eval(&mut rt, "a.self.self.n;").unwrap().to_number(), 42.0);
+    assert_eq!(eval(&mut rt, "next(); next();").unwrap().to_number(), 2.0);
+
+    let obj = held.get_object();
+    let obj = obj.borrow();
+    assert_eq!(obj.get_property("v").value.to_number(), 7.0);
+    assert!(obj.get_property("self").value.is_object());
+}
+
+#[test]
+fn memory_limit_collects_before_failing() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    rt.gc();
+    let base = rt.memory_usage();
+    rt.set_memory_limit(Some(base + 256 * 1024));
+
+    eval(&mut rt, "for (var i = 0; i < 20000; i++) { var a = {}; a.self = a; }").unwrap();
+    assert!(rt.heap_stats().collections > 0);
+    assert!(rt.memory_usage() <= base + 256 * 1024);
+}
+
+#[test]
+fn stats_are_kept_per_runtime() {
+    let mut a = ezjs::new_runtime(NoHook);
+    let b = ezjs::new_runtime(NoHook);
+    let before = b.heap_stats();
+
+    eval(&mut a, "var keep = []; for (var i = 0; i < 1000; i++) { keep.push({}); }").unwrap();
+    assert!(a.heap_stats().objects >= 1000);
+    assert_eq!(b.heap_stats().objects, before.objects);
+
+    eval(&mut a, "keep = null;").unwrap();
+    assert!(a.heap_stats().objects < 1000);
+}
+
+#[test]
+fn usage_over_the_limit_is_collected_once() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    eval(&mut rt, "var keep = []; for (var i = 0; i < 1000; i++) { keep.push({}); }").unwrap();
+    rt.set_memory_limit(Some(rt.memory_usage() / 2));
+    let before = rt.heap_stats().collections;
+
+    for _ in 0..3 {
+        assert!(matches!(eval(&mut rt, "for (var i = 0; i < 100; i++) {}"), Err(JsError::OutOfMemory)));
+    }
+    assert_eq!(rt.heap_stats().collections, before + 1);
+
+    rt.set_memory_limit(Some(rt.memory_usage() * 2));
+    eval(&mut rt, "keep.length;").unwrap();
+}
@@ -0,0 +1,206 @@
+// Hook objects, the hook value is finalized once when its object becomes unreachable,
+// and property access or calls on the object are dispatched to the hook.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ezjs::error::JsError;
+use ezjs::runtime::{Hookable, JsBuiltinFunction, JsRuntime};
+use ezjs::value::{SharedObject_new, SharedValue};
+
+#[derive(Clone)]
+struct Device {
+    name: String,
+    temperature: f64,
+    reset: SharedValue,
+    finalized: Rc<RefCell<Vec<String>>>,
+}
+
+impl Hookable for Device {
+    fn name(&self) -> String {
+        return self.name.clone();
+    }
+    fn finalize(&mut self) {
+        self.finalized.borrow_mut().push(self.name.clone());
+    }
+
+    fn get_property(&mut self, rt: &mut JsRuntime<Self>, name: &str) -> Result<Option<SharedValue>, JsError> {
+        match name {
+            "name" => return Ok(Some(rt.new_string(self.name.clone()))),
+            "temperature" => return Ok(Some(SharedValue::new_number(self.temperature))),
+            "reset" => return Ok(Some(self.reset.clone())),
+            _ => return Ok(None),
+        }
+    }
+    fn set_property(&mut self, rt: &mut JsRuntime<Self>, name: &str, value: SharedValue) -> Result<bool, JsError> {
+        match name {
+            "temperature" => {
+                self.temperature = ezjs::convert::FromJs::from_js(rt, &value)?;
+                return Ok(true);
+            },
+            "name" => return Err(rt.new_type_error("name is read only".to_string())),
+            _ => return Ok(false),
+        }
+    }
+    fn has_property(&mut self, _rt: &mut JsRuntime<Self>, name: &str) -> Result<Option<bool>, JsError> {
+        return Ok(Some(name == "name" || name == "temperature" || name == "reset"));
+    }
+    fn delete_property(&mut self, _rt: &mut JsRuntime<Self>, name: &str) -> Result<Option<bool>, JsError> {
+        return Ok(Some(name != "name" && name != "temperature" && name != "reset"));
+    }
+    fn own_keys(&mut self, _rt: &mut JsRuntime<Self>) -> Option<Vec<String>> {
+        return Some(vec!["name".to_string(), "temperature".to_string()]);
+    }
+    fn call(&mut self, rt: &mut JsRuntime<Self>, argc: usize) -> Result<(), JsError> {
+        let delta: f64 = rt.arg(argc, 0)?;
+        rt.push_number(self.temperature + delta);
+        return Ok(());
+    }
+}
+
+fn device_reset(rt: &mut JsRuntime<Device>, argc: usize) -> Result<(), JsError> {
+    let thiz = rt.top(-1 - argc as isize);
+    if !thiz.is_object() || !thiz.get_object().borrow().is_hook() {
+        return Err(rt.new_type_error("reset needs a device".to_string()));
+    }
+    let hid = thiz.get_object().borrow().get_hook();
+    rt.hooks.get_mut(&hid).unwrap().temperature = 0.0;
+    rt.push_undefined();
+    return Ok(());
+}
+
+fn new_runtime() -> (JsRuntime<Device>, Rc<RefCell<Vec<String>>>) {
+    let finalized = Rc::new(RefCell::new(Vec::new()));
+    let root = Device {
+        name: "root".to_string(),
+        temperature: 0.0,
+        reset: SharedValue::new_undefined(),
+        finalized: finalized.clone(),
+    };
+    let mut rt = ezjs::new_runtime(root);
+    let reset = SharedValue::new_object(rt.new_builtin(JsBuiltinFunction::new(device_reset)));
+
+    let log = finalized.clone();
+    let fobj = rt.new_builtin(JsBuiltinFunction::new_closure(move |rt, argc| {
+        let name: String = rt.arg(argc, 0)?;
+        let hook = rt.new_hook(Device {
+            name: name,
+            temperature: 20.0,
+            reset: reset.clone(),
+            finalized: log.clone(),
+        });
+        rt.push_object(SharedObject_new(hook));
+        return Ok(());
+    }));
+    rt.genv.borrow_mut().init_var("device", SharedValue::new_object(fobj));
+    return (rt, finalized);
+}
+
+fn eval(rt: &mut JsRuntime<Device>, script: &str) -> Result<SharedValue, JsError> {
+    let vmf = ezjs::build_function_from_code(script)?;
+    return ezjs::run_script(rt, vmf);
+}
+
+#[test]
+fn overwritten_hook_is_finalized() {
+    let (mut rt, finalized) = new_runtime();
+    eval(&mut rt, "var a = device('a'); var b = a;").unwrap();
+    eval(&mut rt, "a = null;").unwrap();
+    assert!(finalized.borrow().is_empty());
+    assert_eq!(rt.hooks.len(), 1);
+
+    eval(&mut rt, "b = 1;").unwrap();
+    assert_eq!(*finalized.borrow(), vec!["a".to_string()]);
+    assert!(rt.hooks.is_empty());
+}
+
+#[test]
+fn hook_in_array_and_closure_is_finalized() {
+    let (mut rt, finalized) = new_runtime();
+    eval(&mut rt, "var list = [device('x'), device('y')]; var f = (function() { var d = device('z'); return function() { return d; }; })();").unwrap();
+    assert_eq!(rt.hooks.len(), 3);
+
+    eval(&mut rt, "list = null; device('tmp'); 0;").unwrap();
+    assert_eq!(finalized.borrow().len(), 3);
+    assert_eq!(rt.hooks.len(), 1);
+
+    // a closure is in a cycle with its scope, the collector releases it
+    eval(&mut rt, "f = null;").unwrap();
+    rt.gc();
+    assert!(finalized.borrow().contains(&"z".to_string()));
+    assert!(rt.hooks.is_empty());
+}
+
+#[test]
+fn hook_in_cycle_is_finalized_by_gc() {
+    let (mut rt, finalized) = new_runtime();
+    eval(&mut rt, "(function() { var o = { d: device('c') }; o.self = o; })();").unwrap();
+    assert!(finalized.borrow().is_empty());
+
+    rt.gc();
+    assert_eq!(*finalized.borrow(), vec!["c".to_string()]);
+    assert!(rt.hooks.is_empty());
+}
+
+#[test]
+fn runtime_drop_finalizes_remaining_hooks() {
+    let (mut rt, finalized) = new_runtime();
+    eval(&mut rt, "var keep = device('k'); device('t'); 0;").unwrap();
+    assert_eq!(*finalized.borrow(), vec!["t".to_string()]);
+
+    drop(rt);
+    assert_eq!(*finalized.borrow(), vec!["t".to_string(), "k".to_string()]);
+}
+
+#[test]
+fn property_access_is_dispatched_to_hook() {
+    let (mut rt, _) = new_runtime();
+    eval(&mut rt, "var d = device('fan');").unwrap();
+    assert_eq!(eval(&mut rt, "d.name;").unwrap().to_string(), "fan");
+    assert_eq!(eval(&mut rt, "d.temperature;").unwrap().to_number(), 20.0);
+
+    eval(&mut rt, "d.temperature = 31.5; d['temperature'] += 1;").unwrap();
+    assert_eq!(eval(&mut rt, "d.temperature;").unwrap().to_number(), 32.5);
+
+    let msg = eval(&mut rt, "var r; try { d.name = 'x'; } catch(e) { r = e.message(); } r;").unwrap();
+    assert_eq!(msg.to_string(), "TypeError: name is read only");
+
+    eval(&mut rt, "d.reset();").unwrap();
+    assert_eq!(eval(&mut rt, "d.temperature;").unwrap().to_number(), 0.0);
+    let d = eval(&mut rt, "d;").unwrap();
+    assert_eq!(rt.get_hook(&d).temperature, 0.0);
+}
+
+#[test]
+fn unknown_properties_fall_back_to_object() {
+    let (mut rt, _) = new_runtime();
+    eval(&mut rt, "var d = device('fan');").unwrap();
+    assert!(eval(&mut rt, "d.missing;").unwrap().is_undefined());
+    assert!(eval(&mut rt, "d.other = 1;").is_err());
+}
+
+#[test]
+fn in_delete_and_for_in_are_dispatched_to_hook() {
+    let (mut rt, _) = new_runtime();
+    eval(&mut rt, "var d = device('fan');").unwrap();
+    assert!(eval(&mut rt, "'temperature' in d;").unwrap().to_boolean());
+    assert!(!eval(&mut rt, "'speed' in d;").unwrap().to_boolean());
+    assert!(!eval(&mut rt, "delete d.temperature;").unwrap().to_boolean());
+    assert!(eval(&mut rt, "delete d.speed;").unwrap().to_boolean());
+
+    let keys = eval(&mut rt, "var k = ''; for (var p in d) { k = k + p + ','; } k;").unwrap();
+    assert_eq!(keys.to_string(), "name,temperature,");
+}
+
+#[test]
+fn call_is_dispatched_to_hook() {
+    let (mut rt, _) = new_runtime();
+    eval(&mut rt, "var d = device('fan');").unwrap();
+    assert_eq!(eval(&mut rt, "d(2);").unwrap().to_number(), 22.0);
+
+    let d = eval(&mut rt, "d;").unwrap();
+    let value = rt.call(&d, SharedValue::new_undefined(), &[SharedValue::new_number(5.0)]).unwrap();
+    assert_eq!(value.to_number(), 25.0);
+
+    assert!(eval(&mut rt, "d('x');").is_err());
+}
@@ -0,0 +1,187 @@
+// Host functions registered from Rust and called by scripts.
+
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ezjs::runtime::{JsBuiltinFunction, JsRuntime};
+use ezjs::value::SharedValue;
+
+use common::{eval, NoHook};
+
+fn register(rt: &mut JsRuntime<NoHook>, name: &str, f: JsBuiltinFunction<NoHook>) {
+    let fobj = rt.new_builtin(f);
+    rt.genv.borrow_mut().init_var(name, SharedValue::new_object(fobj));
+}
+
+#[test]
+fn closure_keeps_own_state() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    let mut counter = 0;
+    register(&mut rt, "tick", JsBuiltinFunction::new_closure(move |rt, _argc| {
+        counter = counter + 1;
+        rt.push_number(counter as f64);
+        return Ok(());
+    }));
+
+    let value = eval(&mut rt, "tick(); tick(); tick();").unwrap();
+    assert_eq!(value.to_number(), 3.0);
+}
+
+#[test]
+fn closure_shares_state_with_host() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    let events: Rc<RefCell<Vec<String>>> = Rc::new(RefCell::new(Vec::new()));
+    let sender = events.clone();
+    register(&mut rt, "emit", JsBuiltinFunction::new_closure(move |rt, argc| {
+        if argc != 1 {
+            return Err(rt.new_type_error("emit needs one argument".to_string()));
+        }
+        sender.borrow_mut().push(rt.top(-1).to_string());
+        rt.push_undefined();
+        return Ok(());
+    }));
+
+    eval(&mut rt, "emit('a'); emit('b');").unwrap();
+    assert_eq!(*events.borrow(), vec!["a".to_string(), "b".to_string()]);
+
+    let value = eval(&mut rt, "var r; try { emit(); } catch(e) { r = e.message(); } r;").unwrap();
+    assert_eq!(value.to_string(), "TypeError: emit needs one argument");
+}
+
+#[test]
+fn closure_reentry_is_an_error() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    register(&mut rt, "again", JsBuiltinFunction::new_closure(move |rt, _argc| {
+        let func = rt.top(-1);
+        rt.push(func);
+        rt.push_undefined();
+        ezjs::runtime::jscall(rt, 0)?;
+        return Ok(());
+    }));
+
+    let result = eval(&mut rt, "function f() { return again(f); } f();");
+    assert!(result.is_err());
+}
+
+#[test]
+fn call_script_function_from_host() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    eval(&mut rt, "var seen = 0; function onEvent(e, n) { seen = seen + n; return e + '!' ; }").unwrap();
+
+    let args = vec![rt.new_string("hello".to_string()), SharedValue::new_number(2.0)];
+    let value = rt.call_global("onEvent", &args).unwrap();
+    assert_eq!(value.to_string(), "hello!");
+    assert_eq!(rt.stack.len(), 0);
+
+    let handler = eval(&mut rt, "onEvent;").unwrap();
+    let value = rt.call(&handler, SharedValue::new_undefined(), &args).unwrap();
+    assert_eq!(value.to_string(), "hello!");
+    assert_eq!(eval(&mut rt, "seen;").unwrap().to_number(), 4.0);
+}
+
+#[test]
+fn call_keeps_stack_balanced_on_exception() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    eval(&mut rt, "function fail() { var a = [1, 2]; throw new Error('bad'); }").unwrap();
+
+    let result = rt.call_global("fail", &[]);
+    assert_eq!(result.err().unwrap().message(), "bad");
+    assert_eq!(rt.stack.len(), 0);
+
+    assert!(rt.call_global("missing", &[]).is_err());
+    assert!(rt.call(&SharedValue::new_number(1.0), SharedValue::new_undefined(), &[]).is_err());
+    assert_eq!(rt.stack.len(), 0);
+
+    let value = eval(&mut rt, "1 + 1;").unwrap();
+    assert_eq!(value.to_number(), 2.0);
+}
+
+#[test]
+fn call_with_this_from_host_function() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    register(&mut rt, "invoke", JsBuiltinFunction::new_closure(move |rt, argc| {
+        if argc != 2 {
+            return Err(rt.new_type_error("invoke needs an object and a function".to_string()));
+        }
+        let thiz = rt.top(-2);
+        let func = rt.top(-1);
+        let value = rt.call(&func, thiz, &[SharedValue::new_number(1.0)])?;
+        rt.push(value);
+        return Ok(());
+    }));
+
+    let value = eval(&mut rt, "var o = { x: 41 }; invoke(o, function(n) { return this.x + n; });").unwrap();
+    assert_eq!(value.to_number(), 42.0);
+}
+
+#[test]
+fn typed_arguments_and_results() {
+    use std::collections::HashMap;
+
+    let mut rt = ezjs::new_runtime(NoHook);
+    register(&mut rt, "summary", JsBuiltinFunction::new_closure(move |rt, argc| {
+        let name: String = rt.arg(argc, 0)?;
+        let scores: Vec<u32> = rt.arg(argc, 1)?;
+        let tags: HashMap<String, bool> = rt.arg(argc, 2)?;
+        let limit: Option<f64> = rt.arg(argc, 3)?;
+
+        let total: u32 = scores.iter().sum();
+        let mut keys: Vec<String> = tags.into_iter().filter(|(_, v)| *v).map(|(k, _)| k).collect();
+        keys.sort();
+        rt.push_value((name, total, keys, limit));
+        return Ok(());
+    }));
+
+    let value = eval(&mut rt, "var r = summary('abc', [1, 2, 3], {x: true, y: false, z: true}); r;").unwrap();
+    let (name, total, keys, limit): (String, i64, Vec<String>, Option<f64>) = ezjs::convert::FromJs::from_js(&rt, &value).unwrap();
+    assert_eq!(name, "abc");
+    assert_eq!(total, 6);
+    assert_eq!(keys, vec!["x".to_string(), "z".to_string()]);
+    assert_eq!(limit, None);
+
+    let value = eval(&mut rt, "r[0] + r[1] + r[2] + r[3];").unwrap();
+    assert_eq!(value.to_string(), "abc6x, znull");
+}
+
+#[test]
+fn conversion_failure_is_type_error() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    register(&mut rt, "half", JsBuiltinFunction::new_closure(move |rt, argc| {
+        let v: u8 = rt.arg(argc, 0)?;
+        rt.push_value(v / 2);
+        return Ok(());
+    }));
+
+    assert_eq!(eval(&mut rt, "half(10);").unwrap().to_number(), 5.0);
+    let script = "var m = []; \
+                  try { half('10'); } catch(e) { m.push(e.message()); } \
+                  try { half(1.5); } catch(e) { m.push(e.message()); } \
+                  try { half(256); } catch(e) { m.push(e.message()); } \
+                  try { half(); } catch(e) { m.push(e.message()); } \
+                  m;";
+    let value = eval(&mut rt, script).unwrap();
+    let messages: Vec<String> = ezjs::convert::FromJs::from_js(&rt, &value).unwrap();
+    assert_eq!(messages, vec![
+        "TypeError: expected u8 but got string".to_string(),
+        "TypeError: 1.5 can not be converted to u8".to_string(),
+        "TypeError: 256 can not be converted to u8".to_string(),
+        "TypeError: expected u8 but got undefined".to_string(),
+    ]);
+}
+
+#[test]
+fn type_errors_are_instances_of_type_error() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    register(&mut rt, "fail", JsBuiltinFunction::new(|rt, _argc| {
+        return Err(rt.new_type_error("bad argument".to_string()));
+    }));
+
+    let r = eval(&mut rt, "var e; try { fail(); } catch(x) { e = x; } '' + (e instanceof TypeError) + (e instanceof Error) + e.message();").unwrap();
+    assert_eq!(r.to_string(), "truetrueTypeError: bad argument");
+    let r = eval(&mut rt, "var a = {}; try { Object.setPrototypeOf(a, a); } catch(x) { e = x; } '' + (e instanceof TypeError);").unwrap();
+    assert_eq!(r.to_string(), "true");
+    let r = eval(&mut rt, "var t = new TypeError('x'); '' + (t instanceof TypeError) + (t instanceof Error) + t.message() + (new Error('y') instanceof TypeError);").unwrap();
+    assert_eq!(r.to_string(), "truetrueTypeError: xfalse");
+}
@@ -0,0 +1,138 @@
+// Property access through the inline caches of OP_GETPROP_S/OP_SETPROP_S gives the same results
+// when objects, their keys or their prototypes change between two runs of an instruction.
+
+mod common;
+
+use ezjs::bytecode::OpcodeType;
+
+use common::eval_string;
+
+#[test]
+fn each_access_has_an_entry() {
+    let vmf = ezjs::build_function_from_code("var o = { a: 1 }; o.a = o.a + o.b; o.a;").unwrap();
+    assert_eq!(vmf.prop_tab.len(), 4);
+    for id in vmf.prop_tab.iter() {
+        assert!(vmf.str_tab[*id] == "a" || vmf.str_tab[*id] == "b");
+    }
+    assert!(vmf.code.contains(&(OpcodeType::OP_GETPROP_S as u16)));
+    assert!(vmf.verify().is_ok());
+}
+
+#[test]
+fn records_of_one_literal_share_the_cache() {
+    let script = "
+        var l = [];
+        for (var i = 0; i < 4; i++) {
+            l.push({ a: i * 1, b: 'x' });
+        }
+        var s = '';
+        for (var i = 0; i < 4; i++) {
+            l[i].a = l[i].a * 2;
+            s = s + l[i].a + l[i].b;
+        }
+        s;
+    ";
+    assert_eq!(eval_string(script), "0x2x4x6x");
+}
+
+#[test]
+fn own_properties_hide_prototypes() {
+    let script = "
+        function P() {}
+        P.prototype.m = function() { return 'p'; };
+        var a = new P();
+        var b = new P();
+        var r = '';
+        var l = [a, b, a, b];
+        for (var i = 0; i < 4; i++) {
+            r = r + l[i].m();
+            if (i == 1) b.m = function() { return 'b'; };
+        }
+        a.m = function() { return 'a'; };
+        for (var i = 0; i < 2; i++) {
+            r = r + a.m();
+            delete a.m;
+        }
+        r;
+    ";
+    assert_eq!(eval_string(script), "pppbap");
+}
+
+#[test]
+fn changed_prototypes_are_seen() {
+    // a key added to a prototype in the middle of the chain
+    let script = "
+        var base = { v: 1 };
+        var mid = {};
+        Object.setPrototypeOf(mid, base);
+        var o = {};
+        Object.setPrototypeOf(o, mid);
+        var r = '';
+        for (var i = 0; i < 3; i++) {
+            r = r + o.v;
+            if (i == 0) mid.v = 2;
+        }
+        r;
+    ";
+    assert_eq!(eval_string(script), "122");
+
+    // objects of one shape with other prototypes, and a replaced prototype
+    let script = "
+        var x = {};
+        Object.setPrototypeOf(x, { v: 'x' });
+        var y = {};
+        Object.setPrototypeOf(y, { v: 'y' });
+        var r = '';
+        var l = [x, y, x];
+        for (var i = 0; i < 3; i++) {
+            r = r + l[i].v;
+        }
+        Object.setPrototypeOf(x, { v: 'z' });
+        for (var i = 0; i < 3; i++) {
+            r = r + l[i].v;
+        }
+        r;
+    ";
+    assert_eq!(eval_string(script), "xyxzyz");
+}
+
+#[test]
+fn accessors_and_writes_follow_the_property() {
+    // a cached data property redefined as a getter
+    let script = "
+        var o = { v: 1 };
+        var r = '';
+        for (var i = 0; i < 3; i++) {
+            r = r + o.v;
+            if (i == 0) Object.defineProperty(o, 'v', { get: function() { return 9; } });
+        }
+        r;
+    ";
+    assert_eq!(eval_string(script), "199");
+
+    // writing a key of the prototype creates an own one
+    let script = "
+        var p = { v: 1 };
+        var o = {};
+        Object.setPrototypeOf(o, p);
+        for (var i = 0; i < 3; i++) {
+            o.v = o.v + 1;
+        }
+        '' + o.v + p.v;
+    ";
+    assert_eq!(eval_string(script), "41");
+}
+
+#[test]
+fn accesses_past_a_full_table_run_uncached() {
+    let mut script = "var o = { b: 0 };\n".to_string();
+    for _ in 0..40000 {
+        script.push_str("o.b = o.b + 1;\n");
+    }
+    script.push_str("o.b;");
+    let vmf = ezjs::build_function_from_code(&script).unwrap();
+    assert_eq!(vmf.prop_tab.len(), 0x10000);
+    assert!(vmf.code.contains(&(OpcodeType::OP_SETPROP as u16)));
+    assert!(vmf.verify().is_ok());
+    assert_eq!(eval_string(&script), "40000");
+}
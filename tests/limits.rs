@@ -0,0 +1,136 @@
+// Execution limits of runtime, the runtime must stay usable after a script is stopped.
+
+mod common;
+
+use ezjs::error::JsError;
+use ezjs::value::SharedValue;
+
+use common::{eval, NoHook};
+
+#[test]
+fn fuel_stops_endless_loop() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    rt.set_fuel(Some(10000));
+
+    let result = eval(&mut rt, "function spin() { while(true) {} } try { spin(); } catch(e) { }");
+    match result {
+        Err(JsError::OutOfFuel) => {},
+        _ => panic!("expected OutOfFuel"),
+    }
+    assert_eq!(rt.fuel, Some(0));
+    assert_eq!(rt.stack.len(), 0);
+    assert_eq!(rt.call_depth, 0);
+    assert!(std::rc::Rc::ptr_eq(&rt.cenv, &rt.genv));
+
+    rt.add_fuel(1000);
+    let value = eval(&mut rt, "var a = 1; a + 1;").unwrap();
+    assert_eq!(value.to_number(), 2.0);
+    assert!(rt.fuel.unwrap() < 1000);
+}
+
+#[test]
+fn fuel_used_up_in_top_level_catch() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    rt.set_fuel(Some(10000));
+
+    let result = eval(&mut rt, "try { throw 1; } catch(e) { while(true) {} }");
+    assert!(matches!(result, Err(JsError::OutOfFuel)));
+    assert!(std::rc::Rc::ptr_eq(&rt.cenv, &rt.genv));
+
+    rt.add_fuel(1000);
+    assert_eq!(eval(&mut rt, "typeof e;").unwrap().to_string(), "undefined");
+    eval(&mut rt, "var b = 2;").unwrap();
+    assert!(rt.genv.borrow().query_variable("b"));
+}
+
+#[test]
+fn fuel_stops_host_call() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    eval(&mut rt, "function count(n) { var s = 0; for (var i = 0; i < n; i++) { s = s + i; } return s; }").unwrap();
+
+    rt.set_fuel(Some(100));
+    let result = rt.call_global("count", &[SharedValue::new_number(1000000.0)]);
+    assert!(matches!(result, Err(JsError::OutOfFuel)));
+    assert_eq!(rt.stack.len(), 0);
+
+    rt.set_fuel(None);
+    let value = rt.call_global("count", &[SharedValue::new_number(10.0)]).unwrap();
+    assert_eq!(value.to_number(), 45.0);
+}
+
+#[test]
+fn interrupt_from_another_thread() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    let handle = rt.interrupt_handle();
+    let timer = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        handle.interrupt();
+    });
+
+    let result = eval(&mut rt, "function spin() { while(true) {} } \
+                                while(true) { try { spin(); } catch(e) { } }");
+    timer.join().unwrap();
+    assert!(matches!(result, Err(JsError::Interrupted)));
+    assert_eq!(rt.stack.len(), 0);
+    assert!(std::rc::Rc::ptr_eq(&rt.cenv, &rt.genv));
+
+    let value = eval(&mut rt, "1 + 2;").unwrap();
+    assert_eq!(value.to_number(), 3.0);
+}
+
+#[test]
+fn interrupt_flag_is_cleared_once_reported() {
+    let rt = ezjs::new_runtime(NoHook);
+    rt.interrupt_handle().interrupt();
+    let result = rt.check_interrupt();
+    assert!(matches!(result, Err(JsError::Interrupted)));
+    assert!(rt.check_interrupt().is_ok());
+
+    fn assert_send_sync<H: Send + Sync>(_h: &H) {}
+    assert_send_sync(&rt.interrupt_handle());
+}
+
+#[test]
+fn interrupt_while_idle_is_dropped() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    rt.interrupt_handle().interrupt();
+    assert_eq!(eval(&mut rt, "1 + 2;").unwrap().to_number(), 3.0);
+
+    eval(&mut rt, "function f() { return 4; }").unwrap();
+    rt.interrupt_handle().interrupt();
+    assert_eq!(rt.call_global("f", &[]).unwrap().to_number(), 4.0);
+}
+
+#[test]
+fn memory_limit_stops_growing_script() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    let base = rt.memory_usage();
+    rt.set_memory_limit(Some(base + 1024 * 1024));
+
+    let result = eval(&mut rt, "var a = []; while(true) { a.push('item ' + a.length); }");
+    assert!(matches!(result, Err(JsError::OutOfMemory)));
+    assert!(rt.memory_usage() > base + 512 * 1024);
+    assert_eq!(rt.stack.len(), 0);
+
+    let result = eval(&mut rt, "var s = 'x'; while(true) { try { s = s + s; } catch(e) { } }");
+    assert!(matches!(result, Err(JsError::OutOfMemory)));
+
+    rt.set_memory_limit(None);
+    eval(&mut rt, "a = null; s = null;").unwrap();
+    assert!(rt.memory_usage() < base + 64 * 1024);
+}
+
+#[test]
+fn memory_is_released_after_return() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    eval(&mut rt, "function build(n) { var o = {}; for (var i = 0; i < n; i++) { o['k' + i] = [i, {v: i}]; } return 1; }").unwrap();
+    let before = rt.memory_usage();
+
+    rt.call_global("build", &[SharedValue::new_number(1000.0)]).unwrap();
+    assert!(rt.memory_usage() < before + 4 * 1024);
+
+    rt.set_memory_limit(Some(before + 16 * 1024));
+    let result = rt.call_global("build", &[SharedValue::new_number(100000.0)]);
+    assert!(matches!(result, Err(JsError::OutOfMemory)));
+    assert!(rt.memory_usage() < before + 4 * 1024);
+}
@@ -0,0 +1,217 @@
+// Regression corpus for malformed scripts, each one must come back as an Err, never a panic.
+
+mod common;
+use common::NoHook;
+
+// limits are sized for the default 8MB main thread, the test harness threads are smaller
+fn with_main_stack<F: FnOnce() + Send + 'static>(f: F) {
+    let t = std::thread::Builder::new().stack_size(8 * 1024 * 1024).spawn(f).unwrap();
+    t.join().unwrap();
+}
+
+fn run(script: &str) -> Result<(), ezjs::error::JsError> {
+    let vmf = ezjs::build_function_from_code(script)?;
+    let mut rt = ezjs::new_runtime(NoHook);
+    ezjs::run_script(&mut rt, vmf)?;
+    return Ok(());
+}
+
+const BAD_SYNTAX: &[&str] = &[
+    "var",
+    "var = 1;",
+    "1 +",
+    "(1",
+    "[1, 2",
+    "{ a: 1",
+    "function",
+    "function (",
+    "function f( { }",
+    "if (",
+    "for (;;",
+    "for (var i in ) {}",
+    "switch (1) { foo }",
+    "switch (1) { default: 1; default: 2; }",
+    "try {}",
+    "\"abc",
+    "'abc",
+    "/* abc",
+    "\\",
+    "1 = 2;",
+    "a + b = 3;",
+    "++1;",
+    "f() = 1;",
+    "delete 1;",
+    "break;",
+    "continue;",
+    "return 1;",
+    "while (1) { break foo; }",
+    "x = { get a(b) { } };",
+    "x = { set a() { } };",
+    "new",
+    ")",
+    "}",
+    "]",
+    ";;)",
+];
+
+const BAD_RUNTIME: &[&str] = &[
+    "undefined_name;",
+    "#",
+    "@",
+    "var a; a.b;",
+    "var a = null; a.b = 1;",
+    "var a = 1; a[0] = 1;",
+    "var a; delete a.b;",
+    "var a; delete a[1];",
+    "var a = 1; a();",
+    "new 5;",
+    "new 5",
+    "var o = {}; new o();",
+    "throw 1;",
+    "throw 'str';",
+    "throw {};",
+    "Object.defineProperty();",
+    "var o = { push: [].push }; o.push(2);",
+    "[].push();",
+    "Error.prototype.message.call({});",
+    "assert(false, 'fail');",
+    "var f = function() { throw 1; }; f.apply(null, []);",
+    "function f() { return f(); } f();",
+    "function f() { f.x = 1; return f(); } f();",
+    "var o = {}; o.f = function() { return this.f(); }; o.f();",
+    "try { r } catch(e) { g } finally { }",
+    "var o = { get b() { return this.b; } }; o.b;",
+    "var o = {}; Object.defineProperty(o, 'b', { get: function() { return o.b; } }); o.b;",
+    "var a = {}; Object.setPrototypeOf(a, a); a.x;",
+    "var a = {}, b = {}; Object.setPrototypeOf(a, b); Object.setPrototypeOf(b, a);",
+];
+
+const GOOD: &[&str] = &[
+    "try { undefined_name; } catch(e) { }",
+    "try { throw 1; } catch(e) { }",
+    "try { new 5; } catch(e) { }",
+    "try { Object.setPrototypeOf(1); } catch(e) { }",
+    "function F() { this.a = 1; } var o = new F;",
+    "function f(n) { return n == 0 ? 0 : f(n - 1); } f(100);",
+    "var a = [1]; a.push(a); a.toString();",
+    "var s = '\u{4e2d}\u{6587}'; s[1];",
+    "var n = 0b101; var m = 0x10; var k = 8; k >>>= 1;",
+    "function f() { try { return f(); } catch(e) { return 1; } } f();",
+    "try { try { r } catch(e) { g } finally { } } catch(e) { }",
+    "var n = 0; try { r } catch(e) { n = 1; } finally { n = n + 1; } assert(n == 2, 'finally');",
+    "var n = 0; for (;;) { try { r } catch(e) { break; } finally { n = n + 1; } } assert(n == 1, 'break');",
+    "function f() { try { r } catch(e) { return 1; } finally { e = 2; } } var e = 0; f(); assert(e == 2, 'scope');",
+    "assert((function(){ try { return 1; } finally { return 2; } })() == 2, 'finally return');",
+    "assert((function(){ for (;;) { try { return 1; } finally { break; } } return 3; })() == 3, 'finally break');",
+    "var n = 0; for (var i = 0; i < 3; i++) { try { throw 1; } finally { n = n + 1; continue; } } assert(n == 3, 'finally continue');",
+    "try { Object.setPrototypeOf(Object.prototype, {}); } catch(e) { }",
+    "var o = { a: 1, set c(v) { this.a = v; } }; o.c = 5; assert(o.a == 5, 'setter');",
+    "var o = { get g() { return 7; } }; for (var i = 0; i < 20000; i++) { function F() {} F.prototype = o; o = new F(); } \
+     assert(o.zzz == undefined && o.g == 7 && o instanceof F, 'deep prototypes');",
+    "function f(a, a) { return a; } assert(f(1, 2) == 2, 'repeated param');",
+    "function g(a, b, a) { return function() { return a * 10 + b; }; } assert(g(1, 2, 3)() == 32, 'captured repeated param');",
+    "var o = {}, e; try { for (var i = 0; i < 100000; i++) { var n = {}; Object.setPrototypeOf(n, o); o = n; } } catch(x) { e = x; } \
+     assert(e instanceof TypeError && i < 1000, 'deep setPrototypeOf');",
+    "var o = { push: [].push }, e; try { o.push(2); } catch(x) { e = x; } \
+     assert(e instanceof TypeError && e.message() == 'TypeError: Array.prototype.push called on none array', 'push on none array');",
+];
+
+#[test]
+fn malformed_syntax_returns_error() {
+    with_main_stack(|| {
+        for script in BAD_SYNTAX {
+            assert!(ezjs::build_function_from_code(script).is_err(), "expected a syntax error: {}", script);
+        }
+    });
+}
+
+#[test]
+fn malformed_runtime_returns_error() {
+    with_main_stack(|| {
+        for script in BAD_RUNTIME {
+            assert!(run(script).is_err(), "expected a runtime error: {}", script);
+        }
+    });
+}
+
+#[test]
+fn recoverable_faults_are_catchable() {
+    with_main_stack(|| {
+        for script in GOOD {
+            assert!(run(script).is_ok(), "expected success: {}", script);
+        }
+    });
+}
+
+#[test]
+fn deep_nesting_returns_error() {
+    with_main_stack(|| {
+        let parens = format!("{}1{}", "(".repeat(10000), ")".repeat(10000));
+        assert!(ezjs::build_function_from_code(&parens).is_err());
+
+        let blocks = format!("{}{}", "{".repeat(10000), "}".repeat(10000));
+        assert!(ezjs::build_function_from_code(&blocks).is_err());
+
+        let unary = format!("{}1", "!".repeat(10000));
+        assert!(ezjs::build_function_from_code(&unary).is_err());
+
+        let chain = format!("1{}", " + 1".repeat(100000));
+        assert!(ezjs::build_function_from_code(&chain).is_err());
+
+        let functions = format!("{}{}", "function f() {".repeat(2000), "}".repeat(2000));
+        assert!(ezjs::build_function_from_code(&functions).is_err());
+
+        let finally = format!("function f() {{ {}1;{} }}", "try { return 1; } finally { ".repeat(18), " }".repeat(18));
+        assert!(ezjs::build_function_from_code(&finally).is_err());
+        let finally = format!("function f() {{ {}1;{} }}", "try { return 1; } finally { ".repeat(4), " }".repeat(4));
+        assert!(ezjs::build_function_from_code(&finally).is_ok());
+    });
+}
+
+#[test]
+fn long_else_if_chain_runs() {
+    with_main_stack(|| {
+        let mut script = "var x = 499, r;".to_string();
+        for i in 0..500 {
+            script += &format!("if (x == {}) r = {}; else ", i, i * 2);
+        }
+        script += "r = -1; r;";
+        let vmf = ezjs::build_function_from_code(&script).unwrap();
+        let mut rt = ezjs::new_runtime(NoHook);
+        assert_eq!(ezjs::run_script(&mut rt, vmf).unwrap().to_string(), "998");
+
+        let chain = format!("{}0;", "if (x) 0; else ".repeat(10000));
+        assert!(ezjs::build_function_from_code(&chain).is_err());
+    });
+}
+
+#[test]
+fn long_object_chain_drops() {
+    with_main_stack(|| {
+        assert!(run("var a = null; for (var i = 0; i < 100000; i++) { a = { next: a }; }").is_ok());
+        assert!(run("var a = null; for (var i = 0; i < 100000; i++) { a = [a]; } '' + a;").is_ok());
+    });
+}
+
+#[test]
+fn syntax_errors_report_char_columns() {
+    for (script, line, column) in [
+        ("var a = 1;\n  var = 1;", 2, 7),
+        ("var s = 'ééé'; var = 1;", 1, 20),
+        ("/* é\n é */ var s = 'é\\\né'; var = 1;", 3, 9),
+    ] {
+        match ezjs::build_function_from_code(script) {
+            Err(ezjs::error::JsError::SyntaxError { line: l, column: c, .. }) => {
+                assert_eq!((l, c), (line, column), "{}", script);
+            },
+            _ => panic!("expected a syntax error: {}", script),
+        }
+    }
+
+    /* columns are counted as the tokenizer advances, a long line isn't scanned again for every token */
+    let long = format!("var a = [{}1];\nvar = 1;", "1, ".repeat(200000));
+    match ezjs::build_function_from_code(&long) {
+        Err(ezjs::error::JsError::SyntaxError { line, column, .. }) => assert_eq!((line, column), (2, 5)),
+        _ => panic!("expected a syntax error in the long script"),
+    }
+}
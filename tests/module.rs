@@ -0,0 +1,229 @@
+// Modules loaded by require or import through a ModuleLoader of the host.
+
+mod common;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use ezjs::error::JsError;
+use ezjs::module::{FsModuleLoader, ModuleLoader};
+use ezjs::runtime::JsRuntime;
+use ezjs::value::SharedValue;
+
+use common::{eval, NoHook};
+
+struct MapLoader {
+    sources: HashMap<String, String>,
+    loads: Rc<RefCell<Vec<String>>>,
+}
+
+impl ModuleLoader for MapLoader {
+    fn resolve(&mut self, specifier: &str, referrer: &str) -> Result<String, String> {
+        if specifier.starts_with("./") && referrer != "" {
+            let dir = referrer.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+            return Ok(format!("{}/{}", dir, &specifier[2..]));
+        }
+        return Ok(specifier.trim_start_matches("./").to_string());
+    }
+    fn load(&mut self, id: &str) -> Result<String, String> {
+        self.loads.borrow_mut().push(id.to_string());
+        return self.sources.get(id).cloned().ok_or("not found".to_string());
+    }
+}
+
+fn new_runtime(sources: &[(&str, &str)]) -> (JsRuntime<NoHook>, Rc<RefCell<Vec<String>>>) {
+    let loads = Rc::new(RefCell::new(Vec::new()));
+    let loader = MapLoader {
+        sources: sources.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        loads: loads.clone(),
+    };
+    let mut rt = ezjs::new_runtime(NoHook);
+    rt.set_module_loader(Box::new(loader));
+    return (rt, loads);
+}
+
+#[test]
+fn exports_and_cache() {
+    let (mut rt, loads) = new_runtime(&[
+        ("math.js", "var hidden = 2; exports.double = function(x) { return x * hidden; }; exports.loaded = (exports.loaded || 0) + 1;"),
+        ("point.js", "module.exports = function(x, y) { return { x: x, y: y }; };"),
+    ]);
+    assert_eq!(eval(&mut rt, "var m = require('./math.js'); m.double(21);").unwrap().to_number(), 42.0);
+    assert_eq!(eval(&mut rt, "require('math.js').loaded;").unwrap().to_number(), 1.0);
+    assert!(eval(&mut rt, "require('./math.js') === m;").unwrap().to_boolean());
+    assert_eq!(eval(&mut rt, "var Point = require('point.js'); Point(1, 2).y;").unwrap().to_number(), 2.0);
+    assert_eq!(*loads.borrow(), vec!["math.js".to_string(), "point.js".to_string()]);
+
+    let msg = eval(&mut rt, "var r; try { hidden; } catch(e) { r = e.message(); } r;").unwrap();
+    assert!(msg.to_string().contains("hidden"));
+}
+
+#[test]
+fn relative_require_uses_referrer() {
+    let (mut rt, loads) = new_runtime(&[
+        ("lib/a.js", "exports.b = require('./b.js').name;"),
+        ("lib/b.js", "exports.name = 'b:' + module.id;"),
+    ]);
+    assert_eq!(eval(&mut rt, "require('lib/a.js').b;").unwrap().to_string(), "b:lib/b.js");
+    assert_eq!(*loads.borrow(), vec!["lib/a.js".to_string(), "lib/b.js".to_string()]);
+}
+
+#[test]
+fn circular_require_sees_partial_exports() {
+    let (mut rt, _) = new_runtime(&[
+        ("a.js", "exports.done = false; var b = require('b.js'); exports.fromB = b.sawA; exports.done = true;"),
+        ("b.js", "var a = require('a.js'); exports.sawA = a.done ? 'done' : 'partial';"),
+    ]);
+    let value = eval(&mut rt, "var a = require('a.js'); '' + a.done + ',' + a.fromB + ',' + require('b.js').sawA;").unwrap();
+    assert_eq!(value.to_string(), "true,partial,partial");
+}
+
+#[test]
+fn failed_modules_throw_and_are_not_cached() {
+    let (mut rt, loads) = new_runtime(&[
+        ("bad.js", "var = 1;"),
+        ("throws.js", "exports.a = 1; throw 'boom';"),
+    ]);
+    let msg = eval(&mut rt, "var r; try { require('missing.js'); } catch(e) { r = e.message(); } r;").unwrap();
+    assert_eq!(msg.to_string(), "Cannot find module 'missing.js': not found");
+
+    let msg = eval(&mut rt, "var r; try { require('bad.js'); } catch(e) { r = e.message(); } r;").unwrap();
+    assert!(msg.to_string().starts_with("bad.js: SyntaxError"));
+
+    let msg = eval(&mut rt, "var r; try { require('throws.js'); } catch(e) { r = e; } r;").unwrap();
+    assert_eq!(msg.to_string(), "boom");
+    assert!(eval(&mut rt, "require('throws.js');").is_err());
+    assert_eq!(loads.borrow().iter().filter(|id| *id == "throws.js").count(), 2);
+    assert!(rt.modules.is_empty());
+}
+
+#[test]
+fn require_without_loader_throws() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    assert!(eval(&mut rt, "require('x');").is_err());
+    assert!(eval(&mut rt, "try { require('x'); } catch(e) { }").is_ok());
+}
+
+#[test]
+fn fs_loader_resolves_relative_paths() {
+    let mut loader = FsModuleLoader::new("scripts");
+    assert_eq!(loader.resolve("util", "").unwrap(), "scripts/util.js");
+    assert_eq!(loader.resolve("./lib/x.js", "scripts/main.js").unwrap(), "scripts/lib/x.js");
+    assert_eq!(loader.resolve("../shared/y", "scripts/lib/x.js").unwrap(), "scripts/shared/y.js");
+}
+
+fn import(rt: &mut JsRuntime<NoHook>, id: &str) -> Result<SharedValue, JsError> {
+    let ns = rt.import_module(id, "")?;
+    rt.genv.borrow_mut().init_var("m", ns.clone());
+    return Ok(ns);
+}
+
+#[test]
+fn named_default_and_namespace_imports() {
+    let (mut rt, loads) = new_runtime(&[
+        ("lib/util.js", "export function helper(x) { return x * 2; } export var name = 'util'; var hidden = 1; export { hidden as secret }; export default function(x) { return x + 1; }"),
+        ("lib/main.js", "import inc, { helper, name as n } from './util.js'; import * as util from './util.js'; export var result = '' + helper(inc(1)) + ',' + n + ',' + util.secret + ',' + util['default'](0);"),
+    ]);
+    import(&mut rt, "lib/main.js").unwrap();
+    assert_eq!(eval(&mut rt, "m.result;").unwrap().to_string(), "4,util,1,1");
+    assert_eq!(*loads.borrow(), vec!["lib/main.js".to_string(), "lib/util.js".to_string()]);
+
+    // modules are run once, and their variables stay out of the global scope
+    import(&mut rt, "lib/util.js").unwrap();
+    assert_eq!(loads.borrow().len(), 2);
+    assert_eq!(eval(&mut rt, "var n = 0; for (var p in m) { n++; } n;").unwrap().to_number(), 4.0);
+    assert!(eval(&mut rt, "'secret' in m && 'default' in m && !('hidden' in m);").unwrap().to_boolean());
+    assert!(eval(&mut rt, "helper;").is_err());
+}
+
+#[test]
+fn imports_are_live_and_read_only() {
+    let (mut rt, _) = new_runtime(&[
+        ("counter.js", "export var count = 0; export function inc() { count = count + 1; }"),
+        ("main.js", "import { count, inc } from 'counter.js'; import * as c from 'counter.js'; inc(); export function read() { return '' + count + ',' + c.count; } export var error; try { count = 5; } catch(e) { error = e.message(); }"),
+    ]);
+    import(&mut rt, "main.js").unwrap();
+    assert_eq!(eval(&mut rt, "m.read();").unwrap().to_string(), "1,1");
+    assert_eq!(eval(&mut rt, "m.error;").unwrap().to_string(), "TypeError: Assignment to read only variable 'count'");
+
+    let counter = rt.import_module("counter.js", "").unwrap();
+    rt.genv.borrow_mut().init_var("counter", counter);
+    eval(&mut rt, "counter.inc(); counter.inc();").unwrap();
+    assert_eq!(eval(&mut rt, "m.read();").unwrap().to_string(), "3,3");
+}
+
+#[test]
+fn read_only_globals_are_assigned_like_before() {
+    let (mut rt, _) = new_runtime(&[
+        ("shadow.js", "var saved = Object; Object = 5; export var kind = typeof Object; Object = saved;"),
+    ]);
+    import(&mut rt, "shadow.js").unwrap();
+    assert_eq!(eval(&mut rt, "m.kind;").unwrap().to_string(), "number");
+    assert_eq!(eval(&mut rt, "var saved = Object; Object = 5; var kind = typeof Object; Object = saved; kind;").unwrap().to_string(), "number");
+}
+
+#[test]
+fn circular_imports_see_hoisted_functions() {
+    let (mut rt, _) = new_runtime(&[
+        ("a.js", "import { b } from 'b.js'; export function a() { return 'a'; } export var fromB = b();"),
+        ("b.js", "import { a, fromB } from 'a.js'; export function b() { return 'b' + a(); } export var early = typeof fromB;"),
+    ]);
+    import(&mut rt, "a.js").unwrap();
+    assert_eq!(eval(&mut rt, "m.fromB;").unwrap().to_string(), "ba");
+    import(&mut rt, "b.js").unwrap();
+    assert_eq!(eval(&mut rt, "m.early;").unwrap().to_string(), "undefined");
+}
+
+#[test]
+fn failed_imports_are_not_cached() {
+    let (mut rt, _) = new_runtime(&[
+        ("lib.js", "export var x = 1;"),
+        ("missing.js", "import { y } from 'lib.js';"),
+        ("throws.js", "import { x } from 'lib.js'; throw 'boom';"),
+        ("bad.js", "export var = 1;"),
+    ]);
+    let e = rt.import_module("missing.js", "").err().unwrap();
+    assert_eq!(e.to_string(), "Uncaught: missing.js: module 'lib.js' has no export named 'y'");
+    assert!(rt.es_modules.is_empty());
+
+    assert!(rt.import_module("throws.js", "").is_err());
+    assert!(rt.import_module("bad.js", "").is_err());
+    assert_eq!(rt.es_modules.keys().collect::<Vec<_>>(), vec!["lib.js"]);
+    assert!(rt.import_module("lib.js", "").is_ok());
+    assert_eq!(rt.es_modules.len(), 1);
+}
+
+#[test]
+fn imports_which_ran_are_kept_when_the_importer_throws() {
+    let (mut rt, loads) = new_runtime(&[
+        ("count.js", "counter = counter + 1; export var n = counter;"),
+        ("throws.js", "import { n } from 'count.js'; throw 'boom';"),
+        ("main.js", "import { n } from 'count.js'; export var seen = n;"),
+    ]);
+    eval(&mut rt, "var counter = 0;").unwrap();
+    assert!(rt.import_module("throws.js", "").is_err());
+    assert!(rt.import_module("throws.js", "").is_err());
+    assert!(rt.import_module("main.js", "").is_ok());
+    assert_eq!(eval(&mut rt, "counter;").unwrap().to_number(), 1.0);
+    assert_eq!(loads.borrow().iter().filter(|id| *id == "count.js").count(), 1);
+    assert_eq!(loads.borrow().iter().filter(|id| *id == "throws.js").count(), 2);
+}
+
+#[test]
+fn module_items_are_rejected_in_scripts() {
+    assert!(ezjs::build_function_from_code("import { a } from 'a.js';").is_err());
+    assert!(ezjs::build_function_from_code("var x = 1;\nexport { x };").is_err());
+    assert!(ezjs::build_function_from_code("function f() { export var x; }").is_err());
+    assert!(ezjs::build_module_from_code("import d, * as ns from 'a.js'; export default d; export { ns as other };").is_ok());
+    assert!(ezjs::build_module_from_code("export var a; export { a };").is_err());
+}
+
+#[test]
+fn import_and_export_are_names_outside_module_items() {
+    let script = "var o = { import: 1, export: 2 }; var import = o.import + o.export; function f(export) { return export * 2; } f(import);";
+    let vmf = ezjs::build_function_from_code(script).unwrap();
+    let mut rt = ezjs::new_runtime(NoHook);
+    assert_eq!(ezjs::run_script(&mut rt, vmf).unwrap().to_string(), "6");
+    assert!(ezjs::build_module_from_code("export var loader = { import: function(id) { return id; } }; loader.import('a');").is_ok());
+}
@@ -0,0 +1,171 @@
+// Scripts compiled with the AST optimizer give the same results, with less code.
+
+mod common;
+
+use ezjs::bytecode::{OpcodeType, VMFunction};
+use ezjs::optimizer::OptLevel;
+use std::convert::TryFrom;
+use ezjs::runtime::JsRuntime;
+
+use common::NoHook;
+
+fn eval(script: &str, level: OptLevel) -> String {
+    let mut rt: JsRuntime<NoHook> = ezjs::new_runtime(NoHook);
+    let vmf = ezjs::build_function_with_opt_level(script, level).unwrap();
+    return ezjs::run_script(&mut rt, vmf).unwrap().to_string();
+}
+
+fn same_at_all_levels(script: &str) -> String {
+    let r = eval(script, OptLevel::O0);
+    assert_eq!(eval(script, OptLevel::O1), r, "{}", script);
+    return r;
+}
+
+#[test]
+fn constants_are_folded() {
+    let vmf = ezjs::build_function_from_code("0.5 + 2 * 3 - -4;").unwrap();
+    assert_eq!(vmf.num_tab, vec![10.5]);
+    let vmf = ezjs::build_function_from_code("'a' + 'b' + 'c';").unwrap();
+    assert!(vmf.str_tab.contains(&"abc".to_string()));
+    assert!(!vmf.str_tab.contains(&"a".to_string()));
+    let vmf = ezjs::build_function_with_opt_level("1 + 2 * 3;", OptLevel::O0).unwrap();
+    assert_eq!(vmf.num_tab.len(), 3);
+
+    assert_eq!(same_at_all_levels("1 + 2 * 3 - -4;"), "11");
+    assert_eq!(same_at_all_levels("7 % 3 / 2;"), "0.5");
+    assert_eq!(same_at_all_levels("'a' + 'b';"), "ab");
+    assert_eq!(same_at_all_levels("'' + (1 < 2) + (2 <= 1) + ('b' > 'a') + ('a' >= 'b') + (1 == 1) + (1 !== 1) + ('x' === 'x');"),
+               "truefalsetruefalsetruefalsetrue");
+    // NaN and -0 are left to the VM
+    same_at_all_levels("'' + (0 / 0 < 1) + (0 / 0 > 1) + ',' + 1 / 0 + ',' + 1 / -0 + ',' + 1 / (0 * -1);");
+}
+
+#[test]
+fn constant_if_keeps_the_taken_branch() {
+    let vmf = ezjs::build_function_from_code("if (1 > 2) { foo(); } else { bar(); }").unwrap();
+    assert!(!vmf.str_tab.contains(&"foo".to_string()));
+    assert!(vmf.str_tab.contains(&"bar".to_string()));
+
+    assert_eq!(same_at_all_levels("var r = 0; if (true) r = 1; else r = 2; r;"), "1");
+    assert_eq!(same_at_all_levels("var r = 0; if (1 - 1) r = 1; r;"), "0");
+    // the VM takes an empty string as true, it's left as it is
+    same_at_all_levels("var r = 0; if ('') r = 1; r;");
+    // an if has no completion value, the taken branch doesn't get one
+    assert_eq!(same_at_all_levels("if (0) 1; else 2;"), "undefined");
+    assert_eq!(same_at_all_levels("if (1) 'a'; else if (0) 'b';"), "undefined");
+
+    // a variable declared in the dead branch is still a variable
+    assert_eq!(same_at_all_levels("function f() { x = 1; if (false) { var x; } return typeof x; } f() + typeof x;"),
+               "numberundefined");
+}
+
+#[test]
+fn dead_statements_are_removed() {
+    let vmf = ezjs::build_function_from_code("function f() { return 1; foo(); }").unwrap();
+    assert!(!vmf.func_tab[0].str_tab.contains(&"foo".to_string()));
+
+    assert_eq!(same_at_all_levels("var s = ''; for (var i = 0; i < 3; i++) { s = s + i; continue; s = 'x'; } s;"), "012");
+    assert_eq!(same_at_all_levels("var s = ''; try { throw 'e'; s = 'x'; } catch (e) { s = e; } s;"), "e");
+
+    // hoisted declarations after return are kept
+    let script = "function f() { x = 2; return g() + x; var x; function g() { return 1; } } typeof x + f();";
+    assert_eq!(same_at_all_levels(script), "undefined3");
+}
+
+/* instructions of the code as (address, opcode, operand) */
+fn instructions(f: &VMFunction) -> Vec<(usize, OpcodeType, usize)> {
+    use OpcodeType::*;
+    let mut lst = Vec::new();
+    let mut pc = 0;
+    while pc < f.code.len() {
+        let op = OpcodeType::try_from(f.code[pc]).unwrap();
+        let width = match op {
+            OP_TRY | OP_JCASE | OP_JUMP | OP_JTRUE | OP_JFALSE => 2,
+            OP_INTEGER | OP_NUMBER | OP_STRING | OP_CLOSURE | OP_HASVAR | OP_GETVAR | OP_SETVAR | OP_DELVAR |
+            OP_GETPROP_S | OP_SETPROP_S | OP_DELPROP_S | OP_GETLOCAL | OP_SETLOCAL | OP_CALL | OP_NEW | OP_CATCH => 1,
+            _ => 0,
+        };
+        let operand = match width {
+            2 => f.code[pc + 1] as usize + (f.code[pc + 2] as usize) * 65536,
+            1 => f.code[pc + 1] as usize,
+            _ => 0,
+        };
+        lst.push((pc, op, operand));
+        pc = pc + 1 + width;
+    }
+    return lst;
+}
+
+const LOOPS: &str = "
+var s = '';
+var n = 0;
+for (var i = 0; i < 4; i++) {
+    for (var j = 0; j < 4; j++) {
+        if (j > i) continue;
+        if (j == 2) break;
+        n++;
+    }
+    do { s = s + i; } while (false);
+}
+while (true) { n = n + 100; break; }
+s + ',' + n;
+";
+
+#[test]
+fn bytecode_is_rewritten() {
+    use OpcodeType::*;
+    assert_eq!(same_at_all_levels(LOOPS), "0123,107");
+
+    let plain = ezjs::build_function_with_opt_level(LOOPS, OptLevel::O0).unwrap();
+    let vmf = ezjs::build_function_from_code(LOOPS).unwrap();
+    assert!(vmf.verify().is_ok());
+    assert!(vmf.code.len() < plain.code.len());
+
+    // small numbers are OP_INTEGER, the table only keeps other numbers
+    assert!(vmf.num_tab.is_empty());
+
+    let ops = instructions(&vmf);
+    for (i, (_, op, operand)) in ops.iter().enumerate() {
+        if *op == OP_DUP {
+            assert!(ops[i + 1].1 != OP_POP);
+        }
+        if *op == OP_TRUE || *op == OP_FALSE {
+            assert!(ops[i + 1].1 != OP_JTRUE && ops[i + 1].1 != OP_JFALSE);
+        }
+        if *op == OP_JUMP || *op == OP_JTRUE || *op == OP_JFALSE {
+            // jumps are threaded and land on an instruction
+            let target = ops.iter().find(|(pc, _, _)| pc == operand).unwrap();
+            assert!(target.1 != OP_JUMP);
+            assert!(*operand != ops[i + 1].0 || *op != OP_JUMP);
+        }
+    }
+}
+
+#[test]
+fn try_targets_are_moved() {
+    let script = "
+        function f(x) {
+            var r = '';
+            for (var k in { a: 1 }) {
+                try {
+                    if (true) { r = r + k; }
+                    if (x) throw 'e';
+                } catch (e) {
+                    r = r + e;
+                    continue;
+                } finally {
+                    r = r + ';';
+                }
+            }
+            return r;
+        }
+        f(false) + f(true);
+    ";
+    assert_eq!(same_at_all_levels(script), "a;ae;");
+
+    let vmf = ezjs::build_function_from_code(script).unwrap();
+    assert!(vmf.verify().is_ok());
+    let f = &vmf.func_tab[0];
+    let plain = ezjs::build_function_with_opt_level(script, OptLevel::O0).unwrap();
+    assert!(f.code.len() < plain.func_tab[0].code.len());
+}
@@ -0,0 +1,141 @@
+// Conversion between SharedValue and Rust types through serde, needs the "serde" feature.
+#![cfg(feature = "serde")]
+
+mod common;
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use common::{eval, NoHook};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Mode {
+    Fast,
+    Limit(u32),
+    Range { low: i32, high: i32 },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Server {
+    host: String,
+    port: u16,
+    ratio: f64,
+    enabled: bool,
+    tags: Vec<String>,
+    backup: Option<String>,
+    labels: HashMap<String, i64>,
+    modes: Vec<Mode>,
+    pair: (u8, String),
+}
+
+#[test]
+fn config_object_to_struct() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    let v = eval(&mut rt, "var cfg = { host: 'localhost', port: 8080, ratio: 0.5, enabled: true, \
+                                       tags: ['a', 'b'], labels: { x: 1, y: -2 }, \
+                                       modes: ['Fast', { Limit: 3 }, { Range: { low: -1, high: 1 } }], \
+                                       pair: [7, 'seven'] }; cfg;").unwrap();
+    let cfg: Server = ezjs::from_value(&rt, &v).unwrap();
+
+    let mut labels = HashMap::new();
+    labels.insert("x".to_string(), 1);
+    labels.insert("y".to_string(), -2);
+    assert_eq!(cfg, Server {
+        host: "localhost".to_string(),
+        port: 8080,
+        ratio: 0.5,
+        enabled: true,
+        tags: vec!["a".to_string(), "b".to_string()],
+        backup: None,
+        labels: labels,
+        modes: vec![Mode::Fast, Mode::Limit(3), Mode::Range { low: -1, high: 1 }],
+        pair: (7, "seven".to_string()),
+    });
+}
+
+#[test]
+fn struct_round_trip() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    let cfg = Server {
+        host: "example".to_string(),
+        port: 80,
+        ratio: 1.25,
+        enabled: false,
+        tags: vec![],
+        backup: Some("spare".to_string()),
+        labels: HashMap::new(),
+        modes: vec![Mode::Limit(9), Mode::Fast],
+        pair: (1, "one".to_string()),
+    };
+
+    let before = rt.heap_stats();
+    let v = ezjs::to_value(&rt, &cfg).unwrap();
+    let back: Server = ezjs::from_value(&rt, &v).unwrap();
+    assert_eq!(back, cfg);
+    /* the server, tags, labels, modes with the Limit variant, pair and the strings */
+    assert!(rt.heap_stats().objects >= before.objects + 7);
+    assert!(rt.heap_stats().used > before.used);
+
+    let global = rt.genv.borrow().target();
+    let mut prop = ezjs::value::JsProperty::new();
+    prop.fill(v, ezjs::value::JS_DEFAULT_ATTR, None, None);
+    global.borrow_mut().set_property("server", prop);
+    let s = eval(&mut rt, "server.host + ':' + server.port + ' ' + server.modes[0].Limit + ' ' + server.backup;").unwrap();
+    assert_eq!(s.to_string(), "example:80 9 spare");
+}
+
+#[test]
+fn wrong_shape_is_type_error() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    let v = eval(&mut rt, "({ host: 'h', port: 'not a number' });").unwrap();
+    let result: Result<Server, _> = ezjs::from_value(&rt, &v);
+    let message = result.err().unwrap().message();
+    assert!(message.starts_with("TypeError: "), "{}", message);
+
+    let v = eval(&mut rt, "300;").unwrap();
+    let result: Result<u8, _> = ezjs::from_value(&rt, &v);
+    assert!(result.is_err());
+}
+
+#[derive(Debug, Deserialize)]
+struct Cfg {
+    name: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Node {
+    name: String,
+    next: Option<Box<Node>>,
+}
+
+#[test]
+fn cyclic_or_deep_value_is_type_error() {
+    let mut rt = ezjs::new_runtime(NoHook);
+    let v = eval(&mut rt, "var c = { name: 'x' }; c;").unwrap();
+    let cfg: Cfg = ezjs::from_value(&rt, &v).unwrap();
+    assert_eq!(cfg.name, "x");
+
+    let v = eval(&mut rt, "c.me = c; c;").unwrap();
+    let result: Result<Cfg, _> = ezjs::from_value(&rt, &v);
+    let message = result.err().unwrap().message();
+    assert!(message.starts_with("TypeError: ") && message.contains("cyclic"), "{}", message);
+
+    let v = eval(&mut rt, "var a = []; a.push(a); a;").unwrap();
+    let result: Result<Vec<Vec<u8>>, _> = ezjs::from_value(&rt, &v);
+    assert!(result.is_err());
+
+    fn chain(depth: usize) -> Node {
+        let mut node = Node { name: "end".to_string(), next: None };
+        for _ in 0..depth {
+            node = Node { name: "node".to_string(), next: Some(Box::new(node)) };
+        }
+        return node;
+    }
+    let v = ezjs::to_value(&rt, &chain(100)).unwrap();
+    let back: Node = ezjs::from_value(&rt, &v).unwrap();
+    assert_eq!(back, chain(100));
+    let v = ezjs::to_value(&rt, &chain(200)).unwrap();
+    let result: Result<Node, _> = ezjs::from_value(&rt, &v);
+    assert!(result.is_err());
+}
@@ -0,0 +1,84 @@
+// Objects keep their properties in slots described by shared shapes, keys enumerate in insertion order.
+
+mod common;
+
+use common::eval_string;
+
+const KEYS: &str = "
+function keys(o) {
+    var s = '';
+    for (var k in o) {
+        s = s + k;
+    }
+    return s;
+}
+";
+
+#[test]
+fn keys_follow_insertion_order() {
+    let script = "
+        var o = { b: 1, a: 2, c: 3 };
+        o.d = 4;
+        var s = keys(o) + ',';
+        delete o.a;
+        o.a = 5;
+        s + keys(o) + ',' + o.a + o.b;
+    ";
+    assert_eq!(eval_string(&(KEYS.to_string() + script)), "bacd,bcda,51");
+}
+
+#[test]
+fn records_of_one_shape_keep_own_values() {
+    let script = "
+        var l = [];
+        for (var i = 0; i < 3; i++) {
+            l.push({ x: i * 1, y: 'v' + i });
+        }
+        l[1].x = 10;
+        l[2].z = true;
+        var s = '';
+        for (var i = 0; i < 3; i++) {
+            s = s + l[i].x + l[i].y + keys(l[i]) + ';';
+        }
+        s;
+    ";
+    assert_eq!(eval_string(&(KEYS.to_string() + script)), "0v0xy;10v1xy;2v2xyz;");
+}
+
+#[test]
+fn large_objects_with_deletes_stay_in_order() {
+    // more keys than a shared shape has, then most of them deleted
+    let script = "
+        var o = {};
+        for (var i = 0; i < 200; i++) {
+            o['k' + i] = i * 1;
+        }
+        for (var i = 0; i < 200; i++) {
+            if (i % 50 != 7) delete o['k' + i];
+        }
+        o.last = 'z';
+        '' + keys(o) + ',' + o.k57 + o.k157 + o.k8 + o.last;
+    ";
+    assert_eq!(eval_string(&(KEYS.to_string() + script)), "k7k57k107k157last,57157undefinedz");
+}
+
+#[test]
+fn objects_with_distinct_keys_stay_correct() {
+    // more distinct first keys than the empty shape keeps transitions for, the rest are dictionaries
+    let script = "
+        var l = [];
+        for (var i = 0; i < 3000; i++) {
+            var o = {};
+            o['k' + i] = 'v' + i;
+            l.push(o);
+        }
+        for (var i = 0; i < 3000; i++) {
+            var o = {};
+            o['t' + i] = i;
+        }
+        var r = { x: 1, y: 2 };
+        r.z = 3;
+        l[5].k5 + l[2999].k2999 + keys(l[2000]) + keys(r) + r.z;
+    ";
+    assert_eq!(eval_string(&(KEYS.to_string() + script)), "v5v2999k2000xyz3");
+}
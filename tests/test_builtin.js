@@ -105,6 +105,43 @@ function test_call() {
     
 }
 
+function test_builtin_throw() {
+    var caught = false;
+    try {
+        Object.defineProperty({}, "x");
+    } catch (e) {
+        caught = true;
+        assert(e.message() == "TypeError: object_defineproperty argument count error!", "throw 1111");
+    }
+    assert(caught, "throw 2222");
+
+    caught = false;
+    try {
+        var o = { push: Array.prototype.push };
+        o.push(1);
+    } catch (e) {
+        caught = true;
+        assert(e instanceof TypeError, "throw 3333 type");
+        assert(e.message() == "TypeError: Array.prototype.push called on none array", "throw 3333 message");
+    }
+    assert(caught, "throw 3333");
+
+    var thrower = function() {
+        throw new Error("inner");
+    };
+    caught = false;
+    try {
+        thrower.apply(null, []);
+    } catch (e) {
+        caught = true;
+        assert(e.message() == "inner", "throw 4444");
+    }
+    assert(caught, "throw 5555");
+
+    console.log("-------- END TESTING -----------");
+}
+
 test_proto();
 test_prototype();
 test_apply();
+test_builtin_throw();
@@ -117,6 +117,69 @@ function test_closure3()
     console.log("-------- END TESTING -----------");
 }
 
+function test_closure4()
+{
+    /* locals in slots and captured locals in the scope */
+    function counter(start)
+    {
+        var n = start;
+        var step = 1;
+        function next() { n = n + step; return n; }
+        var i;
+        for (i = 0; i < 3; i++) {
+            next();
+        }
+        return next;
+    }
+    var next = counter(10);
+    assert(next() == 14, "captured local");
+
+    function shadow(a)
+    {
+        var e = 1;
+        a = 2;
+        try {
+            throw 3;
+        } catch (e) {
+            e = 4;
+        }
+        return e + arguments[0] + a;
+    }
+    assert(shadow(1) == 5, "catch variable and arguments");
+
+    console.log("-------- END TESTING -----------");
+}
+
 test_closure1();
 test_closure2();
 test_closure3();
+function test_closure5()
+{
+    /* only names an inner function takes from outside are captured */
+    function outer(x)
+    {
+        var i = 0;
+        var sum = 0;
+        function add(i) { var x = i * 2; return x; }
+        for (i = 0; i < 3; i++) {
+            sum = sum + add(i);
+        }
+        function deep() { return function() { return x + i; }; }
+        return sum + deep()();
+    }
+    assert(outer(10) == 19, "shadowed and deep captures");
+
+    var fact = function f(n) { return n <= 1 ? 1 : n * f(n - 1); };
+    assert(fact(5) == 120, "name of function expression");
+
+    function args() { return function() { return arguments.length; }; }
+    assert(args(1, 2)(3) == 1, "arguments of the inner function");
+
+    function undeclared() { made = 1; return made; }
+    assert(undeclared() == 1 && typeof made == "undefined", "undeclared name stays in the call");
+
+    console.log("-------- END TESTING -----------");
+}
+
+test_closure4();
+test_closure5();
@@ -335,6 +335,27 @@ function test_try_catch8()
     console.log("-------- END TESTING -----------");
 }
 
+function test_try_catch9()
+{
+    var s;
+    s = "";
+    try {
+        for(;;) {
+            try {
+                throw Error("a");
+            } catch (e) {
+                s += e.message();
+                break;
+            }
+        }
+        throw Error("b");
+    } catch (e) {
+        s += e.message();
+    }
+    assert(s == "ab", "catch 11");
+    console.log("-------- END TESTING -----------");
+}
+
 test_while();
 test_while_break();
 test_do_while();
@@ -353,3 +374,4 @@ test_try_catch5();
 test_try_catch6();
 test_try_catch7();
 test_try_catch8();
+test_try_catch9();